@@ -0,0 +1,51 @@
+// Lexer throughput on a multi-megabyte, machine-generated style SIL source
+// Run with `cargo bench --bench lex`
+
+use std::time::Instant;
+
+use rachit_cc::lexer::lex;
+
+const PROGRAM: &str = r#"
+# Compute Fibonacci iteratively.
+def fib_iterative(count) {
+  let previous = 0
+  let current = 1
+  while (count > 0) {
+    let temp = previous
+    previous = current
+    current = temp + current
+    count = count - 1
+  }
+  return previous
+}
+
+def describe(n) {
+  if (n >= 10 && n != 42) {
+    return "large number"
+  }
+  return "small number"
+}
+
+fib_iterative(0x1F) * 2.5e3 + describe(1_000)
+"#;
+
+const COPIES: usize = 20_000;
+const RUNS: u32 = 10;
+
+fn main() {
+  let source: String = PROGRAM.repeat(COPIES);
+  let megabytes = source.len() as f64 / (1024.0 * 1024.0);
+
+  // Warm up once so the first timed run isn't paying for page faults on the source
+  let token_count = lex(&source).unwrap().len();
+
+  let start = Instant::now();
+  for _ in 0..RUNS {
+    let tokens = lex(&source).unwrap();
+    assert_eq!(tokens.len(), token_count);
+  }
+  let elapsed = start.elapsed() / RUNS;
+
+  println!("lexed {:.1} MiB into {} tokens in {:.1?} ({:.0} MiB/s)",
+    megabytes, token_count, elapsed, megabytes / elapsed.as_secs_f64());
+}
@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rachit_cc::{lexer::{lex, lex_reader, lex_with_recovery}, source::decode};
+
+// Run with `cargo +nightly fuzz run lex` from the repository root.
+//
+// Run arbitrary bytes through every lexer entry point, checking that none of them panic and that the
+// in-memory and streaming lexers agree
+fuzz_target!(|data: &[u8]| {
+  if let Ok(source) = decode(data) {
+    let whole = lex(&source);
+    let (_, errors) = lex_with_recovery(&source);
+    assert_eq!(whole.is_ok(), errors.is_empty());
+
+    if let (Ok(whole), Ok(streamed)) = (whole, lex_reader(source.as_bytes())) {
+      assert_eq!(whole.len(), streamed.len());
+    }
+  }
+
+  let _ = lex_reader(data);
+});
@@ -0,0 +1,18 @@
+// A function the runtime provides, which every program can call without defining it
+pub struct Builtin {
+  pub name: &'static str,
+  pub arity: Option<usize>,   // How many arguments it takes, or None if it takes any number
+}
+
+pub const BUILTINS: [Builtin; 5] = [
+  Builtin { name: "print", arity: None },
+  Builtin { name: "len", arity: Some(1) },
+  Builtin { name: "push", arity: Some(2) },
+  Builtin { name: "int", arity: Some(1) },     // Converts a float to an int, dropping anything after the point
+  Builtin { name: "float", arity: Some(1) },   // Converts an int to a float
+];
+
+// The builtin called `name`, if there is one
+pub fn builtin(name: &str) -> Option<&'static Builtin> {
+  return BUILTINS.iter().find(|builtin| builtin.name == name);
+}
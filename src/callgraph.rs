@@ -0,0 +1,329 @@
+use std::collections::HashSet;
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{Expr, ExprAST, NodeId, NodeMap, Program, Stmt, StmtAST};
+use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+// Which functions use which. The functions are every `def` and every let or const whose value is a lambda,
+// by the id of the node declaring them, as in the resolver's declarations table. A function uses another
+// if it calls it, or reads it as a value to call later, as in `map(xs, double)`. Lambdas that aren't bound
+// to a name are part of the function they're written in
+#[derive(Debug, Default)]
+pub struct CallGraph {
+  pub names: NodeMap<String>,
+  pub uses: NodeMap<Vec<NodeId>>,   // The functions each function uses, in the order it first uses them, with what the top-level code uses under TOP_LEVEL
+}
+
+impl CallGraph {
+  // Where the uses by the program's top-level code are kept
+  pub const TOP_LEVEL: NodeId = NodeId::DUMMY;
+
+  pub fn build(program: &Program, declarations: &NodeMap<NodeId>) -> CallGraph {
+    let mut builder = Builder { declarations, names: NodeMap::new(), uses: NodeMap::new(), current: vec![CallGraph::TOP_LEVEL] };
+    for function in &program.functions {
+      builder.visit_expr(function);
+    }
+    for stmt in &program.top_level {
+      builder.visit_stmt(stmt);
+    }
+
+    // A variable might be read before it's known whether it names a function, so the uses of anything else
+    // are dropped once they all are known
+    let names = builder.names;
+    let uses = builder.uses.into_iter()
+      .map(|(user, used)| (user, used.into_iter().filter(|used| names.contains_key(used)).collect()))
+      .collect();
+    return CallGraph { names, uses };
+  }
+
+  // The functions `function` uses
+  pub fn uses(&self, function: NodeId) -> &[NodeId] {
+    return self.uses.get(&function).map(Vec::as_slice).unwrap_or(&[]);
+  }
+
+  // Every function the top-level code can end up calling
+  pub fn reachable(&self) -> HashSet<NodeId> {
+    let mut reachable: HashSet<NodeId> = HashSet::new();
+    let mut pending: Vec<NodeId> = self.uses(CallGraph::TOP_LEVEL).to_vec();
+    while let Some(function) = pending.pop() {
+      if reachable.insert(function) {
+        pending.extend(self.uses(function));
+      }
+    }
+    return reachable;
+  }
+}
+
+struct Builder<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  names: NodeMap<String>,
+  uses: NodeMap<Vec<NodeId>>,
+  current: Vec<NodeId>,   // The functions being walked, innermost last
+}
+
+impl Builder<'_> {
+  fn use_declaration(&mut self, expr: &Expr) {
+    let Some(declaration) = self.declarations.get(&expr.id) else { return };
+    let uses = self.uses.entry(*self.current.last().unwrap()).or_default();
+    if !uses.contains(declaration) {
+      uses.push(*declaration);
+    }
+  }
+
+  fn walk_function(&mut self, id: NodeId, name: &str, walk: impl FnOnce(&mut Self)) {
+    self.names.insert(id, name.to_string());
+    self.current.push(id);
+    walk(self);
+    self.current.pop();
+  }
+}
+
+impl Visitor for Builder<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    match &expr.node {
+      ExprAST::Variable(_) | ExprAST::Call(..) => {
+        self.use_declaration(expr);
+        walk_expr(self, expr);
+      },
+      ExprAST::Function(name, ..) => self.walk_function(expr.id, name, |builder| walk_expr(builder, expr)),
+      _ => walk_expr(self, expr),
+    }
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Let(name, _, value) | StmtAST::Const(name, _, value) if matches!(value.node, ExprAST::Lambda(..)) => {
+        self.walk_function(stmt.id, name, |builder| walk_stmt(builder, stmt));
+      },
+      _ => walk_stmt(self, stmt),
+    }
+  }
+}
+
+// Warnings for functions that call themselves on every path through them before they can return, so they
+// can only end by running out of stack. A function whose every call to itself is a tail call, `return f(x)`,
+// is left alone, as that's a loop written as recursion. A lambda can't call itself by name, as the name it's
+// bound to is only declared after it
+pub fn unbounded_recursion(program: &Program, declarations: &NodeMap<NodeId>, graph: &CallGraph) -> Vec<Diagnostic> {
+  let mut recursion = Recursion { declarations, graph, warnings: Vec::new() };
+  for function in &program.functions {
+    recursion.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    recursion.visit_stmt(stmt);
+  }
+  recursion.warnings.sort_by_key(|warning| warning.span.start);
+  return recursion.warnings;
+}
+
+struct Recursion<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  graph: &'a CallGraph,
+  warnings: Vec<Diagnostic>,
+}
+
+// Whether each path into a statement can leave the function, and carry on past the statement, without the
+// function calling itself
+struct Flow {
+  returns: bool,
+  continues: bool,
+}
+
+impl Recursion<'_> {
+  fn check(&mut self, function: &Expr, name: &str, body: &[Stmt]) {
+    let id = function.id;
+    if !self.graph.uses(id).contains(&id) {
+      return;
+    }
+    let mut calls = SelfCalls { declarations: self.declarations, function: id, found: 0, tail: 0 };
+    for stmt in body {
+      calls.visit_stmt(stmt);
+    }
+    let flow = self.flow_block(id, body);
+    if !flow.returns && !flow.continues && calls.tail < calls.found {
+      let message = format!("Function '{}' calls itself on every path before it can return, so it never stops recursing", name);
+      self.warnings.push(Diagnostic::warning("recursion", message, function.span));
+    }
+  }
+
+  // Whether evaluating `expr` always calls the function `id`
+  fn recurses(&self, id: NodeId, expr: &Expr) -> bool {
+    match &expr.node {
+      ExprAST::Call(_, args) => return self.declarations.get(&expr.id) == Some(&id) || args.iter().any(|arg| self.recurses(id, arg)),
+      ExprAST::Unary(_, operand) | ExprAST::Field(operand, _) => return self.recurses(id, operand),
+      ExprAST::MethodCall(receiver, _, args) => return self.recurses(id, receiver) || args.iter().any(|arg| self.recurses(id, arg)),
+      ExprAST::Binary(_, lhs, rhs) | ExprAST::Index(lhs, rhs) => return self.recurses(id, lhs) || self.recurses(id, rhs),
+      // The right side of && and || doesn't always run, and only one side of ?: does
+      ExprAST::Logical(_, lhs, _) => return self.recurses(id, lhs),
+      ExprAST::Conditional(condition, then_value, else_value) => {
+        return self.recurses(id, condition) || (self.recurses(id, then_value) && self.recurses(id, else_value));
+      },
+      ExprAST::ArrayLiteral(elements) | ExprAST::Tuple(elements) | ExprAST::Interpolation(elements) => return elements.iter().any(|element| self.recurses(id, element)),
+      ExprAST::StructLiteral(_, fields) => return fields.iter().any(|(_, value)| self.recurses(id, value)),
+      _ => return false,
+    }
+  }
+
+  fn flow_block(&self, id: NodeId, statements: &[Stmt]) -> Flow {
+    let mut returns = false;
+    for stmt in statements {
+      let flow = self.flow(id, stmt);
+      returns |= flow.returns;
+      if !flow.continues {
+        return Flow { returns, continues: false };
+      }
+    }
+    return Flow { returns, continues: true };
+  }
+
+  fn flow(&self, id: NodeId, stmt: &Stmt) -> Flow {
+    let expr = |expr: &Expr| Flow { returns: false, continues: !self.recurses(id, expr) };
+    match &stmt.node {
+      StmtAST::Let(_, _, value) | StmtAST::Const(_, _, value) | StmtAST::LetTuple(_, value) | StmtAST::ExprStmt(value) => return expr(value),
+      StmtAST::Assign(target, value) => return Flow { returns: false, continues: !self.recurses(id, target) && !self.recurses(id, value) },
+      StmtAST::Return(value) => return Flow { returns: value.as_ref().is_none_or(|value| !self.recurses(id, value)), continues: false },
+      StmtAST::Break | StmtAST::Continue => return Flow { returns: false, continues: false },
+      StmtAST::If(condition, then_block, else_block) => {
+        if self.recurses(id, condition) {
+          return Flow { returns: false, continues: false };
+        }
+        let then_flow = self.flow(id, then_block);
+        let else_flow = match else_block {
+          Some(else_block) => self.flow(id, else_block),
+          None => Flow { returns: false, continues: true },
+        };
+        return Flow { returns: then_flow.returns || else_flow.returns, continues: then_flow.continues || else_flow.continues };
+      },
+      // The body might not run at all, and breaking out of it carries on after the loop
+      StmtAST::While(condition, body) => {
+        if self.recurses(id, condition) {
+          return Flow { returns: false, continues: false };
+        }
+        return Flow { returns: self.flow(id, body).returns, continues: true };
+      },
+      StmtAST::Block(statements) => return self.flow_block(id, statements),
+      StmtAST::Struct(..) | StmtAST::Import(_) => return Flow { returns: false, continues: true },
+    }
+  }
+}
+
+impl Visitor for Recursion<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Function(name, _, _, body, _) = &expr.node {
+      self.check(expr, name, body);
+    }
+    walk_expr(self, expr);
+  }
+}
+
+// Counts a function's calls to itself, and how many of them are tail calls
+struct SelfCalls<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  function: NodeId,
+  found: usize,
+  tail: usize,
+}
+
+impl Visitor for SelfCalls<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Call(..) = expr.node && self.declarations.get(&expr.id) == Some(&self.function) {
+      self.found += 1;
+    }
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    if let StmtAST::Return(Some(value)) = &stmt.node
+      && let ExprAST::Call(..) = value.node
+      && self.declarations.get(&value.id) == Some(&self.function) {
+      self.tail += 1;
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::resolver::resolve;
+
+  fn build(source: &str) -> (Program, NodeMap<NodeId>, CallGraph) {
+    let tokens = lex(source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    let graph = CallGraph::build(&program, &declarations);
+    return (program, declarations, graph);
+  }
+
+  // The uses of every function, by name, and the names of the functions the top level can reach, sorted
+  fn describe(graph: &CallGraph) -> (Vec<String>, Vec<String>) {
+    let name = |id: &NodeId| graph.names.get(id).cloned().unwrap_or("<top level>".to_string());
+    let mut uses: Vec<String> = graph.uses.iter()
+      .map(|(user, used)| format!("{} -> {}", name(user), used.iter().map(name).collect::<Vec<String>>().join(" ")))
+      .collect();
+    uses.sort();
+    let mut reachable: Vec<String> = graph.reachable().iter().map(name).collect();
+    reachable.sort();
+    return (uses, reachable);
+  }
+
+  #[test]
+  fn build_call_graph() {
+    let source: String = r#"
+def main() {
+  let double = def(x) { return helper(x) * 2 }
+  return map([1, 2], double)
+}
+def map(xs, f) { return [f(xs[0]), f(xs[1])] }
+def helper(x) { return def() { return x + unused(x) } }
+def unused(x) { return unused(x - 1) }
+def orphan() { return main() }
+main()
+"#.to_string();
+    let (_, _, graph) = build(&source);
+    let (uses, reachable) = describe(&graph);
+    assert_eq!(uses, vec![
+      "<top level> -> main",
+      "double -> helper",
+      "helper -> unused",
+      "main -> map double",
+      "map -> ",
+      "orphan -> main",
+      "unused -> unused",
+    ]);
+    assert_eq!(reachable, vec!["double", "helper", "main", "map", "unused"]);
+  }
+
+  #[test]
+  fn warn_about_unbounded_recursion() {
+    let source: String = r#"
+def forever(n) {
+  print(n)
+  return forever(n + 1) + 1
+}
+def countdown(n) {
+  if n == 0 { return 0 }
+  return countdown(n - 1) + 1
+}
+def tail(n) {
+  print(n)
+  return tail(n + 1)
+}
+def both(n) {
+  if n > 0 { print(both(n - 1)) } else { both(n + 1) }
+}
+def guarded(n) {
+  while n > 0 { return guarded(n - 1) * 2 }
+  return 1
+}
+"#.to_string();
+    let (program, declarations, graph) = build(&source);
+    let warnings: Vec<String> = unbounded_recursion(&program, &declarations, &graph).iter().map(|warning| warning.to_string()).collect();
+    assert_eq!(warnings, vec![
+      "Function 'forever' calls itself on every path before it can return, so it never stops recursing at 2:1",
+      "Function 'both' calls itself on every path before it can return, so it never stops recursing at 14:1",
+    ]);
+  }
+}
@@ -0,0 +1,138 @@
+use crate::builtins::builtin;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Span;
+use crate::parser::{Expr, ExprAST, NodeId, NodeMap, Program, Stmt, StmtAST};
+use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+// Check every call in `program` against what it calls, using the declarations the resolver found. A call to
+// a function, to a let or const holding a lambda, or to a builtin has to pass as many arguments as it has
+// parameters. Calls through parameters and other variables can't be checked until there are types, so
+// they're let through, and calls to undefined names have already been reported by the resolver
+pub fn check_calls(program: &Program, declarations: &NodeMap<NodeId>) -> Result<(), Vec<Diagnostic>> {
+  let mut signatures = Signatures { found: NodeMap::new() };
+  let mut calls = Calls { declarations, signatures: NodeMap::new(), errors: Vec::new() };
+  for function in &program.functions {
+    signatures.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    signatures.visit_stmt(stmt);
+  }
+  calls.signatures = signatures.found;
+
+  for function in &program.functions {
+    calls.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    calls.visit_stmt(stmt);
+  }
+  calls.errors.sort_by_key(|error| error.span.start);
+  match calls.errors.is_empty() {
+    true => return Ok(()),
+    false => return Err(calls.errors),
+  }
+}
+
+// The parameter count and location of something callable
+struct Signature {
+  arity: usize,
+  span: Span,
+}
+
+// Collects the signature of every function, and of every let or const whose value is a lambda, by the id of
+// the node the resolver reports as the declaration
+struct Signatures {
+  found: NodeMap<Signature>,
+}
+
+impl Visitor for Signatures {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Function(_, params, ..) = &expr.node {
+      self.found.insert(expr.id, Signature { arity: params.len(), span: expr.span });
+    }
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    if let StmtAST::Let(_, _, value) | StmtAST::Const(_, _, value) = &stmt.node
+      && let ExprAST::Lambda(params, ..) = &value.node {
+      self.found.insert(stmt.id, Signature { arity: params.len(), span: stmt.span });
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+struct Calls<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  signatures: NodeMap<Signature>,
+  errors: Vec<Diagnostic>,
+}
+
+impl Calls<'_> {
+  fn check(&mut self, name: &str, args: usize, call: &Expr) {
+    if let Some(declaration) = self.declarations.get(&call.id) {
+      if let Some(signature) = self.signatures.get(declaration) && signature.arity != args {
+        let message = format!("'{}' (defined at {}:{}) takes {} but is called with {}",
+          name, signature.span.line, signature.span.column, arguments(signature.arity), args);
+        self.errors.push(Diagnostic::new(message, call.span));
+      }
+      return;
+    }
+
+    if let Some(builtin) = builtin(name) && let Some(arity) = builtin.arity && arity != args {
+      let message = format!("Builtin '{}' takes {} but is called with {}", name, arguments(arity), args);
+      self.errors.push(Diagnostic::new(message, call.span));
+    }
+  }
+}
+
+impl Visitor for Calls<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Call(name, args) = &expr.node {
+      self.check(name, args.len(), expr);
+    }
+    walk_expr(self, expr);
+  }
+}
+
+// "1 argument", "2 arguments"
+fn arguments(count: usize) -> String {
+  match count {
+    1 => return "1 argument".to_string(),
+    _ => return format!("{} arguments", count),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::resolver::resolve;
+
+  fn check(source: &str) -> Vec<String> {
+    let tokens = lex(source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    match check_calls(&program, &declarations) {
+      Ok(()) => return Vec::new(),
+      Err(errors) => return errors.iter().map(|error| error.to_string()).collect(),
+    }
+  }
+
+  #[test]
+  fn check_call_arity() {
+    let mut source: String = "def fib(x) { return fib(x - 1) + fib(x - 2) }\nprint(fib(10), len([1]), push([], 1))".to_string();
+    assert_eq!(check(&source), Vec::<String>::new());
+
+    source = "def fib(x) { return x }\nfib(1, 2)\nlet add = def(a, b) { return a + b }\nadd(1)\nlen()".to_string();
+    assert_eq!(check(&source), vec![
+      "'fib' (defined at 1:1) takes 1 argument but is called with 2 at 2:1",
+      "'add' (defined at 3:1) takes 2 arguments but is called with 1 at 4:1",
+      "Builtin 'len' takes 1 argument but is called with 0 at 5:1",
+    ]);
+
+    // Calls through parameters aren't checked, and a local shadowing a function is what gets called
+    source = "def apply(f, x) { return f(x, x, x) }\ndef g() { }\ndef h(g) { return g(1) }".to_string();
+    assert_eq!(check(&source), Vec::<String>::new());
+  }
+}
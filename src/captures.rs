@@ -0,0 +1,184 @@
+use crate::parser::{Expr, ExprAST, Param, Program, Spanned, Stmt, StmtAST};
+use crate::visit::{walk_expr_mut, walk_stmt_mut, VisitorMut};
+
+// Fills in the capture list of every function and lambda nested inside another function: the variables of
+// enclosing functions it uses, in the order it first uses them. Anything declared at the top level of the
+// program is global, so nothing captures it. A function that uses a variable from two functions out
+// captures it too, so it can hand it on to the one that needs it
+pub fn resolve_captures(program: &mut Program) {
+  let mut resolver = Captures { scopes: vec![Scope { names: Vec::new(), function: 0 }], captures: Vec::new() };
+  for function in &mut program.functions {
+    resolver.visit_expr_mut(function);
+  }
+  for stmt in &mut program.top_level {
+    resolver.visit_stmt_mut(stmt);
+  }
+}
+
+// The names declared in a function's parameters or in a block, and how many functions deep it is, the top
+// level being 0
+struct Scope {
+  names: Vec<String>,
+  function: usize,
+}
+
+struct Captures {
+  scopes: Vec<Scope>,
+  captures: Vec<Vec<String>>,   // What each function being walked captures so far, innermost last
+}
+
+impl Captures {
+  fn function_depth(&self) -> usize {
+    return self.captures.len();
+  }
+
+  fn declare(&mut self, name: &str) {
+    self.scopes.last_mut().unwrap().names.push(name.to_string());
+  }
+
+  // Record a use of `name`, capturing it into every function between here and where it's declared
+  fn use_name(&mut self, name: &str) {
+    let declared_in = self.scopes.iter().rev().find(|scope| scope.names.iter().any(|declared| declared == name));
+    let Some(scope) = declared_in else { return };
+    if scope.function == 0 {
+      return;
+    }
+    for captures in &mut self.captures[scope.function..] {
+      if !captures.iter().any(|captured| captured == name) {
+        captures.push(name.to_string());
+      }
+    }
+  }
+
+  fn push_scope(&mut self) {
+    let function = self.function_depth();
+    self.scopes.push(Scope { names: Vec::new(), function });
+  }
+
+  // Walk a function's parameters and body, returning what it captures. Its own name is in scope inside it,
+  // so a function calling itself doesn't capture itself
+  fn walk_function(&mut self, name: Option<&str>, params: &[Spanned<Param>], body: &mut [Stmt]) -> Vec<String> {
+    self.captures.push(Vec::new());
+    self.push_scope();
+    if let Some(name) = name {
+      self.declare(name);
+    }
+    for param in params {
+      self.declare(&param.node.name);
+    }
+    for stmt in body {
+      self.visit_stmt_mut(stmt);
+    }
+    self.scopes.pop();
+    return self.captures.pop().unwrap();
+  }
+}
+
+impl VisitorMut for Captures {
+  fn visit_expr_mut(&mut self, expr: &mut Expr) {
+    match &mut expr.node {
+      ExprAST::Variable(name) => self.use_name(name),
+      ExprAST::Call(name, _) => {
+        self.use_name(name);
+        walk_expr_mut(self, expr);
+      },
+      ExprAST::Function(name, params, _, body, captures) => {
+        self.declare(name);
+        *captures = self.walk_function(Some(name), params, body);
+      },
+      ExprAST::Lambda(params, _, body, captures) => *captures = self.walk_function(None, params, body),
+      _ => walk_expr_mut(self, expr),
+    }
+  }
+
+  fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+    match &mut stmt.node {
+      // The value is walked first, so `let x = x` reads the x from outside
+      StmtAST::Let(name, _, value) | StmtAST::Const(name, _, value) => {
+        self.visit_expr_mut(value);
+        self.declare(name);
+      },
+      StmtAST::LetTuple(names, value) => {
+        self.visit_expr_mut(value);
+        for name in names {
+          self.declare(&name.node.name);
+        }
+      },
+      StmtAST::Block(_) => {
+        self.push_scope();
+        walk_stmt_mut(self, stmt);
+        self.scopes.pop();
+      },
+      _ => walk_stmt_mut(self, stmt),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+
+  fn resolve(source: &str) -> String {
+    let tokens = lex(source).unwrap();
+    let mut program = Parser::new(&tokens).parse().unwrap();
+    resolve_captures(&mut program);
+    return program.to_string();
+  }
+
+  #[test]
+  fn capture_enclosing_variables() {
+    let source: String = r#"
+let total = 0
+def counter(step) {
+  let count = 0
+  def tick() {
+    count = count + step
+    total = total + 1
+    return tick
+  }
+  return tick
+}
+"#.to_string();
+    assert_eq!(resolve(&source), r#"(def counter (step)
+  (let count 0)
+  (def tick () (captures count step)
+    (= count (+ count step))
+    (= total (+ total 1))
+    (return tick))
+  (return tick))
+(let total 0)
+"#);
+  }
+
+  #[test]
+  fn capture_through_nested_lambdas() {
+    let mut source: String = "def outer(a) {\n  let b = 1\n  return def(c) { return def() { return a + c + helper(b) } }\n}".to_string();
+    assert_eq!(resolve(&source), r#"(def outer (a)
+  (let b 1)
+  (return (lambda (c) (captures a b)
+    (return (lambda () (captures a c b)
+      (return (+ (+ a c) (call helper b))))))))
+"#);
+
+    // Shadowing parameters and block-scoped lets aren't captures, and a let's own value reads the outer name
+    source = "def f(x) {\n  { let y = 1 }\n  return def(x) { let x = x; return x + y }\n}".to_string();
+    assert_eq!(resolve(&source), r#"(def f (x)
+  (block
+    (let y 1))
+  (return (lambda (x)
+    (let x x)
+    (return (+ x y)))))
+"#);
+
+    // Local functions, unlike top-level ones, are captured by the functions that call them
+    source = "def main() {\n  def helper() { return 1 }\n  let run = def() { return helper() }\n}".to_string();
+    assert_eq!(resolve(&source), r#"(def main ()
+  (def helper ()
+    (return 1))
+  (let run (lambda () (captures helper)
+    (return (call helper)))))
+"#);
+  }
+}
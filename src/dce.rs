@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+
+use crate::callgraph::CallGraph;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Span;
+use crate::parser::{Expr, ExprAST, NodeId, Program, Stmt, StmtAST};
+use crate::visit::{walk_expr_mut, walk_stmt_mut, VisitorMut};
+
+// Remove the branches of `program` that can never run, once constants are folded: the block an if whose
+// condition is a literal doesn't take, and a while whose condition is false. Returns a note for each one
+// removed, saying what it was
+pub fn eliminate_dead_branches(program: &mut Program) -> Vec<Diagnostic> {
+  let mut branches = Branches { notes: Vec::new() };
+  for function in &mut program.functions {
+    branches.visit_expr_mut(function);
+  }
+  for stmt in &mut program.top_level {
+    branches.visit_stmt_mut(stmt);
+  }
+  flatten_blocks(&mut program.top_level);
+  branches.notes.sort_by_key(|note| note.span.start);
+  return branches.notes;
+}
+
+// Remove every function the top-level code can't end up calling, by the call graph of `program`, including
+// functions and lambdas declared inside the functions that are kept. Returns a note for each one removed,
+// but not for those inside a function that's removed itself
+pub fn eliminate_dead_functions(program: &mut Program, graph: &CallGraph) -> Vec<Diagnostic> {
+  let mut functions = Functions { graph, reachable: graph.reachable(), notes: Vec::new() };
+  let mut kept: Vec<Expr> = Vec::new();
+  for mut function in program.functions.drain(..) {
+    if functions.keep(function.id, function.span) {
+      functions.visit_expr_mut(&mut function);
+      kept.push(function);
+    }
+  }
+  program.functions = kept;
+  functions.retain(&mut program.top_level);
+  for stmt in &mut program.top_level {
+    functions.visit_stmt_mut(stmt);
+  }
+  functions.notes.sort_by_key(|note| note.span.start);
+  return functions.notes;
+}
+
+// Removing an if leaves the block of the branch it takes where it was, or an empty block. A block that
+// declares nothing is spliced into the list of statements it's in
+fn flatten_blocks(statements: &mut Vec<Stmt>) {
+  let declares = |stmt: &Stmt| match &stmt.node {
+    StmtAST::Let(..) | StmtAST::Const(..) | StmtAST::LetTuple(..) => true,
+    StmtAST::ExprStmt(expr) => matches!(expr.node, ExprAST::Function(..)),
+    _ => false,
+  };
+  let mut flattened: Vec<Stmt> = Vec::new();
+  for stmt in statements.drain(..) {
+    match stmt.node {
+      StmtAST::Block(inner) if !inner.iter().any(declares) => flattened.extend(inner),
+      _ => flattened.push(stmt),
+    }
+  }
+  *statements = flattened;
+}
+
+struct Branches {
+  notes: Vec<Diagnostic>,
+}
+
+impl Branches {
+  // Note the removal of `stmt`, unless it's a block that's already empty, as what it held was removed
+  // already or there was nothing there to begin with
+  fn note(&mut self, message: &str, stmt: &Stmt) {
+    if !matches!(&stmt.node, StmtAST::Block(statements) if statements.is_empty()) {
+      self.notes.push(Diagnostic::note(message.to_string(), stmt.span));
+    }
+  }
+}
+
+impl VisitorMut for Branches {
+  fn visit_expr_mut(&mut self, expr: &mut Expr) {
+    walk_expr_mut(self, expr);
+    if let ExprAST::Function(_, _, _, body, _) | ExprAST::Lambda(_, _, body, _) = &mut expr.node {
+      flatten_blocks(body);
+    }
+  }
+
+  fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+    walk_stmt_mut(self, stmt);
+    let empty = || StmtAST::Block(Vec::new());
+    match &mut stmt.node {
+      StmtAST::If(condition, then_block, else_block) => match condition.node {
+        ExprAST::Boolean(true) => {
+          if let Some(else_block) = else_block {
+            self.note("Removed the else block of an if whose condition is always true", else_block);
+          }
+          stmt.node = std::mem::replace(&mut then_block.node, empty());
+        },
+        ExprAST::Boolean(false) => match else_block.take() {
+          Some(else_block) => {
+            self.note("Removed the then block of an if whose condition is always false", then_block);
+            stmt.node = else_block.node;
+          },
+          None => {
+            self.note("Removed an if whose condition is always false", stmt);
+            stmt.node = empty();
+          },
+        },
+        _ => {},
+      },
+      StmtAST::While(condition, _) if matches!(condition.node, ExprAST::Boolean(false)) => {
+        self.note("Removed a while loop whose condition is always false", stmt);
+        stmt.node = empty();
+      },
+      StmtAST::Block(statements) => flatten_blocks(statements),
+      _ => {},
+    }
+  }
+}
+
+struct Functions<'a> {
+  graph: &'a CallGraph,
+  reachable: HashSet<NodeId>,
+  notes: Vec<Diagnostic>,
+}
+
+impl Functions<'_> {
+  // Whether the function declared by `id` is kept, noting it if it isn't
+  fn keep(&mut self, id: NodeId, span: Span) -> bool {
+    if self.reachable.contains(&id) {
+      return true;
+    }
+    let message = format!("Removed function '{}', which is never called", self.graph.names[&id]);
+    self.notes.push(Diagnostic::note(message, span));
+    return false;
+  }
+
+  // Remove the statements from `statements` that declare a function that's never called
+  fn retain(&mut self, statements: &mut Vec<Stmt>) {
+    statements.retain(|stmt| match &stmt.node {
+      StmtAST::ExprStmt(function) if matches!(function.node, ExprAST::Function(..)) => self.keep(function.id, function.span),
+      StmtAST::Let(_, _, value) | StmtAST::Const(_, _, value) if matches!(value.node, ExprAST::Lambda(..)) => self.keep(stmt.id, stmt.span),
+      _ => true,
+    });
+  }
+}
+
+impl VisitorMut for Functions<'_> {
+  fn visit_expr_mut(&mut self, expr: &mut Expr) {
+    if let ExprAST::Function(_, _, _, body, _) | ExprAST::Lambda(_, _, body, _) = &mut expr.node {
+      self.retain(body);
+    }
+    walk_expr_mut(self, expr);
+  }
+
+  fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+    if let StmtAST::Block(statements) = &mut stmt.node {
+      self.retain(statements);
+    }
+    walk_stmt_mut(self, stmt);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::fold::fold_constants;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::resolver::resolve;
+
+  // The program with its dead code removed, and the notes saying what was
+  fn eliminate(source: &str) -> (String, Vec<String>) {
+    let tokens = lex(source).unwrap();
+    let mut program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    fold_constants(&mut program, &declarations, &HashSet::new()).unwrap();
+    let mut notes = eliminate_dead_branches(&mut program);
+    let graph = CallGraph::build(&program, &declarations);
+    notes.extend(eliminate_dead_functions(&mut program, &graph));
+    notes.sort_by_key(|note| note.span.start);
+    return (program.to_string(), notes.iter().map(|note| note.to_string()).collect());
+  }
+
+  #[test]
+  fn eliminate_dead_branches_and_functions() {
+    let source: String = r#"
+const debug = false
+const limit = 10 * 10
+def check(x) {
+  if debug { log(x) }
+  if (limit > 50) { return x < limit } else { return false }
+}
+def log(x) { print(x) }
+def unused() {
+  def inner() { return 1 }
+  return inner()
+}
+while debug { print(limit) }
+if !debug {
+  let double = def(n) { return n * 2 }
+  let half = def(n) { return n / 2 }
+  print(check(double(limit)))
+} else if limit == 0 { log(0) }
+"#.to_string();
+    let (program, notes) = eliminate(&source);
+    assert_eq!(program, r#"(def check (x)
+  (return (< x 100)))
+(const debug false)
+(const limit 100)
+(block
+  (let double (lambda (n)
+    (return (* n 2))))
+  (call print (call check (call double 100))))
+"#);
+    assert_eq!(notes, vec![
+      "Removed an if whose condition is always false at 5:3",
+      "Removed the else block of an if whose condition is always true at 6:45",
+      "Removed function 'log', which is never called at 8:1",
+      "Removed function 'unused', which is never called at 9:1",
+      "Removed a while loop whose condition is always false at 13:1",
+      "Removed function 'half', which is never called at 16:3",
+      "Removed an if whose condition is always false at 18:8",
+    ]);
+  }
+}
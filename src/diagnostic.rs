@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::lexer::Span;
+
+// How serious a diagnostic is. Errors stop the program compiling, warnings point out code that's probably
+// wrong, notes say more about another diagnostic or what the compiler did, and help suggests a fix for the
+// diagnostic it's attached to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+  Error,
+  Warning,
+  Note,
+  Help,
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Severity::Error => return write!(f, "error"),
+      Severity::Warning => return write!(f, "warning"),
+      Severity::Note => return write!(f, "note"),
+      Severity::Help => return write!(f, "help"),
+    }
+  }
+}
+
+// A problem a pass over the AST found in a program, and the node it's about. Displays like a ParseError, as
+// one line ending in the line and column: "Use of undefined variable 'x' at 3:14", followed by a line for
+// each note. Help is about what to write rather than where, so it displays without the line and column
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  pub severity: Severity,
+  pub lint: Option<&'static str>,   // The lint that reported it, for warnings, which decides whether they're shown
+  pub message: String,
+  pub span: Span,
+  pub notes: Vec<Diagnostic>,       // Other places in the program the problem involves, like the declaration of what it's about, and help fixing it
+}
+
+impl Diagnostic {
+  pub fn new(message: String, span: Span) -> Self {
+    return Diagnostic { severity: Severity::Error, lint: None, message, span, notes: Vec::new() };
+  }
+
+  pub fn warning(lint: &'static str, message: String, span: Span) -> Self {
+    return Diagnostic { severity: Severity::Warning, lint: Some(lint), message, span, notes: Vec::new() };
+  }
+
+  pub fn note(message: String, span: Span) -> Self {
+    return Diagnostic { severity: Severity::Note, lint: None, message, span, notes: Vec::new() };
+  }
+
+  pub fn with_note(mut self, message: String, span: Span) -> Self {
+    self.notes.push(Diagnostic::note(message, span));
+    return self;
+  }
+
+  pub fn with_help(mut self, message: String, span: Span) -> Self {
+    self.notes.push(Diagnostic { severity: Severity::Help, lint: None, message, span, notes: Vec::new() });
+    return self;
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.severity {
+      Severity::Help => write!(f, "{}", self.message)?,
+      _ => write!(f, "{} at {}:{}", self.message, self.span.line, self.span.column)?,
+    }
+    for note in &self.notes {
+      write!(f, "\n  {}: {}", note.severity, note)?;
+    }
+    return Ok(());
+  }
+}
+
+// What becomes of the warnings a lint reports: dropped, shown, or made into errors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+  Allow,
+  Warn,
+  Deny,
+}
+
+// A kind of warning, named on the command line to allow or deny it
+pub struct Lint {
+  pub name: &'static str,
+  pub default: Level,
+}
+
+pub const LINTS: [Lint; 6] = [
+  Lint { name: "unused", default: Level::Warn },         // Variables, constants and parameters that are never read
+  Lint { name: "unreachable", default: Level::Warn },    // Statements that can never run
+  Lint { name: "recursion", default: Level::Warn },      // Functions that can only recurse forever
+  Lint { name: "overflow", default: Level::Warn },       // Constant arithmetic that overflows an int
+  Lint { name: "shadow", default: Level::Allow },        // Declarations that hide another with the same name
+  Lint { name: "keyword-case", default: Level::Warn },   // Identifiers that only differ from a keyword by case
+];
+
+// The level of every lint, starting from their defaults, and whether every warning is an error, as set by
+// -W<lint>, --allow=<lint>, --deny=<lint> and -Werror
+#[derive(Debug, Clone, Default)]
+pub struct LintLevels {
+  levels: HashMap<&'static str, Level>,
+  pub warnings_are_errors: bool,
+}
+
+impl LintLevels {
+  pub fn set(&mut self, name: &str, level: Level) -> Result<(), String> {
+    match LINTS.iter().find(|lint| lint.name == name) {
+      Some(lint) => {
+        self.levels.insert(lint.name, level);
+        return Ok(());
+      },
+      None => match closest_match(name, LINTS.iter().map(|lint| lint.name)) {
+        Some(suggestion) => return Err(format!("Unknown lint '{}', did you mean '{}'?", name, suggestion)),
+        None => return Err(format!("Unknown lint '{}'", name)),
+      },
+    }
+  }
+
+  pub fn level(&self, name: &str) -> Level {
+    let default = LINTS.iter().find(|lint| lint.name == name).map_or(Level::Warn, |lint| lint.default);
+    return self.levels.get(name).copied().unwrap_or(default);
+  }
+
+  // The severity of the warnings `lint` reports, or None if they aren't reported
+  pub fn severity(&self, lint: &str) -> Option<Severity> {
+    match self.level(lint) {
+      Level::Allow => return None,
+      Level::Warn if !self.warnings_are_errors => return Some(Severity::Warning),
+      Level::Warn | Level::Deny => return Some(Severity::Error),
+    }
+  }
+
+  // `warning` at the level of the lint that reported it
+  pub fn apply(&self, mut warning: Diagnostic) -> Option<Diagnostic> {
+    warning.severity = self.severity(warning.lint.unwrap_or(""))?;
+    return Some(warning);
+  }
+}
+
+// The candidate most like `name`, for suggesting in place of a misspelled one. Only candidates within a third
+// of the name's length of it, counting each character inserted, deleted or changed, are close enough
+pub fn closest_match<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+  let limit = name.chars().count().div_ceil(3);
+  return candidates.into_iter()
+    .map(|candidate| (edit_distance(name, candidate), candidate))
+    .filter(|(distance, _)| *distance <= limit)
+    .min_by_key(|(distance, _)| *distance)
+    .map(|(_, candidate)| candidate);
+}
+
+// Levenshtein distance between two strings, in characters
+fn edit_distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut previous: Vec<usize> = (0..=b.len()).collect();
+  for (i, a_char) in a.chars().enumerate() {
+    let mut current: Vec<usize> = vec![i + 1];
+    for (j, b_char) in b.iter().enumerate() {
+      let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+      current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+    }
+    previous = current;
+  }
+  return previous[b.len()];
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn apply_lint_levels() {
+    let span = Span { start: 0, end: 1, line: 1, column: 1 };
+    let unused = Diagnostic::warning("unused", "Variable 'x' is never read".to_string(), span);
+    let shadow = Diagnostic::warning("shadow", "Variable 'x' shadows an earlier declaration".to_string(), span);
+    let mut levels = LintLevels::default();
+    assert_eq!(levels.apply(unused.clone()).map(|warning| warning.severity), Some(Severity::Warning));
+    assert_eq!(levels.apply(shadow.clone()), None);
+
+    levels.set("shadow", Level::Warn).unwrap();
+    levels.set("unused", Level::Deny).unwrap();
+    assert_eq!(levels.apply(unused.clone()).map(|warning| warning.severity), Some(Severity::Error));
+    assert_eq!(levels.apply(shadow.clone()).map(|warning| warning.severity), Some(Severity::Warning));
+
+    levels.warnings_are_errors = true;
+    levels.set("unused", Level::Allow).unwrap();
+    assert_eq!(levels.apply(unused), None);
+    assert_eq!(levels.apply(shadow).map(|warning| warning.severity), Some(Severity::Error));
+
+    assert_eq!(levels.set("unusd", Level::Allow), Err("Unknown lint 'unusd', did you mean 'unused'?".to_string()));
+    assert_eq!(levels.set("everything", Level::Allow), Err("Unknown lint 'everything'".to_string()));
+  }
+
+  #[test]
+  fn suggest_closest_match() {
+    assert_eq!(edit_distance("fib", "fib"), 0);
+    assert_eq!(edit_distance("fibb", "fib"), 1);
+    assert_eq!(edit_distance("kitten", "sitting"), 3);
+    assert_eq!(edit_distance("", "abc"), 3);
+
+    let names = ["fib", "print", "fizzbuzz"];
+    assert_eq!(closest_match("fibb", names), Some("fib"));
+    assert_eq!(closest_match("pritn", names), Some("print"));
+    assert_eq!(closest_match("fi", names), Some("fib"));
+    assert_eq!(closest_match("length", names), None);
+  }
+}
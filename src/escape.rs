@@ -0,0 +1,260 @@
+use std::collections::HashSet;
+
+use crate::parser::{Expr, ExprAST, NodeId, NodeMap, Program, Stmt, StmtAST};
+use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+// Where a variable captured by a function lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Storage {
+  Stack,   // In the frame of the function declaring it, which outlives every function capturing it
+  Heap,    // In a cell on the heap shared by the functions capturing it, as one of them can outlive that frame
+}
+
+// One entry of a function's capture list, with where the variable it captures has to live
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+  pub name: String,
+  pub storage: Storage,
+}
+
+// Where each variable captured in `program` has to live, once resolve_captures has filled in the capture
+// lists. The table has an entry for every function and lambda that captures anything, by the id of its
+// Function or Lambda node, in the order of its capture list.
+//
+// A captured variable can stay on the stack unless a function capturing it escapes, and so can outlive the
+// frame the variable is in. A function escapes if it's used as a value rather than called, by being
+// returned, stored or passed on, if it's a lambda that isn't bound straight to a let or const, or if a
+// function that escapes uses it, as that takes it along
+pub fn escape_analysis(program: &Program, declarations: &NodeMap<NodeId>) -> NodeMap<Vec<Capture>> {
+  let mut uses = Uses { declarations, enclosing: Vec::new(), functions: Vec::new(), depths: NodeMap::new(), values: HashSet::new(), uses: Vec::new() };
+  for function in &program.functions {
+    uses.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    uses.visit_stmt(stmt);
+  }
+
+  let mut escaping: HashSet<NodeId> = uses.functions.iter()
+    .filter(|function| function.declaration.is_none_or(|declaration| uses.values.contains(&declaration)))
+    .map(|function| function.id)
+    .collect();
+  let declaring = |declaration: NodeId| uses.functions.iter().find(|function| function.declaration == Some(declaration));
+
+  // A function used from inside others is captured by each of them between its declaration and the use, so
+  // if any of those escapes it does too, which can make the functions it uses escape in turn
+  loop {
+    let mut changed = false;
+    for (declaration, enclosing) in &uses.uses {
+      let Some(function) = declaring(*declaration) else { continue };
+      if escaping.contains(&function.id) {
+        continue;
+      }
+      let mut capturing = enclosing[uses.depths[declaration]..].iter().filter(|frame| frame.declaration != Some(*declaration));
+      if capturing.any(|frame| escaping.contains(&frame.id)) {
+        escaping.insert(function.id);
+        changed = true;
+      }
+    }
+    if !changed {
+      break;
+    }
+  }
+
+  // A captured name is declared in the nearest function around the one capturing it that doesn't capture
+  // it too. Every function capturing a variable has to agree on where it lives, so it's on the heap if any
+  // of them escapes. Functions are listed before the ones inside them, so a parent's captures come first
+  let mut owners: NodeMap<Vec<NodeId>> = NodeMap::new();
+  let mut heap: HashSet<(NodeId, &str)> = HashSet::new();
+  for function in &uses.functions {
+    let Some(parent) = function.parent else { continue };
+    let parent_captures = &uses.functions.iter().find(|other| other.id == parent).unwrap().captures;
+    let owner: Vec<NodeId> = function.captures.iter().map(|name| match parent_captures.iter().position(|captured| captured == name) {
+      Some(index) => return owners[&parent][index],
+      None => return parent,
+    }).collect();
+    if escaping.contains(&function.id) {
+      heap.extend(owner.iter().zip(&function.captures).map(|(owner, name)| (*owner, name.as_str())));
+    }
+    owners.insert(function.id, owner);
+  }
+
+  let mut table: NodeMap<Vec<Capture>> = NodeMap::new();
+  for function in uses.functions.iter().filter(|function| !function.captures.is_empty()) {
+    let captures = owners[&function.id].iter().zip(&function.captures).map(|(owner, name)| {
+      let storage = match heap.contains(&(*owner, name.as_str())) {
+        true => Storage::Heap,
+        false => Storage::Stack,
+      };
+      return Capture { name: name.clone(), storage };
+    }).collect();
+    table.insert(function.id, captures);
+  }
+  return table;
+}
+
+// A function or lambda in the program
+#[derive(Clone, Copy)]
+struct Frame {
+  id: NodeId,                        // Its Function or Lambda node
+  declaration: Option<NodeId>,       // What declares its name, as in the resolver's declarations table, unless it's an unbound lambda
+}
+
+struct Function {
+  id: NodeId,
+  declaration: Option<NodeId>,
+  parent: Option<NodeId>,   // The function it's declared in, if it isn't at the top level
+  captures: Vec<String>,
+}
+
+struct Uses<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  enclosing: Vec<Frame>,               // The functions being walked, innermost last
+  functions: Vec<Function>,            // Every function, each before those inside it
+  depths: NodeMap<usize>,              // How many functions each function's declaration is inside, by declaration
+  values: HashSet<NodeId>,             // The declarations read as values rather than called
+  uses: Vec<(NodeId, Vec<Frame>)>,     // Every use of a declaration inside a function, with the functions it's inside
+}
+
+impl Uses<'_> {
+  fn use_declaration(&mut self, expr: &Expr) {
+    if let Some(declaration) = self.declarations.get(&expr.id) && !self.enclosing.is_empty() {
+      self.uses.push((*declaration, self.enclosing.clone()));
+    }
+  }
+
+  fn walk_function(&mut self, frame: Frame, captures: &[String], walk: impl FnOnce(&mut Self)) {
+    if let Some(declaration) = frame.declaration {
+      self.depths.insert(declaration, self.enclosing.len());
+    }
+    let parent = self.enclosing.last().map(|parent| parent.id);
+    self.functions.push(Function { id: frame.id, declaration: frame.declaration, parent, captures: captures.to_vec() });
+    self.enclosing.push(frame);
+    walk(self);
+    self.enclosing.pop();
+  }
+}
+
+impl Visitor for Uses<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    match &expr.node {
+      ExprAST::Variable(_) => {
+        if let Some(declaration) = self.declarations.get(&expr.id) {
+          self.values.insert(*declaration);
+        }
+        self.use_declaration(expr);
+      },
+      ExprAST::Call(..) => {
+        self.use_declaration(expr);
+        walk_expr(self, expr);
+      },
+      ExprAST::Function(.., captures) => {
+        let frame = Frame { id: expr.id, declaration: Some(expr.id) };
+        self.walk_function(frame, captures, |uses| walk_expr(uses, expr));
+      },
+      ExprAST::Lambda(.., captures) => {
+        let frame = Frame { id: expr.id, declaration: None };
+        self.walk_function(frame, captures, |uses| walk_expr(uses, expr));
+      },
+      _ => walk_expr(self, expr),
+    }
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Let(_, _, value) | StmtAST::Const(_, _, value) if let ExprAST::Lambda(.., captures) = &value.node => {
+        let frame = Frame { id: value.id, declaration: Some(stmt.id) };
+        self.walk_function(frame, captures, |uses| walk_expr(uses, value));
+      },
+      _ => walk_stmt(self, stmt),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::captures::resolve_captures;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::resolver::resolve;
+
+  // Each function's captures, by the line it starts on, with where each has to live
+  fn storage(source: &str) -> Vec<(u32, Vec<(String, Storage)>)> {
+    let tokens = lex(source).unwrap();
+    let mut program = Parser::new(&tokens).parse().unwrap();
+    resolve_captures(&mut program);
+    let declarations = resolve(&program).unwrap();
+    let table = escape_analysis(&program, &declarations);
+
+    let mut lines = NodeMap::new();
+    let mut functions = Lines(&mut lines);
+    for function in &program.functions {
+      functions.visit_expr(function);
+    }
+    for stmt in &program.top_level {
+      functions.visit_stmt(stmt);
+    }
+    let mut storage: Vec<(u32, Vec<(String, Storage)>)> = table.iter()
+      .map(|(id, captures)| (lines[id], captures.iter().map(|capture| (capture.name.clone(), capture.storage)).collect()))
+      .collect();
+    storage.sort_by_key(|(line, _)| *line);
+    return storage;
+  }
+
+  struct Lines<'a>(&'a mut NodeMap<u32>);
+
+  impl Visitor for Lines<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+      if let ExprAST::Function(..) | ExprAST::Lambda(..) = expr.node {
+        self.0.insert(expr.id, expr.span.line);
+      }
+      walk_expr(self, expr);
+    }
+  }
+
+  fn captures(names: &[(&str, Storage)]) -> Vec<(String, Storage)> {
+    return names.iter().map(|(name, storage)| (name.to_string(), *storage)).collect();
+  }
+
+  #[test]
+  fn keep_captures_on_the_stack_unless_they_escape() {
+    let source: String = r#"
+def sum(xs) {
+  let total = 0
+  def add(x) { total = total + x }
+  let i = 0
+  while i < len(xs) { add(xs[i]); i = i + 1 }
+  return total
+}
+def counter(step) {
+  let count = 0
+  def tick() { count = count + step; return count }
+  return tick
+}
+def adder(n) {
+  return def(x) { return x + n }
+}
+def run(n) {
+  def inner() { return n }
+  let wrap = def() { return inner() }
+  return wrap
+}
+def nested(a, b) {
+  let outer = def() {
+    let deep = def() { return a + b }
+    return deep
+  }
+  return outer()
+}
+"#.to_string();
+    assert_eq!(storage(&source), vec![
+      (4, captures(&[("total", Storage::Stack)])),
+      (11, captures(&[("count", Storage::Heap), ("step", Storage::Heap)])),
+      (15, captures(&[("n", Storage::Heap)])),
+      (18, captures(&[("n", Storage::Heap)])),
+      (19, captures(&[("inner", Storage::Heap)])),
+      (23, captures(&[("a", Storage::Heap), ("b", Storage::Heap)])),
+      (24, captures(&[("a", Storage::Heap), ("b", Storage::Heap)])),
+    ]);
+  }
+}
@@ -0,0 +1,313 @@
+use crate::diagnostic::Diagnostic;
+use crate::parser::{Expr, ExprAST, Program, Stmt, StmtAST};
+use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+// Check that break and continue are only used inside loops, and return only inside functions. A function
+// body starts outside any loop, even when the function is defined inside one
+pub fn check_placement(program: &Program) -> Result<(), Vec<Diagnostic>> {
+  let mut placement = Placement { loops: 0, functions: 0, errors: Vec::new() };
+  for function in &program.functions {
+    placement.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    placement.visit_stmt(stmt);
+  }
+  placement.errors.sort_by_key(|error| error.span.start);
+  match placement.errors.is_empty() {
+    true => return Ok(()),
+    false => return Err(placement.errors),
+  }
+}
+
+// Check that every function that returns a value returns one on every path through it, rather than reaching
+// the end of its body. Functions that never return a value can end whenever they like
+pub fn check_returns(program: &Program) -> Result<(), Vec<Diagnostic>> {
+  let mut returns = Returns { errors: Vec::new() };
+  for function in &program.functions {
+    returns.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    returns.visit_stmt(stmt);
+  }
+  returns.errors.sort_by_key(|error| error.span.start);
+  match returns.errors.is_empty() {
+    true => return Ok(()),
+    false => return Err(returns.errors),
+  }
+}
+
+// Warnings for statements that can never run because the one before them in the same block always leaves
+// it, by returning, breaking, continuing or looping forever. Only the first such statement in a block is
+// reported, as the rest follow from it
+pub fn unreachable_code(program: &Program) -> Vec<Diagnostic> {
+  let mut unreachable = Unreachable { warnings: Vec::new() };
+  unreachable.check_block(&program.top_level);
+  for function in &program.functions {
+    unreachable.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    unreachable.visit_stmt(stmt);
+  }
+  unreachable.warnings.sort_by_key(|warning| warning.span.start);
+  return unreachable.warnings;
+}
+
+// Whether `stmt` never finishes, so whatever comes after it in its block never runs
+fn exits(stmt: &Stmt) -> bool {
+  match &stmt.node {
+    StmtAST::Return(_) | StmtAST::Break | StmtAST::Continue => return true,
+    StmtAST::Block(statements) => return statements.iter().any(exits),
+    StmtAST::If(_, then_block, Some(else_block)) => return exits(then_block) && exits(else_block),
+    StmtAST::While(condition, body) => return matches!(condition.node, ExprAST::Boolean(true)) && !breaks(body),
+    _ => return false,
+  }
+}
+
+// Whether `stmt` can break out of the loop it's in, not counting breaks out of loops nested inside it
+fn breaks(stmt: &Stmt) -> bool {
+  match &stmt.node {
+    StmtAST::Break => return true,
+    StmtAST::Block(statements) => return statements.iter().any(breaks),
+    StmtAST::If(_, then_block, else_block) => return breaks(then_block) || else_block.as_ref().is_some_and(|else_block| breaks(else_block)),
+    _ => return false,
+  }
+}
+
+// How to refer to a statement that always leaves its block, in a warning about what follows it
+fn describe(stmt: &Stmt) -> &'static str {
+  match &stmt.node {
+    StmtAST::Return(_) => return "return",
+    StmtAST::Break => return "break",
+    StmtAST::Continue => return "continue",
+    StmtAST::If(..) => return "if",
+    StmtAST::While(..) => return "infinite loop",
+    _ => return "block",
+  }
+}
+
+// Finds whether a function body has a return with a value, without looking inside functions nested in it
+struct ReturnsValue {
+  found: bool,
+}
+
+impl Visitor for ReturnsValue {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if !matches!(expr.node, ExprAST::Function(..) | ExprAST::Lambda(..)) {
+      walk_expr(self, expr);
+    }
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    if let StmtAST::Return(Some(_)) = stmt.node {
+      self.found = true;
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+struct Placement {
+  loops: usize,       // How many loops the statement being visited is in, within its function
+  functions: usize,   // How many functions it's in
+  errors: Vec<Diagnostic>,
+}
+
+impl Visitor for Placement {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Function(..) | ExprAST::Lambda(..) = expr.node {
+      let loops = std::mem::replace(&mut self.loops, 0);
+      self.functions += 1;
+      walk_expr(self, expr);
+      self.functions -= 1;
+      self.loops = loops;
+      return;
+    }
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Break if self.loops == 0 => self.errors.push(Diagnostic::new("'break' outside of a loop".to_string(), stmt.span)),
+      StmtAST::Continue if self.loops == 0 => self.errors.push(Diagnostic::new("'continue' outside of a loop".to_string(), stmt.span)),
+      StmtAST::Return(_) if self.functions == 0 => self.errors.push(Diagnostic::new("'return' outside of a function".to_string(), stmt.span)),
+      StmtAST::While(condition, body) => {
+        self.visit_expr(condition);
+        self.loops += 1;
+        self.visit_stmt(body);
+        self.loops -= 1;
+        return;
+      },
+      _ => {},
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+struct Returns {
+  errors: Vec<Diagnostic>,
+}
+
+impl Visitor for Returns {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Function(_, _, _, body, _) | ExprAST::Lambda(_, _, body, _) = &expr.node {
+      let mut returns_value = ReturnsValue { found: false };
+      for stmt in body {
+        returns_value.visit_stmt(stmt);
+      }
+      if returns_value.found && !body.iter().any(exits) {
+        let message = match &expr.node {
+          ExprAST::Function(name, ..) => format!("Function '{}' can reach the end of its body without returning a value", name),
+          _ => "Lambda can reach the end of its body without returning a value".to_string(),
+        };
+        self.errors.push(Diagnostic::new(message, expr.span));
+      }
+    }
+    walk_expr(self, expr);
+  }
+}
+
+struct Unreachable {
+  warnings: Vec<Diagnostic>,
+}
+
+impl Unreachable {
+  fn check_block(&mut self, statements: &[Stmt]) {
+    let Some(position) = statements.iter().position(exits) else { return };
+    if let Some(next) = statements.get(position + 1) {
+      let exit = &statements[position];
+      let message = format!("Unreachable code after the {} on line {}", describe(exit), exit.span.line);
+      self.warnings.push(Diagnostic::warning("unreachable", message, next.span));
+    }
+  }
+}
+
+impl Visitor for Unreachable {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Function(_, _, _, body, _) | ExprAST::Lambda(_, _, body, _) = &expr.node {
+      self.check_block(body);
+    }
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    if let StmtAST::Block(statements) = &stmt.node {
+      self.check_block(statements);
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+
+  fn parse(source: &str) -> Program {
+    let tokens = lex(source).unwrap();
+    return Parser::new(&tokens).parse().unwrap();
+  }
+
+  fn messages(diagnostics: &[Diagnostic]) -> Vec<String> {
+    return diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+  }
+
+  #[test]
+  fn check_loop_control_and_return_placement() {
+    let mut source: String = r#"
+def f(xs) {
+  while true {
+    if xs { break } else { continue }
+  }
+  return xs
+}
+let i = 0
+while i < 10 { i = i + 1; if i == 5 { break } }
+"#.to_string();
+    assert_eq!(check_placement(&parse(&source)), Ok(()));
+
+    source = r#"
+def f(x) {
+  if x { break }
+  while x {
+    let g = def() { continue }
+    { break }
+  }
+}
+continue
+return 1
+"#.to_string();
+    assert_eq!(messages(&check_placement(&parse(&source)).err().unwrap()), vec![
+      "'break' outside of a loop at 3:10",
+      "'continue' outside of a loop at 5:21",
+      "'continue' outside of a loop at 9:1",
+      "'return' outside of a function at 10:1",
+    ]);
+  }
+
+  #[test]
+  fn check_return_paths() {
+    let mut source: String = r#"
+def sign(x) {
+  if x < 0 { return -1 } else if x > 0 { return 1 } else { return 0 }
+}
+def first(xs) {
+  let i = 0
+  while true {
+    if xs[i] > 0 { return xs[i] }
+    i = i + 1
+  }
+}
+def log(x) {
+  if x { print(x); return }
+}
+"#.to_string();
+    assert_eq!(check_returns(&parse(&source)), Ok(()));
+
+    source = r#"
+def sign(x) {
+  if x < 0 { return -1 } else if x > 0 { return 1 }
+}
+def first(xs) {
+  while true {
+    if xs[0] > 0 { break }
+    return xs[0]
+  }
+}
+def outer() {
+  let inner = def(x) { { if x { return 1 } } }
+  return def() { return 2 }
+}
+"#.to_string();
+    assert_eq!(messages(&check_returns(&parse(&source)).err().unwrap()), vec![
+      "Function 'sign' can reach the end of its body without returning a value at 2:1",
+      "Function 'first' can reach the end of its body without returning a value at 5:1",
+      "Lambda can reach the end of its body without returning a value at 12:15",
+    ]);
+  }
+
+  #[test]
+  fn warn_about_unreachable_code() {
+    let source: String = r#"
+def f(x) {
+  return x
+  print(x)
+  print(x)
+}
+while x {
+  if x { break; x = 1 } else { continue }
+  x = 2
+}
+def g() {
+  while true { }
+  return 1
+}
+return
+"#.to_string();
+    assert_eq!(messages(&unreachable_code(&parse(&source))), vec![
+      "Unreachable code after the return on line 3 at 4:3",
+      "Unreachable code after the break on line 8 at 8:17",
+      "Unreachable code after the if on line 8 at 9:3",
+      "Unreachable code after the infinite loop on line 12 at 13:3",
+    ]);
+  }
+}
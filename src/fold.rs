@@ -0,0 +1,558 @@
+use std::collections::HashSet;
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Span;
+use crate::parser::{BinaryOp, Expr, ExprAST, NodeId, NodeMap, Param, Program, Spanned, Stmt, StmtAST, UnaryOp};
+use crate::visit::{walk_expr, walk_expr_mut, walk_stmt, walk_stmt_mut, Visitor, VisitorMut};
+
+// Evaluate everything in `program` that can be worked out at compile time, using the declarations the
+// resolver found. Operators applied to literals become their result, `3 + 4 * 5` becoming `23`, and reads
+// of a const whose value is a literal become that literal. A ?: whose condition is a literal is replaced by
+// the value it takes, while an if or while whose condition becomes a literal is left for dead code
+// elimination to remove the branch it never runs. A call with literal arguments to one of the `pure`
+// functions is run, and replaced by the literal it returns, as long as it finishes in a few thousand steps
+// and only works with numbers, booleans and strings. int() and float() of a literal are converted.
+//
+// Meant to run after type checking, so it can assume operands have the types the operator needs. Anything
+// whose result can't be represented is left for the program to do when it runs, but dividing by a literal
+// zero is an error, and an operation on integer literals that overflows an int is warned about. Returns
+// the warnings if there were no errors
+pub fn fold_constants(program: &mut Program, declarations: &NodeMap<NodeId>, pure: &HashSet<NodeId>) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+  // The pure functions are run as they were written, as folding changes them while calls to them are folded
+  let mut callables = Callables { pure, found: NodeMap::new() };
+  for function in &program.functions {
+    callables.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    callables.visit_stmt(stmt);
+  }
+
+  let mut folder = Folder { declarations, functions: callables.found, constants: NodeMap::new(), errors: Vec::new(), warnings: Vec::new() };
+
+  // Functions and top-level statements are folded in the order they're written, so a const is folded
+  // before anything below it reads it
+  let mut functions = program.functions.iter_mut().peekable();
+  for stmt in &mut program.top_level {
+    while let Some(function) = functions.next_if(|function| function.span.start < stmt.span.start) {
+      folder.visit_expr_mut(function);
+    }
+    folder.visit_stmt_mut(stmt);
+  }
+  for function in functions {
+    folder.visit_expr_mut(function);
+  }
+
+  folder.errors.sort_by_key(|error| error.span.start);
+  folder.warnings.sort_by_key(|warning| warning.span.start);
+  match folder.errors.is_empty() {
+    true => return Ok(folder.warnings),
+    false => return Err(folder.errors),
+  }
+}
+
+struct Folder<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  functions: NodeMap<Callable>,    // The pure functions, by the id of what declares them
+  constants: NodeMap<ExprAST>,   // The literal value of every const found to have one so far, by the Const statement's id
+  errors: Vec<Diagnostic>,
+  warnings: Vec<Diagnostic>,
+}
+
+impl Folder<'_> {
+  // Report `lhs op rhs` if it divides by zero, overflows, or shifts by more bits than an int has
+  fn check_binary(&mut self, op: BinaryOp, lhs: &ExprAST, rhs: &ExprAST, span: Span) {
+    let zero = matches!(rhs, ExprAST::Integer(0)) || matches!(rhs, ExprAST::Float(value) if *value == 0.0);
+    match op {
+      BinaryOp::Divide if zero => self.errors.push(Diagnostic::new("Division by zero".to_string(), span)),
+      BinaryOp::Modulo if zero => self.errors.push(Diagnostic::new("Modulo by zero".to_string(), span)),
+      BinaryOp::ShiftLeft | BinaryOp::ShiftRight => {
+        if let ExprAST::Integer(bits) = rhs && !(0..64).contains(bits) {
+          let message = format!("Shift by {} is out of range, an int has 64 bits", bits);
+          self.warnings.push(Diagnostic::warning("overflow", message, span));
+        }
+      },
+      BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo | BinaryOp::Power => {
+        if let (ExprAST::Integer(lhs), ExprAST::Integer(rhs)) = (lhs, rhs)
+          && (op != BinaryOp::Power || *rhs >= 0)
+          && fold_integers(op, *lhs, *rhs).is_none() {
+          let message = format!("{} {} {} overflows an int", lhs, op, rhs);
+          self.warnings.push(Diagnostic::warning("overflow", message, span));
+        }
+      },
+      _ => {},
+    }
+  }
+}
+
+// A pure function's parameters and body, for running it
+struct Callable {
+  params: Vec<NodeId>,
+  body: Vec<Stmt>,
+}
+
+struct Callables<'a> {
+  pure: &'a HashSet<NodeId>,
+  found: NodeMap<Callable>,
+}
+
+impl Callables<'_> {
+  fn add(&mut self, id: NodeId, params: &[Spanned<Param>], body: &[Stmt]) {
+    if self.pure.contains(&id) {
+      self.found.insert(id, Callable { params: params.iter().map(|param| param.id).collect(), body: body.to_vec() });
+    }
+  }
+}
+
+impl Visitor for Callables<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Function(_, params, _, body, _) = &expr.node {
+      self.add(expr.id, params, body);
+    }
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    if let StmtAST::Const(_, _, value) = &stmt.node && let ExprAST::Lambda(params, _, body, _) = &value.node {
+      self.add(stmt.id, params, body);
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+// How many statements running a call can take, and how deeply it can call functions, before it's given up
+// on and left for the program to do
+const MAX_STEPS: usize = 10_000;
+const MAX_DEPTH: usize = 64;
+
+// Runs calls to pure functions on literals. Gives up, returning None, on anything it can't work out, like
+// a value with no literal to write it as or a function that doesn't return one
+struct Evaluator<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  functions: &'a NodeMap<Callable>,
+  constants: &'a NodeMap<ExprAST>,
+  steps: usize,
+  depth: usize,
+}
+
+// Where running a statement leaves off
+enum Outcome {
+  Next,
+  Return(ExprAST),
+  Break,
+  Continue,
+}
+
+impl Evaluator<'_> {
+  fn call(&mut self, function: NodeId, args: Vec<ExprAST>) -> Option<ExprAST> {
+    let callable = self.functions.get(&function)?;
+    if callable.params.len() != args.len() || self.depth == MAX_DEPTH {
+      return None;
+    }
+    let mut values: NodeMap<ExprAST> = callable.params.iter().copied().zip(args).collect();
+    self.depth += 1;
+    let outcome = self.block(&callable.body, &mut values);
+    self.depth -= 1;
+    match outcome? {
+      Outcome::Return(value) => return Some(value),
+      _ => return None,
+    }
+  }
+
+  fn block(&mut self, statements: &[Stmt], values: &mut NodeMap<ExprAST>) -> Option<Outcome> {
+    for stmt in statements {
+      match self.stmt(stmt, values)? {
+        Outcome::Next => {},
+        outcome => return Some(outcome),
+      }
+    }
+    return Some(Outcome::Next);
+  }
+
+  fn stmt(&mut self, stmt: &Stmt, values: &mut NodeMap<ExprAST>) -> Option<Outcome> {
+    self.steps += 1;
+    if self.steps > MAX_STEPS {
+      return None;
+    }
+    match &stmt.node {
+      StmtAST::Let(_, _, value) | StmtAST::Const(_, _, value) => {
+        let value = self.expr(value, values)?;
+        values.insert(stmt.id, value);
+      },
+      StmtAST::Assign(target, value) => {
+        let declaration = self.declarations.get(&target.id).filter(|declaration| values.contains_key(declaration))?;
+        let value = self.expr(value, values)?;
+        values.insert(*declaration, value);
+      },
+      StmtAST::ExprStmt(expr) => {
+        self.expr(expr, values)?;
+      },
+      StmtAST::Return(Some(value)) => return Some(Outcome::Return(self.expr(value, values)?)),
+      StmtAST::Break => return Some(Outcome::Break),
+      StmtAST::Continue => return Some(Outcome::Continue),
+      StmtAST::If(condition, then_block, else_block) => match (self.expr(condition, values)?, else_block) {
+        (ExprAST::Boolean(true), _) => return self.stmt(then_block, values),
+        (ExprAST::Boolean(false), Some(else_block)) => return self.stmt(else_block, values),
+        (ExprAST::Boolean(false), None) => {},
+        _ => return None,
+      },
+      StmtAST::While(condition, body) => loop {
+        match self.expr(condition, values)? {
+          ExprAST::Boolean(true) => match self.stmt(body, values)? {
+            Outcome::Return(value) => return Some(Outcome::Return(value)),
+            Outcome::Break => break,
+            Outcome::Next | Outcome::Continue => {},
+          },
+          ExprAST::Boolean(false) => break,
+          _ => return None,
+        }
+      },
+      StmtAST::Block(statements) => return self.block(statements, values),
+      _ => return None,
+    }
+    return Some(Outcome::Next);
+  }
+
+  fn expr(&mut self, expr: &Expr, values: &NodeMap<ExprAST>) -> Option<ExprAST> {
+    match &expr.node {
+      literal if is_literal(literal) => return Some(literal.clone()),
+      ExprAST::Variable(_) => {
+        let declaration = self.declarations.get(&expr.id)?;
+        return values.get(declaration).or(self.constants.get(declaration)).cloned();
+      },
+      ExprAST::Unary(op, operand) => return fold_unary(*op, &self.expr(operand, values)?),
+      ExprAST::Binary(op, lhs, rhs) => return fold_binary(*op, &self.expr(lhs, values)?, &self.expr(rhs, values)?),
+      ExprAST::Logical(op, lhs, rhs) => match (op, self.expr(lhs, values)?) {
+        (BinaryOp::And, ExprAST::Boolean(false)) | (BinaryOp::Or, ExprAST::Boolean(true)) => return Some(ExprAST::Boolean(*op == BinaryOp::Or)),
+        (_, ExprAST::Boolean(_)) => return self.expr(rhs, values),
+        _ => return None,
+      },
+      ExprAST::Conditional(condition, then_value, else_value) => match self.expr(condition, values)? {
+        ExprAST::Boolean(true) => return self.expr(then_value, values),
+        ExprAST::Boolean(false) => return self.expr(else_value, values),
+        _ => return None,
+      },
+      ExprAST::Call(name, args) => {
+        let args = args.iter().map(|arg| self.expr(arg, values)).collect::<Option<Vec<ExprAST>>>()?;
+        match self.declarations.get(&expr.id) {
+          Some(function) => return self.call(*function, args),
+          None => return fold_builtin(name, &args),
+        }
+      },
+      _ => return None,
+    }
+  }
+}
+
+fn is_literal(expr: &ExprAST) -> bool {
+  return matches!(expr, ExprAST::Integer(_) | ExprAST::Float(_) | ExprAST::Boolean(_) | ExprAST::StringLiteral(_));
+}
+
+// A call to the builtin `name` on literals, if it can be worked out. Only the conversions can be
+fn fold_builtin(name: &str, args: &[ExprAST]) -> Option<ExprAST> {
+  match (name, args) {
+    ("int", [ExprAST::Integer(value)]) => return Some(ExprAST::Integer(*value)),
+    ("int", [ExprAST::Float(value)]) => {
+      // Floats past the ends of an int have no int to convert to, but `as` would clamp them to one
+      let truncated = value.trunc();
+      match truncated >= i64::MIN as f64 && truncated < i64::MAX as f64 {
+        true => return Some(ExprAST::Integer(truncated as i64)),
+        false => return None,
+      }
+    },
+    ("float", [ExprAST::Integer(value)]) => return Some(ExprAST::Float(*value as f64)),
+    ("float", [ExprAST::Float(value)]) => return Some(ExprAST::Float(*value)),
+    _ => return None,
+  }
+}
+
+// `op operand`, if it can be worked out
+fn fold_unary(op: UnaryOp, operand: &ExprAST) -> Option<ExprAST> {
+  match (op, operand) {
+    (UnaryOp::Negate, ExprAST::Integer(value)) => return value.checked_neg().map(ExprAST::Integer),
+    (UnaryOp::Negate, ExprAST::Float(value)) => return Some(ExprAST::Float(-value)),
+    (UnaryOp::Not, ExprAST::Boolean(value)) => return Some(ExprAST::Boolean(!value)),
+    (UnaryOp::BitNot, ExprAST::Integer(value)) => return Some(ExprAST::Integer(!value)),
+    _ => return None,
+  }
+}
+
+// `lhs op rhs`, if it can be worked out
+fn fold_binary(op: BinaryOp, lhs: &ExprAST, rhs: &ExprAST) -> Option<ExprAST> {
+  match (lhs, rhs) {
+    (ExprAST::Integer(lhs), ExprAST::Integer(rhs)) => return fold_integers(op, *lhs, *rhs),
+    (ExprAST::Float(lhs), ExprAST::Float(rhs)) => return fold_floats(op, *lhs, *rhs),
+    (ExprAST::Boolean(lhs), ExprAST::Boolean(rhs)) => match op {
+      BinaryOp::Equal => return Some(ExprAST::Boolean(lhs == rhs)),
+      BinaryOp::NotEqual => return Some(ExprAST::Boolean(lhs != rhs)),
+      _ => return None,
+    },
+    (ExprAST::StringLiteral(lhs), ExprAST::StringLiteral(rhs)) => match op {
+      BinaryOp::Add => return Some(ExprAST::StringLiteral(format!("{}{}", lhs, rhs))),
+      BinaryOp::Equal => return Some(ExprAST::Boolean(lhs == rhs)),
+      BinaryOp::NotEqual => return Some(ExprAST::Boolean(lhs != rhs)),
+      _ => return None,
+    },
+    _ => return None,
+  }
+}
+
+fn fold_integers(op: BinaryOp, lhs: i64, rhs: i64) -> Option<ExprAST> {
+  let value = match op {
+    BinaryOp::Add => lhs.checked_add(rhs),
+    BinaryOp::Subtract => lhs.checked_sub(rhs),
+    BinaryOp::Multiply => lhs.checked_mul(rhs),
+    BinaryOp::Divide => lhs.checked_div(rhs),
+    BinaryOp::Modulo => lhs.checked_rem(rhs),
+    BinaryOp::Power => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_pow(rhs)),
+    BinaryOp::BitAnd => Some(lhs & rhs),
+    BinaryOp::BitOr => Some(lhs | rhs),
+    BinaryOp::BitXor => Some(lhs ^ rhs),
+    BinaryOp::ShiftLeft => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shl(rhs)),
+    BinaryOp::ShiftRight => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shr(rhs)),
+    _ => return compare(op, lhs, rhs),
+  };
+  return value.map(ExprAST::Integer);
+}
+
+fn fold_floats(op: BinaryOp, lhs: f64, rhs: f64) -> Option<ExprAST> {
+  let value = match op {
+    BinaryOp::Add => lhs + rhs,
+    BinaryOp::Subtract => lhs - rhs,
+    BinaryOp::Multiply => lhs * rhs,
+    BinaryOp::Divide => lhs / rhs,
+    BinaryOp::Modulo => lhs % rhs,
+    BinaryOp::Power => lhs.powf(rhs),
+    _ => return compare(op, lhs, rhs),
+  };
+  // Infinity and NaN have no literal to write them as
+  match value.is_finite() {
+    true => return Some(ExprAST::Float(value)),
+    false => return None,
+  }
+}
+
+fn compare<T: PartialOrd>(op: BinaryOp, lhs: T, rhs: T) -> Option<ExprAST> {
+  let value = match op {
+    BinaryOp::Equal => lhs == rhs,
+    BinaryOp::NotEqual => lhs != rhs,
+    BinaryOp::LessThan => lhs < rhs,
+    BinaryOp::GreaterThan => lhs > rhs,
+    BinaryOp::LessThanEqual => lhs <= rhs,
+    BinaryOp::GreaterThanEqual => lhs >= rhs,
+    _ => return None,
+  };
+  return Some(ExprAST::Boolean(value));
+}
+
+impl VisitorMut for Folder<'_> {
+  fn visit_expr_mut(&mut self, expr: &mut Expr) {
+    walk_expr_mut(self, expr);
+    let folded = match &mut expr.node {
+      ExprAST::Variable(_) => self.declarations.get(&expr.id).and_then(|declaration| self.constants.get(declaration)).cloned(),
+      ExprAST::Unary(op, operand) => {
+        if let (UnaryOp::Negate, ExprAST::Integer(i64::MIN)) = (*op, &operand.node) {
+          self.warnings.push(Diagnostic::warning("overflow", format!("Negating {} overflows an int", i64::MIN), expr.span));
+        }
+        fold_unary(*op, &operand.node)
+      },
+      ExprAST::Binary(op, lhs, rhs) => {
+        self.check_binary(*op, &lhs.node, &rhs.node, expr.span);
+        fold_binary(*op, &lhs.node, &rhs.node)
+      },
+      // && and || only need their left side to be known, as the right side either decides the result or isn't run
+      ExprAST::Logical(op, lhs, rhs) => match (op, &lhs.node) {
+        (BinaryOp::And, ExprAST::Boolean(true)) | (BinaryOp::Or, ExprAST::Boolean(false)) => Some(std::mem::replace(&mut rhs.node, ExprAST::Boolean(false))),
+        (BinaryOp::And, ExprAST::Boolean(false)) | (BinaryOp::Or, ExprAST::Boolean(true)) => Some(lhs.node.clone()),
+        _ => None,
+      },
+      ExprAST::Conditional(condition, then_value, else_value) => match condition.node {
+        ExprAST::Boolean(true) => Some(std::mem::replace(&mut then_value.node, ExprAST::Boolean(false))),
+        ExprAST::Boolean(false) => Some(std::mem::replace(&mut else_value.node, ExprAST::Boolean(false))),
+        _ => None,
+      },
+      ExprAST::Call(name, args) if args.iter().all(|arg| is_literal(&arg.node)) => {
+        let args: Vec<ExprAST> = args.iter().map(|arg| arg.node.clone()).collect();
+        match self.declarations.get(&expr.id) {
+          Some(function) => {
+            let mut evaluator = Evaluator { declarations: self.declarations, functions: &self.functions, constants: &self.constants, steps: 0, depth: 0 };
+            evaluator.call(*function, args)
+          },
+          None => fold_builtin(name, &args),
+        }
+      },
+      _ => None,
+    };
+    if let Some(folded) = folded {
+      expr.node = folded;
+    }
+  }
+
+  fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+    walk_stmt_mut(self, stmt);
+    if let StmtAST::Const(_, _, value) = &stmt.node && is_literal(&value.node) {
+      self.constants.insert(stmt.id, value.node.clone());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::callgraph::CallGraph;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::purity::pure_functions;
+  use crate::resolver::resolve;
+
+  fn fold(source: &str) -> String {
+    let tokens = lex(source).unwrap();
+    let mut program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    fold_constants(&mut program, &declarations, &HashSet::new()).unwrap();
+    return program.to_string();
+  }
+
+  fn diagnostics(source: &str) -> Result<Vec<String>, Vec<String>> {
+    let tokens = lex(source).unwrap();
+    let mut program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    let messages = |diagnostics: Vec<Diagnostic>| diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+    return fold_constants(&mut program, &declarations, &HashSet::new()).map(messages).map_err(messages);
+  }
+
+  #[test]
+  fn fold_constant_expressions() {
+    let source: String = r#"
+let a = 3 + 4 * 5
+let b = -(2 ** 10) % 7 < 0 && !false
+let c = 1.5 * 2.0 + 0.25
+let d = "con" + "cat" == "concat" ? 1 << 4 : 0
+let e = a + 1 * 2
+let f = false && e > 0 || e == 2
+let g = [9223372036854775807 + 1, 2 ** -1, 1e308 * 10.0]
+let h = [int(2.9), int(-2.9), int(1e19), float(3) / 2.0]
+let i = [-7 % 3, 7 % -3, -7 % -3, -7 / 2, 7 / -2]
+let j = [-7.5 % 2.0, 7.5 % -2.0]
+"#.to_string();
+    assert_eq!(fold(&source), r#"(let a 23)
+(let b true)
+(let c 3.25)
+(let d 16)
+(let e (+ a 2))
+(let f (== e 2))
+(let g (array (+ 9223372036854775807 1) (** 2 -1) (* 1e308 10.0)))
+(let h (array 2 -2 (call int 1e19) 1.5))
+(let i (array -1 1 -1 -3 -3))
+(let j (array -1.5 1.5))
+"#);
+  }
+
+  #[test]
+  fn fold_conditions_and_propagate_constants() {
+    let source: String = r#"
+const debug = false
+const limit = 10 * 10
+const name = "sil"
+def check(x) {
+  if debug { print(x) }
+  if (limit > 50) { return x < limit } else { return false }
+}
+while debug { print(name) }
+let label = debug ? "debug" : name
+if !debug { print(limit) } else if label == "" { print(0) }
+"#.to_string();
+    assert_eq!(fold(&source), r#"(def check (x)
+  (if false
+    (block
+      (call print x)))
+  (if true
+    (block
+      (return (< x 100)))
+    (block
+      (return false))))
+(const debug false)
+(const limit 100)
+(const name "sil")
+(while false
+  (block
+    (call print "sil")))
+(let label "sil")
+(if true
+  (block
+    (call print 100))
+  (if (== label "")
+    (block
+      (call print 0))))
+"#);
+  }
+
+  #[test]
+  fn fold_calls_to_pure_functions() {
+    let source: String = r#"
+def square(x) { return x * x }
+def sum_squares(n) {
+  let sum = 0
+  let i = 0
+  while i < n { sum = sum + square(i); i = i + 1 }
+  return sum
+}
+def shout(x) { print(x); return x }
+def forever(n) { return forever(n + 1) }
+const base = 3
+let a = square(base)
+let b = sum_squares(4) > 10 ? "big" : "small"
+let c = shout(2)
+let d = forever(1)
+let e = square(a)
+"#.to_string();
+    let tokens = lex(&source).unwrap();
+    let mut program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    let pure = pure_functions(&program, &declarations, &CallGraph::build(&program, &declarations));
+    fold_constants(&mut program, &declarations, &pure).unwrap();
+    let folded = program.to_string();
+    assert_eq!(folded.lines().skip_while(|line| !line.starts_with("(const")).collect::<Vec<&str>>(), vec![
+      "(const base 3)",
+      "(let a 9)",
+      "(let b \"big\")",
+      "(let c (call shout 2))",
+      "(let d (call forever 1))",
+      "(let e (call square a))",
+    ]);
+  }
+
+  #[test]
+  fn report_division_by_zero_and_overflow() {
+    let mut source: String = r#"
+const zero = 0
+def f(x) { return x / zero + x % (1 - 1) }
+let y = 1.5 / 0.0
+let z = 10 / 2
+"#.to_string();
+    assert_eq!(diagnostics(&source), Err(vec![
+      "Division by zero at 3:19".to_string(),
+      "Modulo by zero at 3:30".to_string(),
+      "Division by zero at 4:9".to_string(),
+    ]));
+
+    source = r#"
+const big = 9223372036854775807
+let a = big + 1
+let b = 3 * 4611686018427387904
+let c = 2 ** 63 - 1
+let d = 1 << 64
+let e = -(-big - 1)
+let f = big + 0
+let g = (-big - 1) % -1
+let h = (-big - 1) % 1
+"#.to_string();
+    assert_eq!(diagnostics(&source), Ok(vec![
+      "9223372036854775807 + 1 overflows an int at 3:9".to_string(),
+      "3 * 4611686018427387904 overflows an int at 4:9".to_string(),
+      "2 ** 63 overflows an int at 5:9".to_string(),
+      "Shift by 64 is out of range, an int has 64 bits at 6:9".to_string(),
+      "Negating -9223372036854775808 overflows an int at 7:9".to_string(),
+      "-9223372036854775808 % -1 overflows an int at 9:9".to_string(),
+    ]));
+  }
+}
@@ -0,0 +1,91 @@
+use crate::lexer::{Keyword, LosslessTokens, Span, Token, TokenKind, TriviaKind};
+
+// What a piece of source looks like to an editor. Classification only looks at tokens, never at what they
+// mean, so it works on any file the lexer accepts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HighlightKind {
+  Keyword,
+  Operator,
+  Punctuation,   // Groupings, commas, semicolons and the like
+  Literal,       // Numbers, strings, characters, true and false
+  Identifier,
+  Comment,       // Line, block and doc comments
+}
+
+pub fn classify(token: &Token) -> HighlightKind {
+  match token.kind {
+    TokenKind::Keyword(Keyword::True | Keyword::False) => HighlightKind::Literal,
+    TokenKind::Keyword(_) => HighlightKind::Keyword,
+
+    TokenKind::OpenParen | TokenKind::CloseParen | TokenKind::OpenCurly | TokenKind::CloseCurly |
+    TokenKind::OpenBracket | TokenKind::CloseBracket | TokenKind::Comma | TokenKind::Semicolon |
+    TokenKind::Colon | TokenKind::Dot | TokenKind::Arrow => HighlightKind::Punctuation,
+
+    TokenKind::Equal | TokenKind::EqualEqual | TokenKind::LessThan | TokenKind::GreaterThan |
+    TokenKind::LessThanEqual | TokenKind::GreaterThanEqual | TokenKind::NotEqual | TokenKind::And |
+    TokenKind::Or | TokenKind::Not | TokenKind::BitAnd | TokenKind::BitOr | TokenKind::BitXor |
+    TokenKind::BitNot | TokenKind::ShiftLeft | TokenKind::ShiftRight | TokenKind::Plus | TokenKind::PlusPlus |
+    TokenKind::Minus | TokenKind::MinusMinus | TokenKind::Times | TokenKind::Power | TokenKind::Divide |
+    TokenKind::Modulo | TokenKind::Question => HighlightKind::Operator,
+
+    TokenKind::Integer(_) | TokenKind::Float(_) | TokenKind::StringLiteral(_) | TokenKind::StringStart(_) |
+    TokenKind::StringMiddle(_) | TokenKind::StringEnd(_) | TokenKind::CharLiteral(_) => HighlightKind::Literal,
+
+    TokenKind::Identifier(_) => HighlightKind::Identifier,
+    TokenKind::DocComment(_) => HighlightKind::Comment,
+  }
+}
+
+// Every highlighted region of a lexed file in source order: all of its tokens plus the comments in its
+// trivia. Whitespace and newlines are left unhighlighted
+pub fn highlight(lossless: &LosslessTokens) -> Vec<(Span, HighlightKind)> {
+  let mut regions: Vec<(Span, HighlightKind)> = Vec::new();
+  for token in &lossless.tokens {
+    for trivia in token.leading.iter().filter(|trivia| trivia.kind == TriviaKind::Comment) {
+      regions.push((trivia.span, HighlightKind::Comment));
+    }
+    regions.push((token.token.span, classify(&token.token)));
+  }
+  for trivia in lossless.trailing.iter().filter(|trivia| trivia.kind == TriviaKind::Comment) {
+    regions.push((trivia.span, HighlightKind::Comment));
+  }
+  return regions;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::{lex, lex_with_trivia};
+
+  #[test]
+  fn classify_tokens() {
+    let source: String = "def f(x) { return x ** 2 + \"s\" == true }".to_string();
+    let tokens = lex(&source).unwrap();
+    let kinds: Vec<HighlightKind> = tokens.iter().map(classify).collect();
+    assert_eq!(kinds, vec![
+      HighlightKind::Keyword, HighlightKind::Identifier, HighlightKind::Punctuation, HighlightKind::Identifier,
+      HighlightKind::Punctuation, HighlightKind::Punctuation, HighlightKind::Keyword, HighlightKind::Identifier,
+      HighlightKind::Operator, HighlightKind::Literal, HighlightKind::Operator, HighlightKind::Literal,
+      HighlightKind::Operator, HighlightKind::Literal, HighlightKind::Punctuation,
+    ]);
+  }
+
+  #[test]
+  fn highlight_includes_comments() {
+    let source: String = "## Doc\nx = 1 # set x\n#[ block ]# y\n# done".to_string();
+    let lossless = lex_with_trivia(&source).unwrap();
+    let regions: Vec<(&str, HighlightKind)> = highlight(&lossless).into_iter()
+      .map(|(span, kind)| (&source[span.start..span.end], kind))
+      .collect();
+    assert_eq!(regions, vec![
+      ("## Doc", HighlightKind::Comment),
+      ("x", HighlightKind::Identifier),
+      ("=", HighlightKind::Operator),
+      ("1", HighlightKind::Literal),
+      ("# set x", HighlightKind::Comment),
+      ("#[ block ]#", HighlightKind::Comment),
+      ("y", HighlightKind::Identifier),
+      ("# done", HighlightKind::Comment),
+    ]);
+  }
+}
@@ -1,194 +1,1134 @@
-// Token Type(Token Lexeme/Literal)
+use std::{borrow::Cow, fmt, fs::File, io::{BufRead, BufReader}, num::IntErrorKind};
+
+use crate::source::SourceChars;
+
+// Token Type(Literal value)
+// Only literals and identifiers carry a payload; the text of every other token is fixed by its kind and
+// can always be recovered from the token's span. Identifiers and string literals borrow their text from the
+// source whenever it is held in memory, so lexing doesn't allocate per token; only strings with escape
+// sequences (and streamed sources) need an owned copy
 #[derive(Debug, PartialEq)]
-pub enum Token {
-  Def(String),
-  Let(String),
-  If(String),
-  Else(String),
-  While(String),
-  Return(String),
-  Break(String),
-  Continue(String),
-  True(String),
-  False(String),
-
-  OpenParen(char),
-  CloseParen(char),
-  OpenCurly(char),
-  CloseCurly(char),
-  OpenBracket(char),
-  CloseBracket(char),
-
-  Equal(char),
-  EqualEqual(String),
-  LessThan(char),
-  GreaterThan(char),
-  LessThanEqual(String),
-  GreaterThanEqual(String),
-  Plus(char),
-  Minus(char),
-  Times(char),
-  Divide(char),
-  Comma(char),
-  Semicolon(char),
-
-  Number(f64),
-  Identifier(String),
-}
-
-pub struct LoggedToken {
-  pub token: Token,
-  pub line_num: u32,
-  pub position: u32,
-}
-
-impl LoggedToken {
-  pub fn new(t_tok: Token, line_num: u32, position: u32) -> Self {
-    LoggedToken { token: t_tok, line_num, position }
-  }
-}
-
-pub fn lex(input: &String) -> Result<Vec<LoggedToken>, String>  {
-  let mut tokens: Vec<LoggedToken> = Vec::new();
-
-  // Position in file
-  let mut it: std::iter::Peekable<std::str::Chars<'_>> = input.chars().peekable();
-  let mut line_num: u32 = 0;
-  let mut position: u32 = 0;
-
-  while let Some(ch) = it.next() {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TokenKind<'src> {
+  Keyword(Keyword),
+
+  OpenParen,
+  CloseParen,
+  OpenCurly,
+  CloseCurly,
+  OpenBracket,
+  CloseBracket,
+
+  Equal,
+  EqualEqual,
+  LessThan,
+  GreaterThan,
+  LessThanEqual,
+  GreaterThanEqual,
+  NotEqual,
+  And,
+  Or,
+  Not,
+  BitAnd,
+  BitOr,
+  BitXor,
+  BitNot,
+  ShiftLeft,
+  ShiftRight,
+  Plus,
+  PlusPlus,
+  Minus,
+  MinusMinus,
+  Times,
+  Power,
+  Divide,
+  Modulo,
+  Comma,
+  Semicolon,
+  Colon,
+  Question,
+  Dot,
+  Arrow,
+
+  Integer(i64),
+  Float(f64),
+  StringLiteral(Cow<'src, str>),
+  // An interpolated string like "sum is ${a + b}!" is split around its embedded expressions: StringStart("sum is "),
+  // the expression's tokens, then StringEnd("!"), with a StringMiddle for the text between any two expressions
+  StringStart(Cow<'src, str>),
+  StringMiddle(Cow<'src, str>),
+  StringEnd(Cow<'src, str>),
+  CharLiteral(char),
+  Identifier(Cow<'src, str>),
+  DocComment(Cow<'src, str>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Keyword {
+  Def,
+  Let,
+  If,
+  Else,
+  While,
+  Return,
+  Break,
+  Continue,
+  True,
+  False,
+  Struct,
+  Import,
+  Const,
+}
+
+// Every reserved word and the keyword it lexes to. Adding a keyword only takes a new Keyword variant and an
+// entry here
+const KEYWORDS: [(&str, Keyword); 13] = [
+  ("def", Keyword::Def),
+  ("let", Keyword::Let),
+  ("if", Keyword::If),
+  ("else", Keyword::Else),
+  ("while", Keyword::While),
+  ("return", Keyword::Return),
+  ("break", Keyword::Break),
+  ("continue", Keyword::Continue),
+  ("true", Keyword::True),
+  ("false", Keyword::False),
+  ("struct", Keyword::Struct),
+  ("import", Keyword::Import),
+  ("const", Keyword::Const),
+];
+
+impl Keyword {
+  // The keyword spelled by `word`, if it is one
+  pub fn from_word(word: &str) -> Option<Keyword> {
+    KEYWORDS.iter().find(|(text, _)| *text == word).map(|(_, keyword)| *keyword)
+  }
+
+  // How the keyword is written in source
+  pub fn as_str(self) -> &'static str {
+    KEYWORDS.iter().find(|(_, keyword)| *keyword == self).map(|(text, _)| *text).unwrap()
+  }
+}
+
+// Where a token sits in the source. `start` and `end` are byte offsets (end exclusive), while `line` and
+// `column` are 1-based and count characters, for reporting locations to users
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+  pub line: u32,
+  pub column: u32,
+}
+
+impl Span {
+  // Span from the start of this one to the end of `end`, for something made up of several tokens
+  pub fn to(self, end: Span) -> Span {
+    Span { end: end.end, ..self }
+  }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Token<'src> {
+  pub kind: TokenKind<'src>,
+  pub span: Span,
+}
+
+impl<'src> Token<'src> {
+  pub fn new(kind: TokenKind<'src>, span: Span) -> Self {
+    Token { kind, span }
+  }
+}
+
+// Tokens display as they would be written in source, so diagnostics can quote them (e.g. `found '+'`)
+impl fmt::Display for TokenKind<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let lexeme = match self {
+      TokenKind::Keyword(keyword) => keyword.as_str(),
+      TokenKind::OpenParen => "(",
+      TokenKind::CloseParen => ")",
+      TokenKind::OpenCurly => "{",
+      TokenKind::CloseCurly => "}",
+      TokenKind::OpenBracket => "[",
+      TokenKind::CloseBracket => "]",
+      TokenKind::Equal => "=",
+      TokenKind::EqualEqual => "==",
+      TokenKind::LessThan => "<",
+      TokenKind::GreaterThan => ">",
+      TokenKind::LessThanEqual => "<=",
+      TokenKind::GreaterThanEqual => ">=",
+      TokenKind::NotEqual => "!=",
+      TokenKind::And => "&&",
+      TokenKind::Or => "||",
+      TokenKind::Not => "!",
+      TokenKind::BitAnd => "&",
+      TokenKind::BitOr => "|",
+      TokenKind::BitXor => "^",
+      TokenKind::BitNot => "~",
+      TokenKind::ShiftLeft => "<<",
+      TokenKind::ShiftRight => ">>",
+      TokenKind::Plus => "+",
+      TokenKind::PlusPlus => "++",
+      TokenKind::Minus => "-",
+      TokenKind::MinusMinus => "--",
+      TokenKind::Times => "*",
+      TokenKind::Power => "**",
+      TokenKind::Divide => "/",
+      TokenKind::Modulo => "%",
+      TokenKind::Comma => ",",
+      TokenKind::Semicolon => ";",
+      TokenKind::Colon => ":",
+      TokenKind::Question => "?",
+      TokenKind::Dot => ".",
+      TokenKind::Arrow => "->",
+
+      // Literals are shown with their value, re-escaped so the text reads back as the same token
+      TokenKind::Integer(num) => return write!(f, "{}", num),
+      TokenKind::Float(num) => return write!(f, "{:?}", num),
+      TokenKind::StringLiteral(text) => return write!(f, "\"{}\"", escape_string(text)),
+      TokenKind::StringStart(text) => return write!(f, "\"{}${{", escape_string(text)),
+      TokenKind::StringMiddle(text) => return write!(f, "}}{}${{", escape_string(text)),
+      TokenKind::StringEnd(text) => return write!(f, "}}{}\"", escape_string(text)),
+      TokenKind::CharLiteral(ch) => return match ch {
+        '\'' => write!(f, "'\\''"),
+        _ => write!(f, "'{}'", escape_char(*ch)),
+      },
+      TokenKind::Identifier(name) => name,
+      TokenKind::DocComment(text) => return write!(f, "## {}", text),
+    };
+    return write!(f, "{}", lexeme);
+  }
+}
+
+impl fmt::Display for Token<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return write!(f, "{}", self.kind);
+  }
+}
+
+// The escape sequence for a character in a string or character literal, or the character itself
+fn escape_char(ch: char) -> String {
+  match ch {
+    '\n' => "\\n".to_string(),
+    '\t' => "\\t".to_string(),
+    '\\' => "\\\\".to_string(),
+    _ => ch.to_string(),
+  }
+}
+
+pub(crate) fn escape_string(text: &str) -> String {
+  let mut escaped = String::new();
+  for ch in text.chars() {
     match ch {
-      // Handle whitespace and new lines
-      ' ' | '\t' => continue,
-      '\n' => line_num += 1,
-
-      // Handle various single-character tokens like parentheses, brackets, and operators
-      '(' => tokens.push(LoggedToken::new(Token::OpenParen('('), line_num, position)),
-      ')' => tokens.push(LoggedToken::new(Token::CloseParen(')'), line_num, position)),
-      '{' => tokens.push(LoggedToken::new(Token::OpenCurly('{'), line_num, position)),
-      '}' => tokens.push(LoggedToken::new(Token::CloseCurly('}'), line_num, position)),
-      '[' => tokens.push(LoggedToken::new(Token::OpenBracket('['), line_num, position)),
-      ']' => tokens.push(LoggedToken::new(Token::CloseBracket(']'), line_num, position)),
-
-      // Handle single-character operators and punctuation
-      '+' => tokens.push(LoggedToken::new(Token::Plus('+'), line_num, position)),
-      '-' => tokens.push(LoggedToken::new(Token::Minus('-'), line_num, position)),
-      '*' => tokens.push(LoggedToken::new(Token::Times('*'), line_num, position)),
-      '/' => tokens.push(LoggedToken::new(Token::Divide('/'), line_num, position)),
-      ',' => tokens.push(LoggedToken::new(Token::Comma(','), line_num, position)),
-      ';' => tokens.push(LoggedToken::new(Token::Semicolon(';'), line_num, position)),
-
-      // Handle two-character operators
-      '=' => {
-        if let Some(next_ch) = it.peek() {
-          match next_ch {
-            '=' => {
-              tokens.push(LoggedToken::new(Token::EqualEqual("==".to_string()), line_num, position));
-              it.next();
-              position += 1;
-            },
-            _ => {
-              tokens.push(LoggedToken::new(Token::Equal('='), line_num, position));
-              continue;
-            }
+      '"' => escaped.push_str("\\\""),
+      '$' => escaped.push_str("\\$"),
+      _ => escaped.push_str(&escape_char(ch)),
+    }
+  }
+  return escaped;
+}
+
+// Limits on what the lexer will accept, so a service lexing untrusted input can bound the work done and the
+// memory held for it. The defaults impose no limits. Lengths are in bytes
+#[derive(Debug, Clone)]
+pub struct LexerConfig {
+  pub max_identifier_len: usize,
+  pub max_number_len: usize,
+  pub max_tokens: usize,
+}
+
+impl Default for LexerConfig {
+  fn default() -> Self {
+    LexerConfig { max_identifier_len: usize::MAX, max_number_len: usize::MAX, max_tokens: usize::MAX }
+  }
+}
+
+// Why lexing failed. Exceeding a LexerConfig limit stops the lexer immediately, even when recovering from errors
+#[derive(Debug, PartialEq)]
+pub enum LexError {
+  Invalid(String),                                                // A lexical error in the source itself
+  IdentifierTooLong { line: u32, max: usize },
+  NumberTooLong { line: u32, max: usize },
+  TooManyTokens { line: u32, max: usize },
+}
+
+impl From<String> for LexError {
+  fn from(msg: String) -> Self {
+    LexError::Invalid(msg)
+  }
+}
+
+impl fmt::Display for LexError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LexError::Invalid(msg) => write!(f, "{}", msg),
+      LexError::IdentifierTooLong { line, max } => write!(f, "Identifier at line {} is longer than the limit of {} bytes", line, max),
+      LexError::NumberTooLong { line, max } => write!(f, "Numeric literal at line {} is longer than the limit of {} bytes", line, max),
+      LexError::TooManyTokens { line, max } => write!(f, "Source has more than {} tokens (limit reached at line {})", max, line),
+    }
+  }
+}
+
+// Source text that isn't part of any token, which lex_with_trivia keeps so the original file can be rebuilt
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+  Whitespace,  // A run of spaces and tabs
+  Newline,     // \n, \r\n or a lone \r
+  Comment,     // A # line comment (without its line terminator) or a #[ ]# block comment
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Trivia<'src> {
+  pub kind: TriviaKind,
+  pub text: &'src str,
+  pub span: Span,
+}
+
+// A token together with its exact source text and the trivia leading up to it
+#[derive(Debug)]
+pub struct LosslessToken<'src> {
+  pub leading: Vec<Trivia<'src>>,
+  pub token: Token<'src>,
+  pub text: &'src str,
+}
+
+// Output of lex_with_trivia: every token plus whatever trivia follows the last one
+#[derive(Debug)]
+pub struct LosslessTokens<'src> {
+  pub tokens: Vec<LosslessToken<'src>>,
+  pub trailing: Vec<Trivia<'src>>,
+}
+
+impl<'src> LosslessTokens<'src> {
+  // Rebuild the source the tokens were lexed from, byte for byte
+  pub fn to_source(&self) -> String {
+    let mut source = String::new();
+    for token in &self.tokens {
+      for trivia in &token.leading {
+        source.push_str(trivia.text);
+      }
+      source.push_str(token.text);
+    }
+    for trivia in &self.trailing {
+      source.push_str(trivia.text);
+    }
+    return source;
+  }
+}
+
+// Characters that end a line: \n and \r, plus Unicode's next line (U+0085), line separator (U+2028) and
+// paragraph separator (U+2029)
+fn is_line_terminator(ch: char) -> bool {
+  return matches!(ch, '\n' | '\r' | '\u{85}' | '\u{2028}' | '\u{2029}');
+}
+
+// Character stream that keeps track of where the next character sits in the source
+struct Cursor<'src, I: Iterator<Item = char>> {
+  chars: I,
+  peeked: Option<Option<char>>,   // What the next character is, once peek() has looked
+  source: Option<&'src str>,  // The whole source, if it's in memory and tokens can borrow from it
+  offset: usize,
+  line: u32,
+  column: u32,
+}
+
+impl<'src, I: Iterator<Item = char>> Cursor<'src, I> {
+  fn new(chars: I, source: Option<&'src str>) -> Self {
+    Cursor { chars, peeked: None, source, offset: 0, line: 1, column: 1 }
+  }
+
+  fn peek(&mut self) -> Option<&char> {
+    let chars = &mut self.chars;
+    return self.peeked.get_or_insert_with(|| chars.next()).as_ref();
+  }
+
+  fn next(&mut self) -> Option<char> {
+    let ch = match self.peeked.take() {
+      Some(peeked) => peeked,
+      None => self.chars.next(),
+    }?;
+    self.offset += ch.len_utf8();
+
+    // Windows (\r\n) and old Mac (lone \r) line endings count as a single line terminator, as do the Unicode
+    // line terminators, so lines and columns come out the same no matter which editor the file was saved in
+    match ch {
+      '\r' if self.peek() == Some(&'\n') => {},
+      _ if is_line_terminator(ch) => {
+        self.line += 1;
+        self.column = 1;
+      },
+      _ => self.column += 1,
+    }
+    return Some(ch);
+  }
+
+  // Zero-width span at the current location, marking the start of a token
+  fn here(&self) -> Span {
+    Span { start: self.offset, end: self.offset, line: self.line, column: self.column }
+  }
+
+  // Span covering everything consumed since `start`
+  fn span_from(&self, start: Span) -> Span {
+    Span { end: self.offset, ..start }
+  }
+
+  // Text between two byte offsets, borrowed from the source when possible. A streamed source is gone by
+  // now, so the characters the lexer collected while scanning (`scanned`) are copied out instead.
+  fn text(&self, start: usize, end: usize, scanned: &str) -> Cow<'src, str> {
+    match self.source {
+      Some(source) => Cow::Borrowed(&source[start..end]),
+      None => Cow::Owned(scanned.to_string()),
+    }
+  }
+}
+
+// A UTF-8 byte-order mark isn't part of the program. It is dropped before lexing, so spans are offsets into
+// the content after it
+fn strip_bom(input: &str) -> &str {
+  return input.strip_prefix('\u{FEFF}').unwrap_or(input);
+}
+
+pub fn lex(input: &str) -> Result<Vec<Token<'_>>, String>  {
+  return lex_with_config(input, &LexerConfig::default()).map_err(|err| err.to_string());
+}
+
+// Lex within the limits set by `config`
+pub fn lex_with_config<'src>(input: &'src str, config: &LexerConfig) -> Result<Vec<Token<'src>>, LexError> {
+  let input = strip_bom(input);
+  let (tokens, mut errors) = lex_chars(input.chars(), Some(input), false, None, config);
+  if !errors.is_empty() {
+    return Err(errors.remove(0));
+  }
+  return Ok(tokens);
+}
+
+// Lex the whole input even if it has errors, skipping past each bad token so every lexical problem in the
+// file can be reported at once. The tokens that did lex are returned alongside the errors.
+pub fn lex_with_recovery(input: &str) -> (Vec<Token<'_>>, Vec<String>) {
+  let (tokens, errors) = lex_with_recovery_and_config(input, &LexerConfig::default());
+  return (tokens, errors.iter().map(LexError::to_string).collect());
+}
+
+// Lex with recovery within the limits set by `config`. Going past a limit is the last error, as lexing stops there
+pub fn lex_with_recovery_and_config<'src>(input: &'src str, config: &LexerConfig) -> (Vec<Token<'src>>, Vec<LexError>) {
+  let input = strip_bom(input);
+  return lex_chars(input.chars(), Some(input), true, None, config);
+}
+
+// Lex without losing anything: whitespace, newlines and comments are kept as trivia attached to the token
+// that follows them (or to the end of the file), so formatters can rebuild the input exactly (apart from a
+// byte-order mark, which isn't kept)
+pub fn lex_with_trivia(input: &str) -> Result<LosslessTokens<'_>, String> {
+  let input = strip_bom(input);
+  let mut trivia_spans: Vec<Span> = Vec::new();
+  let (tokens, mut errors) = lex_chars(input.chars(), Some(input), false, Some(&mut trivia_spans), &LexerConfig::default());
+  if !errors.is_empty() {
+    return Err(errors.remove(0).to_string());
+  }
+
+  // The lexer skips whitespace one character at a time, so neighbouring pieces are merged back together
+  let mut trivia: Vec<Trivia> = Vec::new();
+  for span in trivia_spans {
+    let text = &input[span.start..span.end];
+    let kind = match text.chars().next() {
+      Some(' ' | '\t') => TriviaKind::Whitespace,
+      Some(ch) if is_line_terminator(ch) => TriviaKind::Newline,
+      _ => TriviaKind::Comment,
+    };
+
+    if let Some(last) = trivia.last_mut() && last.span.end == span.start
+      && ((kind == TriviaKind::Whitespace && last.kind == kind) || (last.text == "\r" && text == "\n")) {
+      last.span.end = span.end;
+      last.text = &input[last.span.start..span.end];
+      continue;
+    }
+    trivia.push(Trivia { kind, text, span });
+  }
+
+  // Hand each piece of trivia to the first token after it
+  let mut trivia = trivia.into_iter().peekable();
+  let mut lossless: Vec<LosslessToken> = Vec::new();
+  for token in tokens {
+    let mut leading: Vec<Trivia> = Vec::new();
+    while let Some(next) = trivia.peek() && next.span.end <= token.span.start {
+      leading.push(trivia.next().unwrap());
+    }
+    let text = &input[token.span.start..token.span.end];
+    lossless.push(LosslessToken { leading, token, text });
+  }
+
+  return Ok(LosslessTokens { tokens: lossless, trailing: trivia.collect() });
+}
+
+// Lex a source that is streamed in chunks rather than held in memory as a whole, e.g. a large
+// machine-generated SIL file. Tokens that straddle a chunk boundary are handled by the lexer itself, since
+// it only ever sees a stream of characters.
+pub fn lex_reader<R: BufRead>(reader: R) -> Result<Vec<Token<'static>>, String> {
+  return lex_reader_with_config(reader, &LexerConfig::default()).map_err(|err| err.to_string());
+}
+
+// Lex a streamed source within the limits set by `config`. A limit stops the lexer before it reads any
+// further, so an oversized input is never read in full
+pub fn lex_reader_with_config<R: BufRead>(reader: R, config: &LexerConfig) -> Result<Vec<Token<'static>>, LexError> {
+  return lex_stream(reader, config).collect();
+}
+
+// Lex a streamed source one token at a time, for callers that handle each token as it comes rather than
+// collecting them, so memory stays bounded however long the input is
+pub fn lex_stream<R: BufRead>(reader: R, config: &LexerConfig) -> TokenStream<R> {
+  let lexer = Lexer::new(SourceChars::new(reader), None, config.clone());
+  return TokenStream { lexer, pending: Vec::new(), done: false };
+}
+
+// Iterator over the tokens of a streamed source, from lex_stream. The first error ends it
+pub struct TokenStream<R: BufRead> {
+  lexer: Lexer<'static, SourceChars<R>>,
+  pending: Vec<Token<'static>>,   // Tokens lexed but not handed out yet
+  done: bool,
+}
+
+impl<R: BufRead> TokenStream<R> {
+  // The error to report for `err`. A read or decoding error ends the character stream early, which is
+  // reported in place of whatever the lexer made of the truncated input
+  fn fail(&mut self, err: LexError) -> LexError {
+    self.done = true;
+    self.pending.clear();
+    if let LexError::Invalid(_) = err && let Err(msg) = self.lexer.it.chars.finish() {
+      return LexError::Invalid(msg);
+    }
+    return err;
+  }
+}
+
+impl<R: BufRead> Iterator for TokenStream<R> {
+  type Item = Result<Token<'static>, LexError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while self.pending.is_empty() && !self.done {
+      match self.lexer.step(&mut self.pending) {
+        Some(Ok(_)) => {},
+        Some(Err(err)) => return Some(Err(self.fail(err))),
+        None => {
+          self.done = true;
+          if let Err(msg) = self.lexer.it.chars.finish() {
+            return Some(Err(LexError::Invalid(msg)));
           }
+          return self.lexer.unclosed().map(Err);
+        },
+      }
+    }
+    match self.pending.is_empty() {
+      true => return None,
+      false => return Some(Ok(self.pending.remove(0))),
+    }
+  }
+}
+
+// Lex a SIL file from disk, streaming it through lex_reader so the whole file is never held in memory
+pub fn lex_file(file_path: &str) -> Result<Vec<Token<'static>>, String> {
+  let file = File::open(file_path).map_err(|msg| format!("{}: {}", file_path, msg))?;
+  return lex_reader(BufReader::new(file)).map_err(|msg| format!("{}: {}", file_path, msg));
+}
+
+// Lex a stream of characters. With `recover` set, lexing continues past errors; with `trivia` given, the span of
+// everything skipped between tokens (whitespace, newlines and comments) is recorded into it.
+fn lex_chars<'src, I: Iterator<Item = char>>(chars: I, source: Option<&'src str>, recover: bool,
+  mut trivia: Option<&mut Vec<Span>>, config: &LexerConfig) -> (Vec<Token<'src>>, Vec<LexError>) {
+  let mut tokens: Vec<Token> = Vec::new();
+  let mut errors: Vec<LexError> = Vec::new();
+  let mut lexer = Lexer::new(chars, source, config.clone());
+
+  loop {
+    // When recovering, whatever the failed token consumed (at least its first character) is skipped and
+    // lexing carries on from there
+    let token_count = tokens.len();
+    match lexer.step(&mut tokens) {
+      None => break,
+      Some(Err(err)) => {
+        let limit = !matches!(err, LexError::Invalid(_));
+        errors.push(err);
+        if !recover || limit {
+          return (tokens, errors);
         }
       },
-      '>' => {
-        if let Some(next_ch) = it.peek() {
-          match next_ch {
-            '=' => {
-              tokens.push(LoggedToken::new(Token::GreaterThanEqual(">=".to_string()), line_num, position));
-              it.next();
-              position += 1;
-            },
-            _ => {
-              tokens.push(LoggedToken::new(Token::GreaterThan('>'), line_num, position));
-              continue;
-            }
-          }
+      Some(Ok(span)) if tokens.len() == token_count => {
+        if let Some(trivia) = trivia.as_mut() {
+          trivia.push(span);
         }
+      },
+      Some(Ok(_)) => {},
+    }
+  }
+
+  while let Some(err) = lexer.unclosed() {
+    errors.push(err);
+    if !recover {
+      break;
+    }
+  }
+
+  return (tokens, errors);
+}
+
+// The state the lexer carries from one token to the next
+struct Lexer<'src, I: Iterator<Item = char>> {
+  it: Cursor<'src, I>,
+  config: LexerConfig,
+  delimiters: Vec<(char, Span)>,   // Currently open groupings (opening character, where it was opened)
+  scratch: String,                 // Reusable buffer for the characters of the token being scanned, so multi-character tokens don't each allocate a fresh String
+  lexed: usize,                    // How many tokens have been lexed, for the token limit
+}
+
+impl<'src, I: Iterator<Item = char>> Lexer<'src, I> {
+  fn new(chars: I, source: Option<&'src str>, config: LexerConfig) -> Self {
+    Lexer { it: Cursor::new(chars, source), config, delimiters: Vec::new(), scratch: String::new(), lexed: 0 }
+  }
+
+  // Lex whatever starts at the next character onto `tokens`, returning the span it covered, or None at the
+  // end of the input. Whitespace and comments push nothing
+  fn step(&mut self, tokens: &mut Vec<Token<'src>>) -> Option<Result<Span, LexError>> {
+    let start = self.it.here();
+    let ch = self.it.next()?;
+    let token_count = tokens.len();
+    if let Err(err) = lex_token(&mut self.it, ch, start, tokens, &mut self.delimiters, &mut self.scratch, &self.config) {
+      return Some(Err(err));
+    }
+
+    // The token count is checked once each token is pushed
+    self.lexed += tokens.len() - token_count;
+    if let Some(token) = tokens.last() && tokens.len() > token_count && self.lexed > self.config.max_tokens {
+      return Some(Err(LexError::TooManyTokens { line: token.span.line, max: self.config.max_tokens }));
+    }
+    return Some(Ok(self.it.span_from(start)));
+  }
+
+  // The error for the innermost grouping still open at the end of the input, closing it
+  fn unclosed(&mut self) -> Option<LexError> {
+    let (open, open_span) = self.delimiters.pop()?;
+    return Some(LexError::Invalid(format!("Unclosed '{}' opened at line {}, expected '{}' before end of file", delimiter_name(open), open_span.line, closing_delimiter(open))));
+  }
+}
+
+// Fail as soon as the numeric literal being scanned from `start` is past its limit, rather than scanning the
+// rest of a pathologically long one first
+fn check_number_len<I: Iterator<Item = char>>(it: &Cursor<'_, I>, start: Span, config: &LexerConfig) -> Result<(), LexError> {
+  match it.offset - start.start > config.max_number_len {
+    true => return Err(LexError::NumberTooLong { line: start.line, max: config.max_number_len }),
+    false => return Ok(()),
+  }
+}
+
+// Lex the token starting with `ch`, pushing it onto `tokens` (whitespace and comments push nothing)
+fn lex_token<'src, I: Iterator<Item = char>>(it: &mut Cursor<'src, I>, ch: char, start: Span, tokens: &mut Vec<Token<'src>>,
+  delimiters: &mut Vec<(char, Span)>, scratch: &mut String, config: &LexerConfig) -> Result<(), LexError> {
+  let line_num = start.line;
+
+  match ch {
+    // Handle whitespace and new lines (the cursor keeps count of lines)
+    ' ' | '\t' => {},
+    _ if is_line_terminator(ch) => {},
+
+    // Handle various single-character tokens like parentheses, brackets, and operators
+    // Openers are remembered so unbalanced groupings can be blamed on the opener instead of on EOF
+    '(' => {
+      delimiters.push(('(', start));
+      tokens.push(Token::new(TokenKind::OpenParen, it.span_from(start)));
+    },
+    ')' => {
+      close_delimiter(delimiters, ')', start)?;
+      tokens.push(Token::new(TokenKind::CloseParen, it.span_from(start)));
+    },
+    '{' => {
+      delimiters.push(('{', start));
+      tokens.push(Token::new(TokenKind::OpenCurly, it.span_from(start)));
+    },
+    '}' => {
+      // Closing an interpolated expression picks the surrounding string back up
+      if let Some(('$', _)) = delimiters.last() {
+        delimiters.pop();
+        return lex_string(it, start, true, tokens, delimiters, scratch);
       }
-      '<' => {
-        if let Some(next_ch) = it.peek() {
-          match next_ch {
-            '=' => { 
-              tokens.push(LoggedToken::new(Token::LessThanEqual("<=".to_string()), line_num, position));
-              it.next();
-              position += 1;
-            },
-            _ => {
-              tokens.push(LoggedToken::new(Token::LessThan('<'), line_num, position));
-              continue;
-            }
+      close_delimiter(delimiters, '}', start)?;
+      tokens.push(Token::new(TokenKind::CloseCurly, it.span_from(start)));
+    },
+    '[' => {
+      delimiters.push(('[', start));
+      tokens.push(Token::new(TokenKind::OpenBracket, it.span_from(start)));
+    },
+    ']' => {
+      close_delimiter(delimiters, ']', start)?;
+      tokens.push(Token::new(TokenKind::CloseBracket, it.span_from(start)));
+    },
+
+    // Handle single-character operators and punctuation
+    '+' => {
+      match it.peek() {
+        Some('+') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::PlusPlus, it.span_from(start)));
+        },
+        _ => tokens.push(Token::new(TokenKind::Plus, it.span_from(start))),
+      }
+    },
+    '-' => {
+      match it.peek() {
+        Some('>') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::Arrow, it.span_from(start)));
+        },
+        Some('-') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::MinusMinus, it.span_from(start)));
+        },
+        _ => tokens.push(Token::new(TokenKind::Minus, it.span_from(start))),
+      }
+    },
+    '*' => {
+      match it.peek() {
+        Some('*') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::Power, it.span_from(start)));
+        },
+        _ => tokens.push(Token::new(TokenKind::Times, it.span_from(start))),
+      }
+    },
+    '/' => tokens.push(Token::new(TokenKind::Divide, it.span_from(start))),
+    '%' => tokens.push(Token::new(TokenKind::Modulo, it.span_from(start))),
+    ',' => tokens.push(Token::new(TokenKind::Comma, it.span_from(start))),
+    ';' => tokens.push(Token::new(TokenKind::Semicolon, it.span_from(start))),
+    ':' => tokens.push(Token::new(TokenKind::Colon, it.span_from(start))),
+    '?' => tokens.push(Token::new(TokenKind::Question, it.span_from(start))),
+    '.' => tokens.push(Token::new(TokenKind::Dot, it.span_from(start))),
+
+    // Handle two-character operators. A lone operator is emitted whatever follows it, including the end of the input
+    '=' => {
+      match it.peek() {
+        Some('=') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::EqualEqual, it.span_from(start)));
+        },
+        _ => tokens.push(Token::new(TokenKind::Equal, it.span_from(start))),
+      }
+    },
+    '>' => {
+      match it.peek() {
+        Some('=') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::GreaterThanEqual, it.span_from(start)));
+        },
+        Some('>') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::ShiftRight, it.span_from(start)));
+        },
+        _ => tokens.push(Token::new(TokenKind::GreaterThan, it.span_from(start))),
+      }
+    },
+    '<' => {
+      match it.peek() {
+        Some('=') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::LessThanEqual, it.span_from(start)));
+        },
+        Some('<') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::ShiftLeft, it.span_from(start)));
+        },
+        _ => tokens.push(Token::new(TokenKind::LessThan, it.span_from(start))),
+      }
+    },
+
+    '!' => {
+      match it.peek() {
+        Some('=') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::NotEqual, it.span_from(start)));
+        },
+        _ => tokens.push(Token::new(TokenKind::Not, it.span_from(start))),
+      }
+    },
+
+    // Handle logical operators (doubled) and bitwise operators (single)
+    '&' => {
+      match it.peek() {
+        Some('&') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::And, it.span_from(start)));
+        },
+        _ => tokens.push(Token::new(TokenKind::BitAnd, it.span_from(start))),
+      }
+    },
+    '|' => {
+      match it.peek() {
+        Some('|') => {
+          it.next();
+          tokens.push(Token::new(TokenKind::Or, it.span_from(start)));
+        },
+        _ => tokens.push(Token::new(TokenKind::BitOr, it.span_from(start))),
+      }
+    },
+    '^' => tokens.push(Token::new(TokenKind::BitXor, it.span_from(start))),
+    '~' => tokens.push(Token::new(TokenKind::BitNot, it.span_from(start))),
+
+    // Skip a #! shebang line at the very start of the file, so SIL scripts can be made executable. Only the
+    // line's text is consumed; its terminator still ends line 1 like any other
+    '#' if start.start == 0 && it.peek() == Some(&'!') => {
+      while let Some(next_ch) = it.peek() {
+        if is_line_terminator(*next_ch) {
+          break;
+        }
+        it.next();
+      }
+    },
+
+    // Ignore block comments #[ ... ]#, which may span lines and nest
+    '#' if it.peek() == Some(&'[') => {
+      let mut depth = 0;
+      let mut prev = '#';
+      loop {
+        let next_ch = match it.next() {
+          Some(next_ch) => next_ch,
+          None => return Err(format!("Unterminated block comment starting at line {}", line_num).into()),
+        };
+
+        match (prev, next_ch) {
+          ('#', '[') => depth += 1,
+          (']', '#') => depth -= 1,
+          _ => {},
+        }
+        if depth == 0 {
+          break;
+        }
+
+        // A delimiter character can't be the start of two delimiters at once (e.g. the `#` in `]#[`)
+        prev = if matches!((prev, next_ch), ('#', '[') | (']', '#')) { ' ' } else { next_ch };
+      }
+    },
+
+    // Keep ## doc comments as tokens so documentation tools and formatters can see them. The text runs to the
+    // end of the line, minus the single space conventionally written after the ##
+    '#' if it.peek() == Some(&'#') => {
+      it.next();
+      if it.peek() == Some(&' ') {
+        it.next();
+      }
+
+      let text_start = it.offset;
+      scratch.clear();
+      while let Some(next_ch) = it.peek() {
+        if is_line_terminator(*next_ch) {
+          break;
+        }
+        scratch.push(it.next().unwrap());
+      }
+      let text = it.text(text_start, it.offset, scratch);
+      tokens.push(Token::new(TokenKind::DocComment(text), it.span_from(start)));
+    },
+
+    // Ignore comments (skip until the end of the line)
+    // The line terminator itself is left for the whitespace arm above
+    '#' => {
+      while let Some(next_ch) = it.peek() {
+        if is_line_terminator(*next_ch) {
+          break;
+        }
+        it.next();
+      }
+    },
+
+    // Handle keywords (def, if, else, while, etc.) and identifiers
+    // A leading underscore marks a name as deliberately unused, like `_index`
+    'a'..='z' | 'A'..='Z' | '_' => {
+      scratch.clear();
+      scratch.push(ch);
+      while let Some(next_ch) = it.peek() {
+        if next_ch.is_alphanumeric() || *next_ch == '_' {
+          scratch.push(it.next().unwrap());
+          if it.offset - start.start > config.max_identifier_len {
+            return Err(LexError::IdentifierTooLong { line: line_num, max: config.max_identifier_len });
           }
+        } else {
+          break;
         }
-      },
+      }
+      let identifier = it.text(start.start, it.offset, scratch);
 
-      // Ignore comments (skip until the end of the line)
-      '#' => {
-        while let Some(ch) = it.next() {
-          position += 1;
-          if ch == '\n' {
-            line_num += 1;
-            break;
+      // Check if it's a keyword
+      match Keyword::from_word(&identifier) {
+        Some(keyword) => tokens.push(Token::new(TokenKind::Keyword(keyword), it.span_from(start))),
+        None => tokens.push(Token::new(TokenKind::Identifier(identifier), it.span_from(start))),
+      }
+    },
+
+    // Handle prefixed integer literals (0x hexadecimal, 0o octal and 0b binary)
+    '0' if matches!(it.peek(), Some('x' | 'o' | 'b')) => {
+      let prefix = it.next().unwrap();
+      let radix = match prefix {
+        'x' => 16,
+        'o' => 8,
+        _ => 2,
+      };
+
+      // Take every alphanumeric character so a stray digit like the 2 in `0b2` is an error, not a new token
+      // Underscores are only visual separators and are dropped from the value
+      let digits = scratch;
+      digits.clear();
+      while let Some(next_ch) = it.peek() {
+        if next_ch.is_ascii_alphanumeric() || *next_ch == '_' {
+          let digit = it.next().unwrap();
+          check_number_len(it, start, config)?;
+          if digit != '_' {
+            digits.push(digit);
           }
+        } else {
+          break;
         }
-      },
+      }
 
-      // Handle keywords (def, if, else, while, etc.)
-      'a'..='z' | 'A'..='Z' => {
-        let mut identifier = ch.to_string();
-        while let Some(next_ch) = it.peek() {
-          if next_ch.is_alphanumeric() || *next_ch == '_' {
-            identifier.push(it.next().unwrap());
-            position += 1;
-          } else {
-            break;
+      if digits.is_empty() {
+        return Err(format!("Missing digits after 0{} at line {}", prefix, line_num).into());
+      }
+      match i64::from_str_radix(digits, radix) {
+        Ok(num) => tokens.push(Token::new(TokenKind::Integer(num), it.span_from(start))),
+        Err(err) => match err.kind() {
+          IntErrorKind::PosOverflow => return Err(format!("Integer literal 0{}{} is too large at line {}", prefix, digits, line_num).into()),
+          _ => return Err(format!("Invalid number 0{}{} at line {}", prefix, digits, line_num).into()),
+        }
+      }
+    },
+
+    // Handle numbers (floating point or integers)
+    '0'..='9' => {
+      let num_str = scratch;
+      num_str.clear();
+      num_str.push(ch);
+      while let Some(next_ch) = it.peek() {
+        if next_ch.is_ascii_digit() || *next_ch == '.' || *next_ch == '_' {
+          let digit = it.next().unwrap();
+          check_number_len(it, start, config)?;
+          if digit != '_' {
+            num_str.push(digit);
           }
+        } else {
+          break;
         }
+      }
 
-        // Check if it's a keyword
-        match identifier.as_str() {
-          "def" => tokens.push(LoggedToken::new(Token::Def(identifier), line_num, position)),
-          "let" => tokens.push(LoggedToken::new(Token::Let(identifier), line_num, position)),
-          "if" => tokens.push(LoggedToken::new(Token::If(identifier), line_num, position)),
-          "else" => tokens.push(LoggedToken::new(Token::Else(identifier), line_num, position)),
-          "while" => tokens.push(LoggedToken::new(Token::While(identifier), line_num, position)),
-          "return" => tokens.push(LoggedToken::new(Token::Return(identifier), line_num, position)),
-          "break" => tokens.push(LoggedToken::new(Token::Break(identifier), line_num, position)),
-          "continue" => tokens.push(LoggedToken::new(Token::Continue(identifier), line_num, position)),
-          "true" => tokens.push(LoggedToken::new(Token::True(identifier), line_num, position)),
-          "false" => tokens.push(LoggedToken::new(Token::False(identifier), line_num, position)),
-          _ => tokens.push(LoggedToken::new(Token::Identifier(identifier), line_num, position)),
+      // Scientific notation (1.5e-3, 2E8), which always makes the literal a float
+      let mut has_exponent = false;
+      if let Some('e' | 'E') = it.peek() {
+        has_exponent = true;
+        num_str.push(it.next().unwrap());
+        if let Some('+' | '-') = it.peek() {
+          num_str.push(it.next().unwrap());
         }
-      },
 
-      // Handle numbers (floating point or integers)
-      '0'..='9' => {
-        let mut num_str = ch.to_string();
+        let mut exponent_digits = 0;
         while let Some(next_ch) = it.peek() {
-          if next_ch.is_digit(10) || *next_ch == '.' {
-            num_str.push(it.next().unwrap());
-            position += 1;
+          if next_ch.is_ascii_digit() || *next_ch == '_' {
+            let digit = it.next().unwrap();
+            check_number_len(it, start, config)?;
+            if digit != '_' {
+              num_str.push(digit);
+              exponent_digits += 1;
+            }
           } else {
             break;
           }
         }
+        if exponent_digits == 0 {
+          return Err(format!("Missing exponent digits in number {} at line {}", num_str, line_num).into());
+        }
+      }
 
-        // Convert to number
+      // Convert to number, keeping integers and floats apart for the type checker
+      if num_str.contains('.') || has_exponent {
         match num_str.parse::<f64>() {
-          Ok(num) => tokens.push(LoggedToken::new(Token::Number(num), line_num, position)),
-          Err(_) => return Err(format!("Invalid number {} at line {}", num_str, line_num)),
+          Ok(num) => tokens.push(Token::new(TokenKind::Float(num), it.span_from(start))),
+          Err(_) => return Err(format!("Invalid number {} at line {}", num_str, line_num).into()),
+        }
+      } else {
+        match num_str.parse::<i64>() {
+          Ok(num) => tokens.push(Token::new(TokenKind::Integer(num), it.span_from(start))),
+          Err(_) => return Err(format!("Integer literal {} is too large at line {}", num_str, line_num).into()),
+        }
+      }
+    },
+
+    // Handle string literals, decoding escape sequences into the literal's value
+    '"' => return lex_string(it, start, false, tokens, delimiters, scratch),
+
+    // Handle character literals, which hold exactly one (possibly escaped) character
+    '\'' => {
+      let mut value: Option<char> = None;
+      let mut count = 0;
+      loop {
+        let next_ch = match it.next() {
+          Some(next_ch) if !is_line_terminator(next_ch) => next_ch,
+          _ => return Err(format!("Unterminated character literal at line {}", line_num).into()),
+        };
+
+        let decoded = match next_ch {
+          '\'' => break,
+          '\\' => {
+            let escape = match it.next() {
+              Some(escape) => escape,
+              None => return Err(format!("Unterminated character literal at line {}", line_num).into()),
+            };
+            match escape {
+              'n' => '\n',
+              't' => '\t',
+              '\'' => '\'',
+              '\\' => '\\',
+              _ => return Err(format!("Invalid escape sequence \\{} in character literal at line {}", escape, line_num).into()),
+            }
+          },
+          _ => next_ch,
+        };
+        value.get_or_insert(decoded);
+        count += 1;
+      }
+
+      match (value, count) {
+        (Some(value), 1) => tokens.push(Token::new(TokenKind::CharLiteral(value), it.span_from(start))),
+        (None, _) => return Err(format!("Empty character literal at line {}", line_num).into()),
+        _ => return Err(format!("Character literal at line {} holds more than one character, use \"...\" for strings", line_num).into()),
+      }
+    },
+    _ => return Err(format!("Unrecognized character {} at line {}", ch, line_num).into())
+  }
+
+  return Ok(());
+}
+
+// Lex the text of a string literal up to its closing quote or the next `${`. `resumed` is set when picking a string
+// back up after the `}` of an interpolated expression, rather than starting at its opening quote
+fn lex_string<'src, I: Iterator<Item = char>>(it: &mut Cursor<'src, I>, start: Span, resumed: bool, tokens: &mut Vec<Token<'src>>,
+  delimiters: &mut Vec<(char, Span)>, value: &mut String) -> Result<(), LexError> {
+  let line_num = start.line;
+  value.clear();
+  let mut escaped = false;
+  let mut invalid_escape: Option<String> = None;
+  let interpolates = loop {
+    let next_ch = match it.next() {
+      Some(next_ch) => next_ch,
+      None => return Err(format!("Unterminated string starting at line {}", line_num).into()),
+    };
+
+    match next_ch {
+      '"' => break false,
+      '$' if it.peek() == Some(&'{') => {
+        it.next();
+        break true;
+      },
+      '\\' => {
+        let escape = match it.next() {
+          Some(escape) => escape,
+          None => return Err(format!("Unterminated string starting at line {}", line_num).into()),
+        };
+
+        escaped = true;
+        match escape {
+          'n' => value.push('\n'),
+          't' => value.push('\t'),
+          '"' => value.push('"'),
+          '\\' => value.push('\\'),
+          '$' => value.push('$'),
+          // Keep going to the closing quote so the rest of the string isn't lexed as code
+          _ => {
+            if invalid_escape.is_none() {
+              invalid_escape = Some(format!("Invalid escape sequence \\{} in string at line {}", escape, it.line));
+            }
+          },
         }
       },
-      _ => return Err(format!("Unrecognized character {} at line {}", ch, line_num))
+      _ => value.push(next_ch),
     }
+  };
+  if let Some(msg) = invalid_escape {
+    return Err(msg.into());
+  }
 
-    position += 1;
+  // Without escapes the value is exactly the text between the quote (or brace) and the terminator
+  let terminator_len = if interpolates { 2 } else { 1 };
+  let literal = match escaped {
+    false => it.text(start.start + 1, it.offset - terminator_len, value),
+    true => Cow::Owned(value.clone()),
+  };
+  let kind = match (resumed, interpolates) {
+    (false, false) => TokenKind::StringLiteral(literal),
+    (false, true) => TokenKind::StringStart(literal),
+    (true, true) => TokenKind::StringMiddle(literal),
+    (true, false) => TokenKind::StringEnd(literal),
+  };
+  if interpolates {
+    delimiters.push(('$', it.span_from(start)));
   }
+  tokens.push(Token::new(kind, it.span_from(start)));
+  return Ok(());
+}
 
-  return Ok(tokens);
+fn closing_delimiter(open: char) -> char {
+  match open {
+    '(' => ')',
+    '{' | '$' => '}',
+    _ => ']',
+  }
+}
+
+// How an opener is shown in error messages; interpolated expressions are opened by the two characters `${`
+fn delimiter_name(open: char) -> String {
+  match open {
+    '$' => "${".to_string(),
+    _ => open.to_string(),
+  }
+}
+
+// Pop the innermost open grouping, checking that `close` is the character that ends it
+fn close_delimiter(delimiters: &mut Vec<(char, Span)>, close: char, span: Span) -> Result<(), String> {
+  match delimiters.pop() {
+    None => return Err(format!("Unmatched '{}' at line {}", close, span.line)),
+    Some((open, open_span)) => {
+      if closing_delimiter(open) != close {
+        return Err(format!("Mismatched '{}' at line {}, expected '{}' to close '{}' opened at line {}",
+          close, span.line, closing_delimiter(open), delimiter_name(open), open_span.line));
+      }
+      return Ok(());
+    }
+  }
+}
+
+// Find the first token in each of two token streams where they disagree, ignoring where the tokens sit in
+// the file. Whitespace and `#` comments never reach the token stream and doc comments are skipped, so only
+// meaningful changes are reported. The indexes are into each stream, and one equal to a stream's length
+// means it ended there, before the other did
+pub fn first_divergence(a: &[Token], b: &[Token]) -> Option<(usize, usize)> {
+  let is_code = |(_, token): &(usize, &Token)| !matches!(token.kind, TokenKind::DocComment(_));
+  let mut a_code = a.iter().enumerate().filter(is_code);
+  let mut b_code = b.iter().enumerate().filter(is_code);
+  loop {
+    match (a_code.next(), b_code.next()) {
+      (Some((_, a_tok)), Some((_, b_tok))) if a_tok.kind == b_tok.kind => continue,
+      (Some((i, _)), Some((j, _))) => return Some((i, j)),
+      (Some((i, _)), None) => return Some((i, b.len())),
+      (None, Some((j, _))) => return Some((a.len(), j)),
+      (None, None) => return None,
+    }
+  }
+}
+
+// Warn about identifiers that only differ from a keyword by case, like `Def` or `IF`. They are perfectly
+// good identifiers, but users coming from case-insensitive languages usually meant the keyword
+pub fn keyword_case_warnings(tokens: &[Token]) -> Vec<String> {
+  let mut warnings: Vec<String> = Vec::new();
+  for token in tokens {
+    if let TokenKind::Identifier(name) = &token.kind && let Some(keyword) = Keyword::from_word(&name.to_lowercase()) {
+      warnings.push(format!("'{}' at line {}, column {} is an identifier, not a keyword, did you mean '{}'?",
+        name, token.span.line, token.span.column, keyword.as_str()));
+    }
+  }
+  return warnings;
 }
 
 #[cfg(test)]
@@ -202,37 +1142,120 @@ mod tests {
     let result = lex(&source);
     assert_eq!(result.is_ok(), true);
     
-    let tokens: Vec<LoggedToken> = result.unwrap();
+    let tokens: Vec<Token> = result.unwrap();
     assert_eq!(tokens.len(), 7);
 
-    assert_eq!(tokens[0].token, Token::Let("let".to_string()));
-    assert_eq!(tokens[1].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[2].token, Token::Equal('='));
-    assert_eq!(tokens[3].token, Token::Number(5.237));
-    assert_eq!(tokens[4].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[5].token, Token::Equal('='));
-    assert_eq!(tokens[6].token, Token::Number(6.0));
+    assert_eq!(tokens[0].kind, TokenKind::Keyword(Keyword::Let));
+    assert_eq!(tokens[1].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[2].kind, TokenKind::Equal);
+    assert_eq!(tokens[3].kind, TokenKind::Float(5.237));
+    assert_eq!(tokens[4].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[5].kind, TokenKind::Equal);
+    assert_eq!(tokens[6].kind, TokenKind::Integer(6));
   }
   
+  #[test]
+  fn lex_leading_underscore_identifiers() {
+    let source: String = "let _unused = _ + a_b".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens[1].kind, TokenKind::Identifier("_unused".into()));
+    assert_eq!(tokens[3].kind, TokenKind::Identifier("_".into()));
+    assert_eq!(tokens[5].kind, TokenKind::Identifier("a_b".into()));
+  }
+
   #[test]
   fn lex_binary_operations() {
     let source: String = "4 + 5 * 6 \n 7.3 / 3.46 - 5.2".to_string();
     let result = lex(&source);
     assert_eq!(result.is_ok(), true);
     
-    let tokens: Vec<LoggedToken> = result.unwrap();
+    let tokens: Vec<Token> = result.unwrap();
     assert_eq!(tokens.len(), 10);
 
-    assert_eq!(tokens[0].token, Token::Number(4.0));
-    assert_eq!(tokens[1].token, Token::Plus('+'));
-    assert_eq!(tokens[2].token, Token::Number(5.0));
-    assert_eq!(tokens[3].token, Token::Times('*'));
-    assert_eq!(tokens[4].token, Token::Number(6.0));
-    assert_eq!(tokens[5].token, Token::Number(7.3));
-    assert_eq!(tokens[6].token, Token::Divide('/'));
-    assert_eq!(tokens[7].token, Token::Number(3.46));
-    assert_eq!(tokens[8].token, Token::Minus('-'));
-    assert_eq!(tokens[9].token, Token::Number(5.2));
+    assert_eq!(tokens[0].kind, TokenKind::Integer(4));
+    assert_eq!(tokens[1].kind, TokenKind::Plus);
+    assert_eq!(tokens[2].kind, TokenKind::Integer(5));
+    assert_eq!(tokens[3].kind, TokenKind::Times);
+    assert_eq!(tokens[4].kind, TokenKind::Integer(6));
+    assert_eq!(tokens[5].kind, TokenKind::Float(7.3));
+    assert_eq!(tokens[6].kind, TokenKind::Divide);
+    assert_eq!(tokens[7].kind, TokenKind::Float(3.46));
+    assert_eq!(tokens[8].kind, TokenKind::Minus);
+    assert_eq!(tokens[9].kind, TokenKind::Float(5.2));
+  }
+
+  #[test]
+  fn lex_power() {
+    let source: String = "2 ** 3 ** 2 * 4 *** x".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let kinds: Vec<TokenKind> = result.unwrap().into_iter().map(|token| token.kind).collect();
+    assert_eq!(kinds, vec![
+      TokenKind::Integer(2), TokenKind::Power, TokenKind::Integer(3), TokenKind::Power, TokenKind::Integer(2),
+      TokenKind::Times, TokenKind::Integer(4), TokenKind::Power, TokenKind::Times, TokenKind::Identifier("x".into()),
+    ]);
+  }
+
+  #[test]
+  fn lex_increment_and_decrement() {
+    let source: String = "x++ --y a+++b c - -d".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let kinds: Vec<TokenKind> = result.unwrap().into_iter().map(|token| token.kind).collect();
+    assert_eq!(kinds, vec![
+      TokenKind::Identifier("x".into()), TokenKind::PlusPlus, TokenKind::MinusMinus, TokenKind::Identifier("y".into()),
+      TokenKind::Identifier("a".into()), TokenKind::PlusPlus, TokenKind::Plus, TokenKind::Identifier("b".into()),
+      TokenKind::Identifier("c".into()), TokenKind::Minus, TokenKind::Minus, TokenKind::Identifier("d".into()),
+    ]);
+  }
+
+  #[test]
+  fn lex_modulo() {
+    let source: String = "x % 2 == 0".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(tokens[0].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[1].kind, TokenKind::Modulo);
+    assert_eq!(tokens[2].kind, TokenKind::Integer(2));
+  }
+
+  #[test]
+  fn lex_arrow_colon_and_dot() {
+    let mut source: String = "def add(a: int, b: int) -> int { return point.x - -1 }".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let kinds: Vec<TokenKind> = result.unwrap().into_iter().map(|token| token.kind).collect();
+    assert_eq!(kinds[4], TokenKind::Colon);
+    assert_eq!(kinds[11], TokenKind::Arrow);
+    assert_eq!(kinds[16], TokenKind::Dot);
+    assert_eq!(kinds[18], TokenKind::Minus);
+    assert_eq!(kinds[19], TokenKind::Minus);
+
+    // `->` is only an arrow when the two characters touch
+    source = "a - > b".to_string();
+    let kinds: Vec<TokenKind> = lex(&source).unwrap().into_iter().map(|token| token.kind).collect();
+    assert_eq!(kinds[1], TokenKind::Minus);
+    assert_eq!(kinds[2], TokenKind::GreaterThan);
+
+    // A dot inside a number is still a decimal point
+    source = "x.y 1.5".to_string();
+    let kinds: Vec<TokenKind> = lex(&source).unwrap().into_iter().map(|token| token.kind).collect();
+    assert_eq!(kinds, vec![TokenKind::Identifier("x".into()), TokenKind::Dot, TokenKind::Identifier("y".into()), TokenKind::Float(1.5)]);
+  }
+
+  #[test]
+  fn lex_conditional_operator() {
+    let source: String = "let max = a > b ? a : b".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let kinds: Vec<TokenKind> = result.unwrap().into_iter().map(|token| token.kind).collect();
+    assert_eq!(kinds.len(), 10);
+    assert_eq!(kinds[6], TokenKind::Question);
+    assert_eq!(kinds[8], TokenKind::Colon);
   }
 
   #[test]
@@ -241,87 +1264,284 @@ mod tests {
     let result = lex(&source);
     assert_eq!(result.is_ok(), true);
     
-    let tokens: Vec<LoggedToken> = result.unwrap();
+    let tokens: Vec<Token> = result.unwrap();
     assert_eq!(tokens.len(), 32);
 
-    assert_eq!(tokens[0].token, Token::Number(8.0));
-    assert_eq!(tokens[1].token, Token::EqualEqual("==".to_string()));
-    assert_eq!(tokens[2].token, Token::Number(3.0));
-    assert_eq!(tokens[3].token, Token::Plus('+'));
-    assert_eq!(tokens[4].token, Token::Number(4.0));
-    assert_eq!(tokens[5].token, Token::Times('*'));
-    assert_eq!(tokens[6].token, Token::Number(5.0));
-
-    assert_eq!(tokens[7].token, Token::Number(8.0));
-    assert_eq!(tokens[8].token, Token::GreaterThanEqual(">=".to_string()));
-    assert_eq!(tokens[9].token, Token::Number(3.0));
-    assert_eq!(tokens[10].token, Token::Plus('+'));
-    assert_eq!(tokens[11].token, Token::Number(4.0));
-    assert_eq!(tokens[12].token, Token::Times('*'));
-    assert_eq!(tokens[13].token, Token::Number(5.0));
-
-    assert_eq!(tokens[14].token, Token::Number(8.0));
-    assert_eq!(tokens[15].token, Token::LessThanEqual("<=".to_string()));
-    assert_eq!(tokens[16].token, Token::Number(3.0));
-    assert_eq!(tokens[17].token, Token::Plus('+'));
-    assert_eq!(tokens[18].token, Token::Number(4.0));
-    assert_eq!(tokens[19].token, Token::Times('*'));
-    assert_eq!(tokens[20].token, Token::Number(5.0));
-    assert_eq!(tokens[21].token, Token::EqualEqual("==".to_string()));
-    assert_eq!(tokens[22].token, Token::False("false".to_string()));
-
-    assert_eq!(tokens[23].token, Token::Number(8.0));
-    assert_eq!(tokens[24].token, Token::GreaterThanEqual(">=".to_string()));
-    assert_eq!(tokens[25].token, Token::Number(3.0));
-    assert_eq!(tokens[26].token, Token::Plus('+'));
-    assert_eq!(tokens[27].token, Token::Number(4.0));
-    assert_eq!(tokens[28].token, Token::Times('*'));
-    assert_eq!(tokens[29].token, Token::Number(5.0));
-    assert_eq!(tokens[30].token, Token::EqualEqual("==".to_string()));
-    assert_eq!(tokens[31].token, Token::True("true".to_string()));
+    assert_eq!(tokens[0].kind, TokenKind::Integer(8));
+    assert_eq!(tokens[1].kind, TokenKind::EqualEqual);
+    assert_eq!(tokens[2].kind, TokenKind::Integer(3));
+    assert_eq!(tokens[3].kind, TokenKind::Plus);
+    assert_eq!(tokens[4].kind, TokenKind::Integer(4));
+    assert_eq!(tokens[5].kind, TokenKind::Times);
+    assert_eq!(tokens[6].kind, TokenKind::Integer(5));
+
+    assert_eq!(tokens[7].kind, TokenKind::Integer(8));
+    assert_eq!(tokens[8].kind, TokenKind::GreaterThanEqual);
+    assert_eq!(tokens[9].kind, TokenKind::Integer(3));
+    assert_eq!(tokens[10].kind, TokenKind::Plus);
+    assert_eq!(tokens[11].kind, TokenKind::Integer(4));
+    assert_eq!(tokens[12].kind, TokenKind::Times);
+    assert_eq!(tokens[13].kind, TokenKind::Integer(5));
+
+    assert_eq!(tokens[14].kind, TokenKind::Integer(8));
+    assert_eq!(tokens[15].kind, TokenKind::LessThanEqual);
+    assert_eq!(tokens[16].kind, TokenKind::Integer(3));
+    assert_eq!(tokens[17].kind, TokenKind::Plus);
+    assert_eq!(tokens[18].kind, TokenKind::Integer(4));
+    assert_eq!(tokens[19].kind, TokenKind::Times);
+    assert_eq!(tokens[20].kind, TokenKind::Integer(5));
+    assert_eq!(tokens[21].kind, TokenKind::EqualEqual);
+    assert_eq!(tokens[22].kind, TokenKind::Keyword(Keyword::False));
+
+    assert_eq!(tokens[23].kind, TokenKind::Integer(8));
+    assert_eq!(tokens[24].kind, TokenKind::GreaterThanEqual);
+    assert_eq!(tokens[25].kind, TokenKind::Integer(3));
+    assert_eq!(tokens[26].kind, TokenKind::Plus);
+    assert_eq!(tokens[27].kind, TokenKind::Integer(4));
+    assert_eq!(tokens[28].kind, TokenKind::Times);
+    assert_eq!(tokens[29].kind, TokenKind::Integer(5));
+    assert_eq!(tokens[30].kind, TokenKind::EqualEqual);
+    assert_eq!(tokens[31].kind, TokenKind::Keyword(Keyword::True));
+  }
+
+  #[test]
+  fn lex_logical_operations() {
+    let source: String = "!(x < 3) && y != 4 || !done".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 13);
+    assert_eq!(tokens[0].kind, TokenKind::Not);
+    assert_eq!(tokens[1].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[2].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[3].kind, TokenKind::LessThan);
+    assert_eq!(tokens[4].kind, TokenKind::Integer(3));
+    assert_eq!(tokens[5].kind, TokenKind::CloseParen);
+    assert_eq!(tokens[6].kind, TokenKind::And);
+    assert_eq!(tokens[7].kind, TokenKind::Identifier("y".into()));
+    assert_eq!(tokens[8].kind, TokenKind::NotEqual);
+    assert_eq!(tokens[9].kind, TokenKind::Integer(4));
+    assert_eq!(tokens[10].kind, TokenKind::Or);
+    assert_eq!(tokens[11].kind, TokenKind::Not);
+    assert_eq!(tokens[12].kind, TokenKind::Identifier("done".into()));
+  }
+
+  #[test]
+  fn lex_operators_at_end_of_input() {
+    let cases = [
+      ("x =", TokenKind::Equal),
+      ("x <", TokenKind::LessThan),
+      ("x >", TokenKind::GreaterThan),
+      ("x ==", TokenKind::EqualEqual),
+      ("x <=", TokenKind::LessThanEqual),
+      ("x >>", TokenKind::ShiftRight),
+      ("x -", TokenKind::Minus),
+      ("x &", TokenKind::BitAnd),
+    ];
+    for (source, last) in cases {
+      let tokens: Vec<Token> = lex(source).unwrap();
+      assert_eq!(tokens.len(), 2);
+      assert_eq!(tokens[1].kind, last);
+      assert_eq!(tokens[1].span.end, source.len());
+    }
+
+    let source: String = "=".to_string();
+    let result = lex(&source);
+    assert_eq!(result.unwrap()[0].kind, TokenKind::Equal);
+  }
+
+  #[test]
+  fn lex_trailing_not() {
+    let source: String = "!".to_string();
+    let result = lex(&source);
+    assert_eq!(result.unwrap()[0].kind, TokenKind::Not);
+  }
+
+  #[test]
+  fn lex_bitwise_operations() {
+    let source: String = "a & b | c ^ ~d << 2 >> 1 && e || f".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 16);
+    assert_eq!(tokens[1].kind, TokenKind::BitAnd);
+    assert_eq!(tokens[3].kind, TokenKind::BitOr);
+    assert_eq!(tokens[5].kind, TokenKind::BitXor);
+    assert_eq!(tokens[6].kind, TokenKind::BitNot);
+    assert_eq!(tokens[8].kind, TokenKind::ShiftLeft);
+    assert_eq!(tokens[10].kind, TokenKind::ShiftRight);
+    assert_eq!(tokens[12].kind, TokenKind::And);
+    assert_eq!(tokens[14].kind, TokenKind::Or);
+  }
+
+  #[test]
+  fn lex_shifts_next_to_comparisons() {
+    let source: String = "a <<b <= c < d >>e >= f > g".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 13);
+    assert_eq!(tokens[1].kind, TokenKind::ShiftLeft);
+    assert_eq!(tokens[3].kind, TokenKind::LessThanEqual);
+    assert_eq!(tokens[5].kind, TokenKind::LessThan);
+    assert_eq!(tokens[7].kind, TokenKind::ShiftRight);
+    assert_eq!(tokens[9].kind, TokenKind::GreaterThanEqual);
+    assert_eq!(tokens[11].kind, TokenKind::GreaterThan);
+  }
+
+  // Smaller, not syntactically correct tests
+  #[test]
+  fn display_tokens_as_source() {
+    let source: String = r#"def f(x) { return x ** 2 >= 1.0 && "a\t\"b\"" != 'c' }"#.to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    let shown: Vec<String> = tokens.iter().map(|token| token.to_string()).collect();
+    assert_eq!(shown.join(" "), r#"def f ( x ) { return x ** 2 >= 1.0 && "a\t\"b\"" != 'c' }"#);
+
+    // Displayed tokens lex back to the same tokens
+    let source: String = r#"'\'' '\\' "${x}, \${y}" 1e3 -> ## doc"#.to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    let shown: String = tokens.iter().map(|token| token.to_string()).collect::<Vec<String>>().join(" ");
+    assert_eq!(first_divergence(&tokens, &lex(&shown).unwrap()), None);
+    assert_eq!(format!("expected ')' but found '{}'", TokenKind::Plus), "expected ')' but found '+'");
+  }
+
+  #[test]
+  fn lex_every_keyword() {
+    for (word, keyword) in KEYWORDS {
+      let tokens: Vec<Token> = lex(word).unwrap();
+      assert_eq!(tokens[0].kind, TokenKind::Keyword(keyword));
+      assert_eq!(keyword.as_str(), word);
+    }
+
+    // Keywords are case-sensitive and must be whole words
+    let source: String = "Def define if_ else2".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.iter().all(|token| matches!(token.kind, TokenKind::Identifier(_))), true);
+  }
+
+  #[test]
+  fn lex_unrecognized_char() {
+    let source: String = "5 + 5@".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+  }
+  
+  #[test]
+  fn lex_comments() {
+    // Comment at end of file
+    let mut source: String = "+ \n # This is a comment".to_string();
+    let mut result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+    let mut tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].kind, TokenKind::Plus);
+
+    // Comment in the middle of a file
+    source = "+ \n # This is a comment \n +".to_string();
+    result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+    tokens = result.unwrap();
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0].kind, TokenKind::Plus);
+    assert_eq!(tokens[1].kind, TokenKind::Plus);
+  }
+  
+  #[test]
+  fn lex_doc_comments() {
+    let source: String = "## Compute the x'th Fibonacci number.\n##\n##  Indented example: fib(3)\r\n# Not a doc comment\ndef fib(x) {}".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 10);
+    assert_eq!(tokens[0].kind, TokenKind::DocComment("Compute the x'th Fibonacci number.".into()));
+    assert_eq!(tokens[0].span, Span { start: 0, end: 37, line: 1, column: 1 });
+    assert_eq!(tokens[1].kind, TokenKind::DocComment("".into()));
+    assert_eq!(tokens[2].kind, TokenKind::DocComment(" Indented example: fib(3)".into()));
+    assert_eq!(tokens[3].kind, TokenKind::Keyword(Keyword::Def));
+    assert_eq!(tokens[3].span.line, 5);
+
+    // Streamed sources produce the same doc comments
+    let streamed: Vec<Token> = lex_reader(source.as_bytes()).unwrap();
+    assert_eq!(streamed[0].kind, tokens[0].kind);
+    assert_eq!(streamed[2].kind, tokens[2].kind);
+  }
+
+  #[test]
+  fn lex_block_comments() {
+    let source: String = "+ #[ spans\nseveral\nlines ]# - #[ on one line ]# *\n/".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 4);
+    assert_eq!(tokens[0].kind, TokenKind::Plus);
+    assert_eq!(tokens[1].kind, TokenKind::Minus);
+    assert_eq!(tokens[1].span.line, 3);
+    assert_eq!(tokens[2].kind, TokenKind::Times);
+    assert_eq!(tokens[3].kind, TokenKind::Divide);
+    assert_eq!(tokens[3].span.line, 4);
   }
 
-  // Smaller, not syntactically correct tests
   #[test]
-  fn lex_unrecognized_char() {
-    let source: String = "5 + 5?".to_string();
+  fn lex_nested_block_comments() {
+    let source: String = "#[ outer #[ inner ]# still a comment ( ]# +\n#[]#-".to_string();
     let result = lex(&source);
-    assert_eq!(result.is_ok(), false);
+    assert_eq!(result.is_ok(), true);
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 2);
+    assert_eq!(tokens[0].kind, TokenKind::Plus);
+    assert_eq!(tokens[1].kind, TokenKind::Minus);
   }
-  
+
   #[test]
-  fn lex_comments() {
-    // Comment at end of file
-    let mut source: String = "+ \n # This is a comment".to_string();
+  fn lex_unterminated_block_comment() {
+    let mut source: String = "+\n#[ outer #[ inner ]#\n".to_string();
     let mut result = lex(&source);
-    assert_eq!(result.is_ok(), true);
-    let mut tokens: Vec<LoggedToken> = result.unwrap();
-    assert_eq!(tokens.len(), 1);
-    assert_eq!(tokens[0].token, Token::Plus('+'));
+    assert_eq!(result.err().unwrap(), "Unterminated block comment starting at line 2");
 
-    // Comment in the middle of a file
-    source = "+ \n # This is a comment \n +".to_string();
+    // A `]` without the `#` does not close the comment
+    source = "#[ ] #".to_string();
     result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+  }
+
+  #[test]
+  fn lex_shebang_line() {
+    let mut source: String = "#!/usr/bin/env sil\r\nlet x = 5\nx".to_string();
+    let result = lex(&source);
     assert_eq!(result.is_ok(), true);
-    tokens = result.unwrap();
+
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(tokens[0].kind, TokenKind::Keyword(Keyword::Let));
+    assert_eq!(tokens[0].span.line, 2);
+    assert_eq!(tokens[4].span.line, 3);
+
+    // A shebang containing comment syntax is still skipped whole
+    source = "#![ not a block comment\nfib(1)".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 4);
+    assert_eq!(tokens[0].span.line, 2);
+
+    // Anywhere but the start of the file, #! is just a comment
+    source = "x #!/usr/bin/env sil\ny".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
     assert_eq!(tokens.len(), 2);
-    assert_eq!(tokens[0].token, Token::Plus('+'));
-    assert_eq!(tokens[1].token, Token::Plus('+'));
+    assert_eq!(tokens[1].span.line, 2);
   }
-  
+
   #[test]
   fn lex_groupings() {
     let source: String = "[{( )}]".to_string();
     let result = lex(&source);
     assert_eq!(result.is_ok(), true);
-    let tokens: Vec<LoggedToken> = result.unwrap();
+    let tokens: Vec<Token> = result.unwrap();
     assert_eq!(tokens.len(), 6);
-    assert_eq!(tokens[0].token, Token::OpenBracket('['));
-    assert_eq!(tokens[1].token, Token::OpenCurly('{'));
-    assert_eq!(tokens[2].token, Token::OpenParen('('));
-    assert_eq!(tokens[3].token, Token::CloseParen(')'));
-    assert_eq!(tokens[4].token, Token::CloseCurly('}'));
-    assert_eq!(tokens[5].token, Token::CloseBracket(']'));
+    assert_eq!(tokens[0].kind, TokenKind::OpenBracket);
+    assert_eq!(tokens[1].kind, TokenKind::OpenCurly);
+    assert_eq!(tokens[2].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[3].kind, TokenKind::CloseParen);
+    assert_eq!(tokens[4].kind, TokenKind::CloseCurly);
+    assert_eq!(tokens[5].kind, TokenKind::CloseBracket);
   }
   
   #[test]
@@ -329,10 +1549,105 @@ mod tests {
     let source: String = "".to_string();
     let result = lex(&source);
     assert_eq!(result.is_ok(), true);
-    let tokens: Vec<LoggedToken> = result.unwrap();
+    let tokens: Vec<Token> = result.unwrap();
     assert_eq!(tokens.len(), 0);
   }
 
+  #[test]
+  fn lex_integers_and_floats() {
+    let source: String = "0 42 3.0 0.5 1.".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(tokens[0].kind, TokenKind::Integer(0));
+    assert_eq!(tokens[1].kind, TokenKind::Integer(42));
+    assert_eq!(tokens[2].kind, TokenKind::Float(3.0));
+    assert_eq!(tokens[3].kind, TokenKind::Float(0.5));
+    assert_eq!(tokens[4].kind, TokenKind::Float(1.0));
+  }
+
+  #[test]
+  fn lex_integer_overflow() {
+    let source: String = "9223372036854775807".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens[0].kind, TokenKind::Integer(i64::MAX));
+
+    let source: String = "9223372036854775808".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+    assert_eq!(result.err().unwrap(), "Integer literal 9223372036854775808 is too large at line 1");
+
+    // Floats don't overflow into an error, they just lose precision
+    let source: String = "9223372036854775808.0".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+  }
+
+  #[test]
+  fn lex_prefixed_integers() {
+    let source: String = "0xFF 0x1f 0o755 0b1010 0x0".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(tokens[0].kind, TokenKind::Integer(255));
+    assert_eq!(tokens[1].kind, TokenKind::Integer(31));
+    assert_eq!(tokens[2].kind, TokenKind::Integer(493));
+    assert_eq!(tokens[3].kind, TokenKind::Integer(10));
+    assert_eq!(tokens[4].kind, TokenKind::Integer(0));
+  }
+
+  #[test]
+  fn lex_malformed_prefixed_integers() {
+    let mut source: String = "0x".to_string();
+    let mut result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Missing digits after 0x at line 1");
+
+    source = "0b2".to_string();
+    result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Invalid number 0b2 at line 1");
+
+    source = "0o78".to_string();
+    result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+
+    source = "0xFG".to_string();
+    result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+
+    source = "0x8000000000000000".to_string();
+    result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Integer literal 0x8000000000000000 is too large at line 1");
+  }
+
+  #[test]
+  fn lex_scientific_notation() {
+    let source: String = "1.5e-3 2E8 6.02e+23 1e0".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 4);
+    assert_eq!(tokens[0].kind, TokenKind::Float(1.5e-3));
+    assert_eq!(tokens[1].kind, TokenKind::Float(2e8));
+    assert_eq!(tokens[2].kind, TokenKind::Float(6.02e23));
+    assert_eq!(tokens[3].kind, TokenKind::Float(1.0));
+
+    let mut source: String = "2e".to_string();
+    let mut result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Missing exponent digits in number 2e at line 1");
+
+    source = "2e+x".to_string();
+    result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+  }
+
+  #[test]
+  fn lex_underscore_separators() {
+    let source: String = "1_000_000 1.234_567 1_0e1_0 0xFF_FF 0b1010_1010".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 5);
+    assert_eq!(tokens[0].kind, TokenKind::Integer(1000000));
+    assert_eq!(tokens[1].kind, TokenKind::Float(1.234567));
+    assert_eq!(tokens[2].kind, TokenKind::Float(10e10));
+    assert_eq!(tokens[3].kind, TokenKind::Integer(0xFFFF));
+    assert_eq!(tokens[4].kind, TokenKind::Integer(0b10101010));
+  }
+
   #[test]
   fn lex_invalid_number_format() {
     let source: String = "5.2.3".to_string();
@@ -360,54 +1675,54 @@ fib(40)
     let result = lex(&source);
     assert_eq!(result.is_ok(), true);
 
-    let tokens: Vec<LoggedToken> = result.unwrap();
+    let tokens: Vec<Token> = result.unwrap();
     assert_eq!(tokens.len(), 38);
     
     // Test for the function definition of fib
-    assert_eq!(tokens[0].token, Token::Def("def".to_string()));
-    assert_eq!(tokens[1].token, Token::Identifier("fib".to_string()));
-    assert_eq!(tokens[2].token, Token::OpenParen('('));
-    assert_eq!(tokens[3].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[4].token, Token::CloseParen(')'));
-    assert_eq!(tokens[5].token, Token::OpenCurly('{'));
+    assert_eq!(tokens[0].kind, TokenKind::Keyword(Keyword::Def));
+    assert_eq!(tokens[1].kind, TokenKind::Identifier("fib".into()));
+    assert_eq!(tokens[2].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[3].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[4].kind, TokenKind::CloseParen);
+    assert_eq!(tokens[5].kind, TokenKind::OpenCurly);
     
     // Test for the if condition and the return statement
-    assert_eq!(tokens[6].token, Token::If("if".to_string()));
-    assert_eq!(tokens[7].token, Token::OpenParen('('));
-    assert_eq!(tokens[8].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[9].token, Token::LessThan('<'));
-    assert_eq!(tokens[10].token, Token::Number(3.0));
-    assert_eq!(tokens[11].token, Token::CloseParen(')'));
-    assert_eq!(tokens[12].token, Token::OpenCurly('{'));
-    assert_eq!(tokens[13].token, Token::Return("return".to_string()));
-    assert_eq!(tokens[14].token, Token::Number(1.0));
-    assert_eq!(tokens[15].token, Token::CloseCurly('}'));
+    assert_eq!(tokens[6].kind, TokenKind::Keyword(Keyword::If));
+    assert_eq!(tokens[7].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[8].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[9].kind, TokenKind::LessThan);
+    assert_eq!(tokens[10].kind, TokenKind::Integer(3));
+    assert_eq!(tokens[11].kind, TokenKind::CloseParen);
+    assert_eq!(tokens[12].kind, TokenKind::OpenCurly);
+    assert_eq!(tokens[13].kind, TokenKind::Keyword(Keyword::Return));
+    assert_eq!(tokens[14].kind, TokenKind::Integer(1));
+    assert_eq!(tokens[15].kind, TokenKind::CloseCurly);
     
     // Test for the else block and recursive call
-    assert_eq!(tokens[16].token, Token::Else("else".to_string()));
-    assert_eq!(tokens[17].token, Token::OpenCurly('{'));
-    assert_eq!(tokens[18].token, Token::Return("return".to_string()));
-    assert_eq!(tokens[19].token, Token::Identifier("fib".to_string()));
-    assert_eq!(tokens[20].token, Token::OpenParen('('));
-    assert_eq!(tokens[21].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[22].token, Token::Minus('-'));
-    assert_eq!(tokens[23].token, Token::Number(1.0));
-    assert_eq!(tokens[24].token, Token::CloseParen(')'));
-    assert_eq!(tokens[25].token, Token::Plus('+'));
-    assert_eq!(tokens[26].token, Token::Identifier("fib".to_string()));
-    assert_eq!(tokens[27].token, Token::OpenParen('('));
-    assert_eq!(tokens[28].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[29].token, Token::Minus('-'));
-    assert_eq!(tokens[30].token, Token::Number(2.0));
-    assert_eq!(tokens[31].token, Token::CloseParen(')'));
-    assert_eq!(tokens[32].token, Token::CloseCurly('}'));
-    assert_eq!(tokens[33].token, Token::CloseCurly('}'));
+    assert_eq!(tokens[16].kind, TokenKind::Keyword(Keyword::Else));
+    assert_eq!(tokens[17].kind, TokenKind::OpenCurly);
+    assert_eq!(tokens[18].kind, TokenKind::Keyword(Keyword::Return));
+    assert_eq!(tokens[19].kind, TokenKind::Identifier("fib".into()));
+    assert_eq!(tokens[20].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[21].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[22].kind, TokenKind::Minus);
+    assert_eq!(tokens[23].kind, TokenKind::Integer(1));
+    assert_eq!(tokens[24].kind, TokenKind::CloseParen);
+    assert_eq!(tokens[25].kind, TokenKind::Plus);
+    assert_eq!(tokens[26].kind, TokenKind::Identifier("fib".into()));
+    assert_eq!(tokens[27].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[28].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[29].kind, TokenKind::Minus);
+    assert_eq!(tokens[30].kind, TokenKind::Integer(2));
+    assert_eq!(tokens[31].kind, TokenKind::CloseParen);
+    assert_eq!(tokens[32].kind, TokenKind::CloseCurly);
+    assert_eq!(tokens[33].kind, TokenKind::CloseCurly);
     
     // Test for the function call at the end
-    assert_eq!(tokens[34].token, Token::Identifier("fib".to_string()));
-    assert_eq!(tokens[35].token, Token::OpenParen('('));
-    assert_eq!(tokens[36].token, Token::Number(40.0));
-    assert_eq!(tokens[37].token, Token::CloseParen(')'));
+    assert_eq!(tokens[34].kind, TokenKind::Identifier("fib".into()));
+    assert_eq!(tokens[35].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[36].kind, TokenKind::Integer(40));
+    assert_eq!(tokens[37].kind, TokenKind::CloseParen);
   }
 
   #[test]
@@ -433,69 +1748,659 @@ fib(10)
     let result = lex(&source);
     assert_eq!(result.is_ok(), true);
 
-    let tokens: Vec<LoggedToken> = result.unwrap();
+    let tokens: Vec<Token> = result.unwrap();
     assert_eq!(tokens.len(), 46);
     
     // Test for the function definition of fib with the while loop
-    assert_eq!(tokens[0].token, Token::Def("def".to_string()));
-    assert_eq!(tokens[1].token, Token::Identifier("fib".to_string()));
-    assert_eq!(tokens[2].token, Token::OpenParen('('));
-    assert_eq!(tokens[3].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[4].token, Token::CloseParen(')'));
-    assert_eq!(tokens[5].token, Token::OpenCurly('{'));
+    assert_eq!(tokens[0].kind, TokenKind::Keyword(Keyword::Def));
+    assert_eq!(tokens[1].kind, TokenKind::Identifier("fib".into()));
+    assert_eq!(tokens[2].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[3].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[4].kind, TokenKind::CloseParen);
+    assert_eq!(tokens[5].kind, TokenKind::OpenCurly);
     
     // Test for variable assignments
-    assert_eq!(tokens[6].token, Token::Let("let".to_string()));
-    assert_eq!(tokens[7].token, Token::Identifier("a".to_string()));
-    assert_eq!(tokens[8].token, Token::Equal('='));
-    assert_eq!(tokens[9].token, Token::Number(0.0));
+    assert_eq!(tokens[6].kind, TokenKind::Keyword(Keyword::Let));
+    assert_eq!(tokens[7].kind, TokenKind::Identifier("a".into()));
+    assert_eq!(tokens[8].kind, TokenKind::Equal);
+    assert_eq!(tokens[9].kind, TokenKind::Integer(0));
     
-    assert_eq!(tokens[10].token, Token::Let("let".to_string()));
-    assert_eq!(tokens[11].token, Token::Identifier("b".to_string()));
-    assert_eq!(tokens[12].token, Token::Equal('='));
-    assert_eq!(tokens[13].token, Token::Number(1.0));
+    assert_eq!(tokens[10].kind, TokenKind::Keyword(Keyword::Let));
+    assert_eq!(tokens[11].kind, TokenKind::Identifier("b".into()));
+    assert_eq!(tokens[12].kind, TokenKind::Equal);
+    assert_eq!(tokens[13].kind, TokenKind::Integer(1));
     
     // Test for the while loop condition
-    assert_eq!(tokens[14].token, Token::While("while".to_string()));
-    assert_eq!(tokens[15].token, Token::OpenParen('('));
-    assert_eq!(tokens[16].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[17].token, Token::GreaterThan('>'));
-    assert_eq!(tokens[18].token, Token::Number(0.0));
-    assert_eq!(tokens[19].token, Token::CloseParen(')'));
-    assert_eq!(tokens[20].token, Token::OpenCurly('{'));
+    assert_eq!(tokens[14].kind, TokenKind::Keyword(Keyword::While));
+    assert_eq!(tokens[15].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[16].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[17].kind, TokenKind::GreaterThan);
+    assert_eq!(tokens[18].kind, TokenKind::Integer(0));
+    assert_eq!(tokens[19].kind, TokenKind::CloseParen);
+    assert_eq!(tokens[20].kind, TokenKind::OpenCurly);
     
     // Test for variable assignments inside the loop
-    assert_eq!(tokens[21].token, Token::Let("let".to_string()));
-    assert_eq!(tokens[22].token, Token::Identifier("temp".to_string()));
-    assert_eq!(tokens[23].token, Token::Equal('='));
-    assert_eq!(tokens[24].token, Token::Identifier("a".to_string()));
+    assert_eq!(tokens[21].kind, TokenKind::Keyword(Keyword::Let));
+    assert_eq!(tokens[22].kind, TokenKind::Identifier("temp".into()));
+    assert_eq!(tokens[23].kind, TokenKind::Equal);
+    assert_eq!(tokens[24].kind, TokenKind::Identifier("a".into()));
     
-    assert_eq!(tokens[25].token, Token::Identifier("a".to_string()));
-    assert_eq!(tokens[26].token, Token::Equal('='));
-    assert_eq!(tokens[27].token, Token::Identifier("b".to_string()));
+    assert_eq!(tokens[25].kind, TokenKind::Identifier("a".into()));
+    assert_eq!(tokens[26].kind, TokenKind::Equal);
+    assert_eq!(tokens[27].kind, TokenKind::Identifier("b".into()));
     
-    assert_eq!(tokens[28].token, Token::Identifier("b".to_string()));
-    assert_eq!(tokens[29].token, Token::Equal('='));
-    assert_eq!(tokens[30].token, Token::Identifier("temp".to_string()));
-    assert_eq!(tokens[31].token, Token::Plus('+'));
-    assert_eq!(tokens[32].token, Token::Identifier("b".to_string()));
+    assert_eq!(tokens[28].kind, TokenKind::Identifier("b".into()));
+    assert_eq!(tokens[29].kind, TokenKind::Equal);
+    assert_eq!(tokens[30].kind, TokenKind::Identifier("temp".into()));
+    assert_eq!(tokens[31].kind, TokenKind::Plus);
+    assert_eq!(tokens[32].kind, TokenKind::Identifier("b".into()));
     
-    assert_eq!(tokens[33].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[34].token, Token::Equal('='));
-    assert_eq!(tokens[35].token, Token::Identifier("x".to_string()));
-    assert_eq!(tokens[36].token, Token::Minus('-'));
-    assert_eq!(tokens[37].token, Token::Number(1.0));
-    assert_eq!(tokens[38].token, Token::CloseCurly('}'));
+    assert_eq!(tokens[33].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[34].kind, TokenKind::Equal);
+    assert_eq!(tokens[35].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[36].kind, TokenKind::Minus);
+    assert_eq!(tokens[37].kind, TokenKind::Integer(1));
+    assert_eq!(tokens[38].kind, TokenKind::CloseCurly);
     
     // Test for the return statement
-    assert_eq!(tokens[39].token, Token::Return("return".to_string()));
-    assert_eq!(tokens[40].token, Token::Identifier("a".to_string()));
-    assert_eq!(tokens[41].token, Token::CloseCurly('}'));
+    assert_eq!(tokens[39].kind, TokenKind::Keyword(Keyword::Return));
+    assert_eq!(tokens[40].kind, TokenKind::Identifier("a".into()));
+    assert_eq!(tokens[41].kind, TokenKind::CloseCurly);
 
     // Test for the function call
-    assert_eq!(tokens[42].token, Token::Identifier("fib".to_string()));
-    assert_eq!(tokens[43].token, Token::OpenParen('('));
-    assert_eq!(tokens[44].token, Token::Number(10.0));
-    assert_eq!(tokens[45].token, Token::CloseParen(')'));
+    assert_eq!(tokens[42].kind, TokenKind::Identifier("fib".into()));
+    assert_eq!(tokens[43].kind, TokenKind::OpenParen);
+    assert_eq!(tokens[44].kind, TokenKind::Integer(10));
+    assert_eq!(tokens[45].kind, TokenKind::CloseParen);
+  }
+
+  #[test]
+  fn lex_unclosed_delimiter() {
+    let source: String = "def f(x) {\n  return (x + 1\n}".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+    assert_eq!(result.err().unwrap(), "Mismatched '}' at line 3, expected ')' to close '(' opened at line 2");
+
+    let source: String = "def f(x) {\n  return x\n".to_string();
+    let result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Unclosed '{' opened at line 1, expected '}' before end of file");
+  }
+
+  #[test]
+  fn lex_unmatched_closing_delimiter() {
+    let source: String = "fib(40))".to_string();
+    let result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Unmatched ')' at line 1");
+  }
+
+  #[test]
+  fn lex_crlf_line_endings() {
+    let unix: String = "# Comment\nlet x = 5\n\nx = x + 1\n".to_string();
+    let windows: String = unix.replace("\n", "\r\n");
+    let unix_tokens: Vec<Token> = lex(&unix).unwrap();
+    let windows_tokens: Vec<Token> = lex(&windows).unwrap();
+    assert_eq!(windows_tokens.len(), 9);
+    assert_eq!(unix_tokens.len(), windows_tokens.len());
+
+    for (unix_tok, windows_tok) in unix_tokens.iter().zip(windows_tokens.iter()) {
+      assert_eq!(unix_tok.kind, windows_tok.kind);
+      assert_eq!(unix_tok.span.line, windows_tok.span.line);
+      assert_eq!(unix_tok.span.column, windows_tok.span.column);
+    }
+    assert_eq!(windows_tokens[0].span.line, 2);
+    assert_eq!(windows_tokens[4].span.line, 4);
+  }
+
+  #[test]
+  fn lex_lone_carriage_return() {
+    let source: String = "let x = 5\rx = 6".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 7);
+    assert_eq!(tokens[4].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[4].span.line, 2);
+  }
+
+  #[test]
+  fn lex_unicode_line_terminators() {
+    let mut source: String = "let x = 5\u{2028}let y = 6 # comment\u{2029}x\u{85}y".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 10);
+    assert_eq!(tokens[4].span.line, 2);
+    assert_eq!(tokens[4].span.column, 1);
+    assert_eq!(tokens[8].span.line, 3);
+    assert_eq!(tokens[9].span.line, 4);
+
+    // Mixed line endings in one file
+    source = "a\r\nb\nc\rd\u{2028}e\r\n\r\nf".to_string();
+    let lines: Vec<u32> = lex(&source).unwrap().iter().map(|token| token.span.line).collect();
+    assert_eq!(lines, vec![1, 2, 3, 4, 5, 7]);
+
+    source = "'\u{2028}'".to_string();
+    let result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Unterminated character literal at line 1");
+
+    source = "x\u{2028}\r\n# comment".to_string();
+    let lossless = lex_with_trivia(&source).unwrap();
+    assert_eq!(lossless.trailing.iter().map(|trivia| trivia.kind).collect::<Vec<TriviaKind>>(),
+      vec![TriviaKind::Newline, TriviaKind::Newline, TriviaKind::Comment]);
+  }
+
+  #[test]
+  fn lex_reader_matches_lex() {
+    let source: String = "# Compute the x'th Fibonacci number, ünïcode and all\ndef fib(x) {\n  return fib(x-1) + fib(x-2)\n}\nfib(40)".to_string();
+    let expected: Vec<Token> = lex(&source).unwrap();
+
+    // Tiny chunks force identifiers, numbers and multi-byte characters to straddle chunk boundaries
+    for chunk_size in [1, 2, 3, 7, 4096] {
+      let mut chars = SourceChars::with_chunk_size(source.as_bytes(), chunk_size);
+      let (tokens, errors) = lex_chars(&mut chars, None, false, None, &LexerConfig::default());
+      assert_eq!(errors.len(), 0);
+      assert_eq!(chars.finish().is_ok(), true);
+      assert_eq!(tokens.len(), expected.len());
+      for (streamed, whole) in tokens.iter().zip(expected.iter()) {
+        assert_eq!(streamed.kind, whole.kind);
+        assert_eq!(streamed.span, whole.span);
+      }
+    }
+  }
+
+  #[test]
+  fn lex_file_streams_from_disk() {
+    let path = std::env::temp_dir().join(format!("rachit-cc-lex-file-{}.sil", std::process::id()));
+    let file_path = path.to_str().unwrap();
+    std::fs::write(&path, "def fib(x) {\n  return fib(x-1) + fib(x-2)\n}\nfib(40)").unwrap();
+    let result = lex_file(file_path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap().len(), 25);
+
+    let result = lex_file("does/not/exist.sil");
+    assert_eq!(result.is_ok(), false);
+    assert_eq!(result.err().unwrap().starts_with("does/not/exist.sil: "), true);
+  }
+
+  #[test]
+  fn lex_skips_byte_order_mark() {
+    let source: String = "\u{FEFF}let x = 5".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 4);
+    assert_eq!(tokens[0].span, Span { start: 0, end: 3, line: 1, column: 1 });
+
+    let streamed = lex_reader(source.as_bytes()).unwrap();
+    assert_eq!(streamed[0].span, tokens[0].span);
+
+    // Only a leading BOM is skipped
+    let source: String = "x \u{FEFF}".to_string();
+    let result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Unrecognized character \u{FEFF} at line 1");
+  }
+
+  #[test]
+  fn lex_reader_invalid_utf8() {
+    let source: &[u8] = b"let x = 5\n\xFF";
+    let result = lex_reader(source);
+    assert_eq!(result.is_ok(), false);
+  }
+
+  #[test]
+  fn lex_stream_yields_tokens_as_it_goes() {
+    let config = LexerConfig::default();
+    let tokens: Vec<Result<Token, LexError>> = lex_stream("let x = (1\n".as_bytes(), &config).collect();
+    assert_eq!(tokens.len(), 6);
+    assert_eq!(tokens[4].as_ref().unwrap().kind, TokenKind::Integer(1));
+    assert_eq!(tokens[5].as_ref().err().unwrap().to_string(), "Unclosed '(' opened at line 1, expected ')' before end of file");
+
+    // Only the tokens taken are lexed, so an endless source can be read from
+    let endless = BufReader::new(std::io::repeat(b';'));
+    assert_eq!(lex_stream(endless, &config).take(100_000).filter(|token| token.is_ok()).count(), 100_000);
+
+    // Nothing comes after an error, including the token that went past the limit
+    let config = LexerConfig { max_tokens: 2, ..LexerConfig::default() };
+    let tokens: Vec<Result<Token, LexError>> = lex_stream("a b c d".as_bytes(), &config).collect();
+    assert_eq!(tokens.len(), 3);
+    assert_eq!(tokens[2].as_ref().err().unwrap(), &LexError::TooManyTokens { line: 1, max: 2 });
+    let tokens: Vec<Result<Token, LexError>> = lex_stream(&b"let x = \"5\xFF\""[..], &LexerConfig::default()).collect();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].as_ref().err().unwrap().to_string(), "Invalid UTF-8 byte 0xFF at offset 10, SIL source files must be UTF-8 encoded");
+  }
+
+  #[test]
+  fn lex_string_literals() {
+    let source: String = r#"let greeting = "hello, world" + "tab\there \"quoted\" back\\slash\n""#.to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 6);
+    assert_eq!(tokens[3].kind, TokenKind::StringLiteral("hello, world".into()));
+    assert_eq!(tokens[4].kind, TokenKind::Plus);
+    assert_eq!(tokens[5].kind, TokenKind::StringLiteral("tab\there \"quoted\" back\\slash\n".into()));
+  }
+
+  #[test]
+  fn lex_empty_and_multiline_strings() {
+    let source: String = "\"\" \"two\nlines\" x".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 3);
+    assert_eq!(tokens[0].kind, TokenKind::StringLiteral("".into()));
+    assert_eq!(tokens[1].kind, TokenKind::StringLiteral("two\nlines".into()));
+    assert_eq!(tokens[2].span.line, 2);
+  }
+
+  #[test]
+  fn lex_unterminated_string() {
+    let source: String = "let x = 5\nlet s = \"never closed\nfib(40)".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+    assert_eq!(result.err().unwrap(), "Unterminated string starting at line 2");
+
+    let source: String = "\"invalid \\q escape\"".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+  }
+
+  #[test]
+  fn lex_interpolated_strings() {
+    let mut source: String = r#"print("sum is ${a + b}!")"#.to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 8);
+    assert_eq!(tokens[2].kind, TokenKind::StringStart("sum is ".into()));
+    assert_eq!(tokens[3].kind, TokenKind::Identifier("a".into()));
+    assert_eq!(tokens[5].kind, TokenKind::Identifier("b".into()));
+    assert_eq!(tokens[6].kind, TokenKind::StringEnd("!".into()));
+    assert_eq!(tokens[7].kind, TokenKind::CloseParen);
+    assert_eq!(&source[tokens[2].span.start..tokens[2].span.end], "\"sum is ${");
+    assert_eq!(&source[tokens[6].span.start..tokens[6].span.end], "}!\"");
+
+    // Several expressions, nested braces and strings inside an expression, and an escaped `$`
+    source = r#""${x}, ${ {y} } and ${"in${z}ner"} cost \${5}""#.to_string();
+    let kinds: Vec<TokenKind> = lex(&source).unwrap().into_iter().map(|token| token.kind).collect();
+    assert_eq!(kinds, vec![
+      TokenKind::StringStart("".into()),
+      TokenKind::Identifier("x".into()),
+      TokenKind::StringMiddle(", ".into()),
+      TokenKind::OpenCurly,
+      TokenKind::Identifier("y".into()),
+      TokenKind::CloseCurly,
+      TokenKind::StringMiddle(" and ".into()),
+      TokenKind::StringStart("in".into()),
+      TokenKind::Identifier("z".into()),
+      TokenKind::StringEnd("ner".into()),
+      TokenKind::StringEnd(" cost ${5}".into()),
+    ]);
+
+    // A lone `$` is just text
+    source = r#""$5 and $""#.to_string();
+    assert_eq!(lex(&source).unwrap()[0].kind, TokenKind::StringLiteral("$5 and $".into()));
+  }
+
+  #[test]
+  fn lex_unterminated_interpolation() {
+    let mut source: String = "\"sum is ${a + b\n".to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+    assert_eq!(result.err().unwrap(), "Unclosed '${' opened at line 1, expected '}' before end of file");
+
+    source = "\"sum is ${a + b)}\"".to_string();
+    let result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Mismatched ')' at line 1, expected '}' to close '${' opened at line 1");
+
+    source = "\"sum is ${a + b} never closed".to_string();
+    let result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Unterminated string starting at line 1");
+  }
+
+  #[test]
+  fn lex_spans() {
+    let source: String = "let x = 5\n  x = x >= 10\n\tfib(x)".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 13);
+
+    assert_eq!(tokens[0].span, Span { start: 0, end: 3, line: 1, column: 1 });
+    assert_eq!(tokens[3].span, Span { start: 8, end: 9, line: 1, column: 9 });
+
+    // Columns restart on every line instead of carrying on from the previous one
+    assert_eq!(tokens[4].kind, TokenKind::Identifier("x".into()));
+    assert_eq!(tokens[4].span, Span { start: 12, end: 13, line: 2, column: 3 });
+    assert_eq!(tokens[7].kind, TokenKind::GreaterThanEqual);
+    assert_eq!(tokens[7].span, Span { start: 18, end: 20, line: 2, column: 9 });
+    assert_eq!(tokens[8].span, Span { start: 21, end: 23, line: 2, column: 12 });
+    assert_eq!(tokens[9].kind, TokenKind::Identifier("fib".into()));
+    assert_eq!(tokens[9].span, Span { start: 25, end: 28, line: 3, column: 2 });
+    assert_eq!(tokens[12].span, Span { start: 30, end: 31, line: 3, column: 7 });
+  }
+
+  #[test]
+  fn lex_spans_after_comments_and_strings() {
+    let source: String = "# comment ünïcode\n\"two\nlines\" #[ block\n comment ]# + 0xFF".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 3);
+    assert_eq!(tokens[0].span, Span { start: 20, end: 31, line: 2, column: 1 });
+    assert_eq!(tokens[1].kind, TokenKind::Plus);
+    assert_eq!(tokens[1].span.line, 4);
+    assert_eq!(tokens[1].span.column, 13);
+    assert_eq!(tokens[2].span, Span { start: source.len() - 4, end: source.len(), line: 4, column: 15 });
+    assert_eq!(&source[tokens[2].span.start..tokens[2].span.end], "0xFF");
+  }
+
+  #[test]
+  fn lex_char_literals() {
+    let source: String = r#"'a' '7' ' ' 'é' '\n' '\t' '\'' '\\' '"'"#.to_string();
+    let result = lex(&source);
+    assert_eq!(result.is_ok(), true);
+    let tokens: Vec<Token> = result.unwrap();
+    assert_eq!(tokens.len(), 9);
+    assert_eq!(tokens[0].kind, TokenKind::CharLiteral('a'));
+    assert_eq!(tokens[1].kind, TokenKind::CharLiteral('7'));
+    assert_eq!(tokens[2].kind, TokenKind::CharLiteral(' '));
+    assert_eq!(tokens[3].kind, TokenKind::CharLiteral('é'));
+    assert_eq!(tokens[4].kind, TokenKind::CharLiteral('\n'));
+    assert_eq!(tokens[5].kind, TokenKind::CharLiteral('\t'));
+    assert_eq!(tokens[6].kind, TokenKind::CharLiteral('\''));
+    assert_eq!(tokens[7].kind, TokenKind::CharLiteral('\\'));
+    assert_eq!(tokens[8].kind, TokenKind::CharLiteral('"'));
+  }
+
+  #[test]
+  fn lex_invalid_char_literals() {
+    let mut source: String = "''".to_string();
+    let mut result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Empty character literal at line 1");
+
+    source = "'ab'".to_string();
+    result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Character literal at line 1 holds more than one character, use \"...\" for strings");
+
+    source = "'a\nb'".to_string();
+    result = lex(&source);
+    assert_eq!(result.err().unwrap(), "Unterminated character literal at line 1");
+
+    source = r"'\q'".to_string();
+    result = lex(&source);
+    assert_eq!(result.is_ok(), false);
+  }
+
+  #[test]
+  fn lex_borrows_from_source() {
+    let source: String = "let name = \"plain\" + \"escaped\\n\"".to_string();
+    let tokens: Vec<Token> = lex(&source).unwrap();
+    assert_eq!(tokens.len(), 6);
+    assert!(matches!(tokens[1].kind, TokenKind::Identifier(Cow::Borrowed("name"))));
+    assert!(matches!(tokens[3].kind, TokenKind::StringLiteral(Cow::Borrowed("plain"))));
+    assert!(matches!(tokens[5].kind, TokenKind::StringLiteral(Cow::Owned(_))));
+    assert_eq!(tokens[5].kind, TokenKind::StringLiteral("escaped\n".into()));
+
+    // A streamed source isn't around to borrow from, so the same tokens are owned instead
+    let streamed: Vec<Token> = lex_reader(source.as_bytes()).unwrap();
+    assert!(matches!(streamed[1].kind, TokenKind::Identifier(Cow::Owned(_))));
+    assert_eq!(streamed[1].kind, tokens[1].kind);
+    assert_eq!(streamed[3].kind, tokens[3].kind);
+  }
+
+  #[test]
+  fn lex_with_config_limits() {
+    let config = LexerConfig { max_identifier_len: 8, max_number_len: 6, max_tokens: 5 };
+    let mut source: String = "let fib = 40".to_string();
+    let result = lex_with_config(&source, &config);
+    assert_eq!(result.is_ok(), true);
+
+    source = "let\nfibonacci_number = 40".to_string();
+    let result = lex_with_config(&source, &config);
+    assert_eq!(result.err().unwrap(), LexError::IdentifierTooLong { line: 2, max: 8 });
+
+    source = "x = 3.14159265".to_string();
+    let result = lex_with_config(&source, &config);
+    assert_eq!(result.err().unwrap(), LexError::NumberTooLong { line: 1, max: 6 });
+    assert_eq!(lex_with_config("0x1234567", &config).err().unwrap().to_string(), "Numeric literal at line 1 is longer than the limit of 6 bytes");
+    assert_eq!(lex_with_config("1e+12345", &config).err().unwrap(), LexError::NumberTooLong { line: 1, max: 6 });
+
+    source = "a + b + c + d".to_string();
+    let result = lex_with_config(&source, &config);
+    assert_eq!(result.err().unwrap(), LexError::TooManyTokens { line: 1, max: 5 });
+    assert_eq!(lex_with_config(&source, &config).err().unwrap().to_string(), "Source has more than 5 tokens (limit reached at line 1)");
+
+    // The default config doesn't limit anything, and lexical errors come through as they always have
+    assert_eq!(lex_with_config(&source, &LexerConfig::default()).is_ok(), true);
+    source = "x = @".to_string();
+    let result = lex_with_config(&source, &config);
+    assert_eq!(result.err().unwrap(), LexError::Invalid("Unrecognized character @ at line 1".to_string()));
+  }
+
+  #[test]
+  fn lex_limits_in_every_mode() {
+    let config = LexerConfig { max_identifier_len: 8, max_number_len: 6, max_tokens: 5 };
+
+    // Recovery carries on past lexical errors, but not past a limit
+    let (tokens, errors) = lex_with_recovery_and_config("x = @ 1\ny = fibonacci_number + 2", &config);
+    assert_eq!(errors, vec![
+      LexError::Invalid("Unrecognized character @ at line 1".to_string()),
+      LexError::IdentifierTooLong { line: 2, max: 8 },
+    ]);
+    assert_eq!(tokens.len(), 5);
+    let (_, errors) = lex_with_recovery_and_config("a + b + c + d", &config);
+    assert_eq!(errors, vec![LexError::TooManyTokens { line: 1, max: 5 }]);
+
+    let result = lex_reader_with_config("let\nfibonacci_number = 40".as_bytes(), &config);
+    assert_eq!(result.err().unwrap(), LexError::IdentifierTooLong { line: 2, max: 8 });
+    let result = lex_reader_with_config("x = 3.14159265".as_bytes(), &config);
+    assert_eq!(result.err().unwrap(), LexError::NumberTooLong { line: 1, max: 6 });
+    let result = lex_reader_with_config("a + b + c + d".as_bytes(), &config);
+    assert_eq!(result.err().unwrap(), LexError::TooManyTokens { line: 1, max: 5 });
+    assert_eq!(lex_reader_with_config("let fib = 40".as_bytes(), &config).is_ok(), true);
+
+    // The limits are checked while a token is scanned, so an endless one stops as soon as it's past the limit
+    let endless = |byte: u8| BufReader::new(std::io::repeat(byte));
+    assert_eq!(lex_reader_with_config(endless(b'a'), &config).err().unwrap(), LexError::IdentifierTooLong { line: 1, max: 8 });
+    assert_eq!(lex_reader_with_config(endless(b'7'), &config).err().unwrap(), LexError::NumberTooLong { line: 1, max: 6 });
+    assert_eq!(lex_reader_with_config(endless(b';'), &config).err().unwrap(), LexError::TooManyTokens { line: 1, max: 5 });
+  }
+
+  #[test]
+  fn lex_recovers_from_errors() {
+    let source: String = "let x = 5 @\nlet y = 0b2 + $ 1\nlet s = \"bad \\q\" + 2".to_string();
+    let (tokens, errors) = lex_with_recovery(&source);
+    assert_eq!(errors.len(), 4);
+    assert_eq!(errors[0], "Unrecognized character @ at line 1");
+    assert_eq!(errors[1], "Invalid number 0b2 at line 2");
+    assert_eq!(errors[2], "Unrecognized character $ at line 2");
+    assert_eq!(errors[3], "Invalid escape sequence \\q in string at line 3");
+
+    // Everything around the bad characters still lexes
+    assert_eq!(tokens.len(), 14);
+    assert_eq!(tokens[4].kind, TokenKind::Keyword(Keyword::Let));
+    assert_eq!(tokens[7].kind, TokenKind::Plus);
+    assert_eq!(tokens[8].kind, TokenKind::Integer(1));
+    assert_eq!(tokens[13].kind, TokenKind::Integer(2));
+
+    // Without recovery only the first problem is reported
+    assert_eq!(lex(&source).err().unwrap(), errors[0]);
+  }
+
+  #[test]
+  fn lex_recovery_reports_every_unclosed_delimiter() {
+    let source: String = "def f(x) {\n  g([1, 2)\n".to_string();
+    let (_, errors) = lex_with_recovery(&source);
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[0], "Mismatched ')' at line 2, expected ']' to close '[' opened at line 2");
+    assert_eq!(errors[1], "Unclosed '(' opened at line 2, expected ')' before end of file");
+    assert_eq!(errors[2], "Unclosed '{' opened at line 1, expected '}' before end of file");
+  }
+
+  #[test]
+  fn lex_with_trivia_roundtrips() {
+    let sources = [
+      "",
+      "   \n\t # only trivia\n",
+      "let x = 5\n  x = x + 1 # trailing comment",
+      "## Doc comment\r\ndef f(a,b){return a+b}\r\n\r\n#[ block\n #[ nested ]# ]#  f(1, 2)\n",
+      "\"string\\twith escapes\" 'c' 0xFF_FF 1.5e-3 \r",
+    ];
+    for source in sources {
+      let result = lex_with_trivia(source);
+      assert_eq!(result.is_ok(), true);
+      assert_eq!(result.unwrap().to_source(), source);
+    }
+  }
+
+  #[test]
+  fn lex_with_trivia_attaches_leading_trivia() {
+    let source: String = "let x  = 5 # five\r\n\tx\n".to_string();
+    let lossless = lex_with_trivia(&source).unwrap();
+    assert_eq!(lossless.tokens.len(), 5);
+
+    assert_eq!(lossless.tokens[0].leading.len(), 0);
+    assert_eq!(lossless.tokens[0].text, "let");
+    assert_eq!(lossless.tokens[2].leading[0].kind, TriviaKind::Whitespace);
+    assert_eq!(lossless.tokens[2].leading[0].text, "  ");
+
+    let before_x: Vec<(TriviaKind, &str)> = lossless.tokens[4].leading.iter().map(|t| (t.kind, t.text)).collect();
+    assert_eq!(before_x, vec![
+      (TriviaKind::Whitespace, " "),
+      (TriviaKind::Comment, "# five"),
+      (TriviaKind::Newline, "\r\n"),
+      (TriviaKind::Whitespace, "\t"),
+    ]);
+    assert_eq!(lossless.trailing.len(), 1);
+    assert_eq!(lossless.trailing[0].kind, TriviaKind::Newline);
+  }
+
+  // Token stream comparison tests
+  #[test]
+  fn diff_ignores_whitespace_and_comments() {
+    let first: String = "def f(x) { return x+1 }".to_string();
+    let second: String = "# Same function, reformatted\ndef f( x ) {\n  return x + 1\n}\n".to_string();
+    let first_tokens = lex(&first).unwrap();
+    let second_tokens = lex(&second).unwrap();
+    assert_eq!(first_divergence(&first_tokens, &second_tokens), None);
+
+    // Doc comments are skipped too, but the tokens after them are still compared
+    let third: String = "## Adds one to x\ndef f(x) {\n  ## The next number\n  return x + 1 ## or 1 + x\n}".to_string();
+    let third_tokens = lex(&third).unwrap();
+    assert_eq!(first_divergence(&first_tokens, &third_tokens), None);
+    assert_eq!(first_divergence(&third_tokens, &first_tokens), None);
+    let fourth: String = "## Adds one to x\ndef f(x) { return x - 1 }".to_string();
+    let fourth_tokens = lex(&fourth).unwrap();
+    assert_eq!(first_divergence(&third_tokens, &fourth_tokens), Some((10, 9)));
+    assert_eq!(fourth_tokens[9].kind, TokenKind::Minus);
+  }
+
+  #[test]
+  fn diff_reports_first_changed_token() {
+    let first: String = "let x = 5 \n x = x + 1".to_string();
+    let second: String = "let x = 5 \n x = x - 1".to_string();
+    let first_tokens = lex(&first).unwrap();
+    let second_tokens = lex(&second).unwrap();
+    assert_eq!(first_divergence(&first_tokens, &second_tokens), Some((7, 7)));
+    assert_eq!(first_tokens[7].kind, TokenKind::Plus);
+    assert_eq!(second_tokens[7].kind, TokenKind::Minus);
+  }
+
+  #[test]
+  fn diff_reports_truncated_stream() {
+    let first: String = "fib(40)".to_string();
+    let second: String = "fib(40) + 1".to_string();
+    let first_tokens = lex(&first).unwrap();
+    let second_tokens = lex(&second).unwrap();
+    assert_eq!(first_divergence(&first_tokens, &second_tokens), Some((4, 4)));
+    assert_eq!(first_divergence(&second_tokens, &first_tokens), Some((4, 4)));
+  }
+
+  #[test]
+  fn warn_about_miscased_keywords() {
+    let source: String = "Def f(x) {\n  IF x { Return 1 }\n  return define\n}".to_string();
+    let tokens = lex(&source).unwrap();
+    let warnings = keyword_case_warnings(&tokens);
+    assert_eq!(warnings.len(), 3);
+    assert_eq!(warnings[0], "'Def' at line 1, column 1 is an identifier, not a keyword, did you mean 'def'?");
+    assert_eq!(warnings[1], "'IF' at line 2, column 3 is an identifier, not a keyword, did you mean 'if'?");
+    assert_eq!(warnings[2], "'Return' at line 2, column 10 is an identifier, not a keyword, did you mean 'return'?");
+  }
+
+  // Property tests
+
+  // Fragments of SIL source and how many tokens each lexes to
+  const PIECES: [(&str, usize); 28] = [
+    ("def", 1), ("let", 1), ("while", 1), ("fib", 1), ("x_1", 1), ("42", 1), ("0x1F", 1), ("1.5e3", 1),
+    ("\"str\\n\"", 1), ("'c'", 1), ("=", 1), ("==", 1), ("<=", 1), (">", 1), ("<<", 1), ("!", 1), ("&&", 1),
+    ("->", 1), ("-", 1), (".", 1), ("?", 1), ("**", 1), ("++", 1), ("--", 1), ("(x)", 3), ("{ }", 2), ("[1, 2]", 5), ("\"a${b}c\"", 3),
+  ];
+
+  // What a program can be cut off with at the end of the file, and how many tokens that lexes to, if it lexes
+  const ENDINGS: [(&str, Option<usize>); 16] = [
+    ("", Some(0)), ("<", Some(1)), ("=", Some(1)), ("!", Some(1)), ("&", Some(1)), ("-", Some(1)), ("*", Some(1)),
+    ("/*", Some(2)), ("#", Some(0)), ("\"abc", None), ("\"a${", None), ("'", None), ("'a", None), ("#[", None),
+    ("0x", None), ("1e", None),
+  ];
+
+  // Tokens must be non-empty, in order, non-overlapping, and lie on character boundaries of the source
+  fn assert_spans_increase(source: &str, tokens: &[Token]) {
+    let mut prev_end = 0;
+    let mut prev_line = 1;
+    for token in tokens {
+      assert!(token.span.start < token.span.end, "empty span {:?}", token.span);
+      assert!(token.span.start >= prev_end, "span {:?} overlaps the previous token", token.span);
+      assert!(token.span.line >= prev_line, "line went backwards at {:?}", token.span);
+      assert!(source.is_char_boundary(token.span.start) && source.is_char_boundary(token.span.end));
+      prev_end = token.span.end;
+      prev_line = token.span.line;
+    }
+    assert!(prev_end <= source.len());
+  }
+
+  proptest::proptest! {
+    #[test]
+    fn lex_never_panics_on_arbitrary_bytes(bytes in proptest::collection::vec(proptest::num::u8::ANY, 0..256)) {
+      if let Ok(source) = crate::source::decode(&bytes) {
+        let _ = lex(&source);
+        let (tokens, _) = lex_with_recovery(&source);
+        assert_spans_increase(&source, &tokens);
+      }
+      let _ = lex_reader(&bytes[..]);
+    }
+
+    #[test]
+    fn lex_spans_increase_on_sil_like_text(source in "[a-z0-9_ \t\r\n#()\\[\\]{}<>=!&|^~+*/%,;:.\"'$\\\\-]{0,64}") {
+      if let Ok(tokens) = lex(&source) {
+        assert_spans_increase(&source, &tokens);
+      }
+      let (tokens, _) = lex_with_recovery(&source);
+      assert_spans_increase(&source, &tokens);
+      if let Ok(lossless) = lex_with_trivia(&source) {
+        assert_eq!(lossless.to_source(), source);
+      }
+    }
+
+    #[test]
+    fn lex_random_programs(pieces in proptest::collection::vec(proptest::sample::select(PIECES.to_vec()), 0..40),
+      separator in proptest::sample::select(vec![" ", "\n", "\r\n", "\t", " # comment\n", " #[ block ]# "]),
+      (ending, count) in proptest::sample::select(ENDINGS.to_vec())) {
+      let texts: Vec<&str> = pieces.iter().map(|(text, _)| *text).collect();
+      let source: String = format!("{}{}{}", texts.join(separator), separator, ending);
+      let result = lex(&source);
+      let (recovered, errors) = lex_with_recovery(&source);
+      assert_spans_increase(&source, &recovered);
+      assert_eq!(lex_reader(source.as_bytes()).is_ok(), count.is_some());
+
+      // An ending that can't be finished is an error, never a panic or a token made up of nothing
+      let Some(count) = count else {
+        assert_eq!(result.is_err(), true);
+        assert_eq!(errors.is_empty(), false);
+        return Ok(());
+      };
+      let tokens = result.unwrap();
+      assert_eq!(tokens.len(), pieces.iter().map(|(_, count)| count).sum::<usize>() + count);
+      assert_spans_increase(&source, &tokens);
+    }
   }
 }
@@ -1,2 +1,24 @@
+// Explicit `return` and `assert_eq!(..., true)` are the house style
+#![allow(clippy::needless_return, clippy::bool_assert_comparison)]
+
+pub mod builtins;
+pub mod callgraph;
+pub mod calls;
+pub mod captures;
+pub mod dce;
+pub mod diagnostic;
+pub mod escape;
+pub mod flow;
+pub mod fold;
+pub mod highlight;
 pub mod lexer;
-pub mod parser;
\ No newline at end of file
+pub mod lints;
+pub mod mutability;
+pub mod parser;
+pub mod passes;
+pub mod purity;
+pub mod resolver;
+pub mod source;
+pub mod symbols;
+pub mod typeck;
+pub mod visit;
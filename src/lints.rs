@@ -0,0 +1,237 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Span;
+use crate::parser::{Expr, ExprAST, NodeId, NodeMap, Param, Program, Spanned, Stmt, StmtAST};
+use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+// Warnings for variables, constants and parameters that are declared but never read, using the declarations
+// the resolver found. Assigning to a variable isn't reading it. Names starting with an underscore are left
+// alone, so a parameter a function has to take but doesn't need can be marked as unused on purpose
+pub fn unused_variables(program: &Program, declarations: &NodeMap<NodeId>) -> Vec<Diagnostic> {
+  let mut bindings = Bindings { declarations, read: HashSet::new(), declared: Vec::new() };
+  for function in &program.functions {
+    bindings.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    bindings.visit_stmt(stmt);
+  }
+
+  let mut warnings: Vec<Diagnostic> = bindings.declared.iter()
+    .filter(|binding| !binding.name.starts_with('_') && !bindings.read.contains(&binding.id))
+    .map(|binding| Diagnostic::warning("unused", format!("{} '{}' is never read", binding.kind, binding.name), binding.span))
+    .collect();
+  warnings.sort_by_key(|warning| warning.span.start);
+  return warnings;
+}
+
+// A name a let, const, destructuring let or parameter declares
+struct Binding {
+  kind: &'static str,   // What declares it, for the warning: "Variable", "Constant" or "Parameter"
+  name: String,
+  id: NodeId,
+  span: Span,
+}
+
+struct Bindings<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  read: HashSet<NodeId>,   // The declarations something reads
+  declared: Vec<Binding>,
+}
+
+impl Bindings<'_> {
+  fn declare(&mut self, kind: &'static str, name: &str, id: NodeId, span: Span) {
+    self.declared.push(Binding { kind, name: name.to_string(), id, span });
+  }
+
+  fn read(&mut self, expr: &Expr) {
+    if let Some(declaration) = self.declarations.get(&expr.id) {
+      self.read.insert(*declaration);
+    }
+  }
+}
+
+impl Visitor for Bindings<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    match &expr.node {
+      ExprAST::Variable(_) | ExprAST::Call(..) => self.read(expr),
+      ExprAST::Function(_, params, ..) | ExprAST::Lambda(params, ..) => {
+        for param in params {
+          self.declare("Parameter", &param.node.name, param.id, param.span);
+        }
+      },
+      _ => {},
+    }
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Let(name, ..) => self.declare("Variable", name, stmt.id, stmt.span),
+      StmtAST::Const(name, ..) => self.declare("Constant", name, stmt.id, stmt.span),
+      StmtAST::LetTuple(names, _) => {
+        for name in names {
+          self.declare("Variable", &name.node.name, name.id, name.span);
+        }
+      },
+      // Assigning to a variable overwrites it without reading it, so only the value is walked
+      StmtAST::Assign(target, value) if matches!(target.node, ExprAST::Variable(_)) => {
+        self.visit_expr(value);
+        return;
+      },
+      _ => {},
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+// Warnings for declarations with the same name as another that's in scope, which they hide from the code
+// after them: a let in a block with the same name as one outside it, a parameter with the same name as a
+// global, or a second let of the same name in one block. Scopes are the ones the resolver uses. Names
+// starting with an underscore are left alone, like for unused variables
+pub fn shadowed_variables(program: &Program) -> Vec<Diagnostic> {
+  let mut shadows = Shadows { scopes: vec![HashMap::new()], warnings: Vec::new() };
+  for function in &program.functions {
+    if let ExprAST::Function(name, ..) = &function.node {
+      shadows.declare("Function", name, function.id, function.span);
+    }
+  }
+  let mut functions = program.functions.iter().peekable();
+  for stmt in &program.top_level {
+    while let Some(function) = functions.next_if(|function| function.span.start < stmt.span.start) {
+      shadows.visit_expr(function);
+    }
+    shadows.visit_stmt(stmt);
+  }
+  for function in functions {
+    shadows.visit_expr(function);
+  }
+  shadows.warnings.sort_by_key(|warning| warning.span.start);
+  return shadows.warnings;
+}
+
+struct Shadows {
+  scopes: Vec<HashMap<String, (NodeId, Span)>>,   // The names in scope with what declares them and where, innermost scope last
+  warnings: Vec<Diagnostic>,
+}
+
+impl Shadows {
+  fn declare(&mut self, kind: &'static str, name: &str, id: NodeId, span: Span) {
+    let earlier = self.scopes.iter().rev().find_map(|scope| scope.get(name));
+    if let Some((earlier_id, earlier_span)) = earlier && *earlier_id != id && !name.starts_with('_') {
+      let warning = Diagnostic::warning("shadow", format!("{} '{}' shadows an earlier declaration", kind, name), span)
+        .with_note(format!("'{}' is declared here", name), *earlier_span);
+      self.warnings.push(warning);
+    }
+    self.scopes.last_mut().unwrap().insert(name.to_string(), (id, span));
+  }
+
+  fn scoped(&mut self, params: &[Spanned<Param>], body: &[Stmt]) {
+    self.scopes.push(HashMap::new());
+    for param in params {
+      self.declare("Parameter", &param.node.name, param.id, param.span);
+    }
+    for stmt in body {
+      self.visit_stmt(stmt);
+    }
+    self.scopes.pop();
+  }
+}
+
+impl Visitor for Shadows {
+  fn visit_expr(&mut self, expr: &Expr) {
+    match &expr.node {
+      ExprAST::Function(name, params, _, body, _) => {
+        self.declare("Function", name, expr.id, expr.span);
+        self.scoped(params, body);
+      },
+      ExprAST::Lambda(params, _, body, _) => self.scoped(params, body),
+      _ => walk_expr(self, expr),
+    }
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Let(name, _, value) | StmtAST::Const(name, _, value) => {
+        self.visit_expr(value);
+        let kind = if let StmtAST::Let(..) = stmt.node { "Variable" } else { "Constant" };
+        self.declare(kind, name, stmt.id, stmt.span);
+      },
+      StmtAST::LetTuple(names, value) => {
+        self.visit_expr(value);
+        for name in names {
+          self.declare("Variable", &name.node.name, name.id, name.span);
+        }
+      },
+      StmtAST::Block(statements) => self.scoped(&[], statements),
+      _ => walk_stmt(self, stmt),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::resolver::resolve;
+
+  fn lint(source: &str) -> Vec<String> {
+    let tokens = lex(source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    return unused_variables(&program, &declarations).iter().map(|warning| warning.to_string()).collect();
+  }
+
+  #[test]
+  fn warn_about_unused_variables() {
+    let mut source: String = r#"
+def apply(f, x, _context) {
+  let (result, extra) = (f(x), 0)
+  let _ignored = 1
+  return result
+}
+const limit = 10
+let count = 0
+count = limit
+let double = def(n, m) { return n * 2 }
+let xs = [1]
+xs[0] = double(3, 4)
+"#.to_string();
+    assert_eq!(lint(&source), vec![
+      "Variable 'extra' is never read at 3:16",
+      "Variable 'count' is never read at 8:1",
+      "Parameter 'm' is never read at 10:21",
+    ]);
+
+    // Everything here is read somewhere
+    source = "def f(a) { return a }\nlet b = f(1)\nwhile b > 0 { let c = b; b = c - 1 }".to_string();
+    assert_eq!(lint(&source), Vec::<String>::new());
+  }
+
+  #[test]
+  fn warn_about_shadowed_variables() {
+    let source: String = r#"
+let count = 0
+def total(count, _step) {
+  let sum = 0
+  if count > 0 { let sum = count; print(sum) }
+  let f = def(x) { let x = 2; return x }
+  return f(sum)
+}
+let (a, count) = (1, 2)
+let _step = a
+{ const _step = 3; def total() { return _step } }
+"#.to_string();
+    let tokens = lex(&source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    let warnings: Vec<String> = shadowed_variables(&program).iter().map(|warning| warning.to_string()).collect();
+    assert_eq!(warnings, vec![
+      "Parameter 'count' shadows an earlier declaration at 3:11\n  note: 'count' is declared here at 2:1",
+      "Variable 'sum' shadows an earlier declaration at 5:18\n  note: 'sum' is declared here at 4:3",
+      "Variable 'x' shadows an earlier declaration at 6:20\n  note: 'x' is declared here at 6:15",
+      "Variable 'count' shadows an earlier declaration at 9:9\n  note: 'count' is declared here at 2:1",
+      "Function 'total' shadows an earlier declaration at 11:20\n  note: 'total' is declared here at 3:1",
+    ]);
+  }
+}
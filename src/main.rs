@@ -1,29 +1,206 @@
-use std::{env, fs};
+#![allow(clippy::needless_return)]
+
+use std::{env, path::Path, process};
 
 use rachit_cc::{
-  lexer::lex, 
-  parser::Parser,
+  diagnostic::{Diagnostic, Level, LintLevels, Severity},
+  lexer::{first_divergence, keyword_case_warnings, lex, lex_with_recovery, Token, TokenKind},
+  parser::{Parser, Program, StatementEnd, StmtAST},
+  passes::PassManager,
+  source::read_source,
+  symbols::{symbol_table, symbols_to_json},
 };
 
-fn compile(file_path: &String) -> Result<(), String> {
-  match fs::read_to_string(file_path) {
-    Err(msg) => return Err(msg.to_string()),
-    Ok(contents) => {
-      let tokens = lex(&contents)?;
-      let parser = Parser { tokens };
-      parser.parse()?;
-      return Ok(())
+// What the compiler writes to stdout instead of carrying on, chosen with --emit=<kind>
+#[derive(Clone, Copy, PartialEq)]
+enum Emit {
+  Nothing,
+  Ast,       // The parsed program as an indented S-expression tree
+  Symbols,   // The functions left once the program is compiled, with their mangled names and types, as JSON
+}
+
+// How to compile a file, from the command line
+struct Options {
+  emit: Emit,
+  optimize: u8,   // The optimization level, -O0 (the default) doing nothing and -O1 folding constants and removing dead code
+  verbose: bool,  // Whether to say what the optimizations changed
+  statement_end: StatementEnd,   // Whether statements end at newlines or need semicolons, with --require-semicolons
+  lints: LintLevels,
+}
+
+fn compile(file_path: &str, options: &Options) -> Result<(), Vec<String>> {
+  let contents = read_source(file_path).map_err(|msg| vec![msg])?;
+
+  // Report every lexical error in the file at once rather than making the user fix them one at a time
+  let (tokens, errors) = lex_with_recovery(&contents);
+  if !errors.is_empty() {
+    return Err(errors);
+  }
+  let keyword_warnings = keyword_case_warnings(&tokens);
+  match options.lints.severity("keyword-case") {
+    Some(Severity::Warning) => {
+      for msg in keyword_warnings {
+        eprintln!("warning: {} [keyword-case]", msg);
+      }
+    },
+    Some(_) if !keyword_warnings.is_empty() => return Err(keyword_warnings.iter().map(|msg| format!("{} [keyword-case]", msg)).collect()),
+    _ => {},
+  }
+
+  let mut parser = Parser::new(&tokens);
+  parser.set_statement_end(options.statement_end);
+  let mut program = parser.parse().map_err(|err| vec![err.to_string()])?;
+  check_imports(file_path, &program)?;
+  let context = PassManager::standard(options.optimize).set_lints(options.lints.clone()).run(&mut program);
+  for warning in &context.warnings {
+    eprintln!("{}: {}", warning.severity, describe_diagnostic(warning));
+  }
+  if options.verbose {
+    for note in &context.notes {
+      eprintln!("{}: {}", note.severity, describe_diagnostic(note));
+    }
+  }
+  if !context.errors.is_empty() {
+    return Err(messages(context.errors));
+  }
+  match options.emit {
+    Emit::Nothing => {},
+    Emit::Ast => print!("{}", program),
+    Emit::Symbols => print!("{}", symbols_to_json(&symbol_table(&program, &context.types))),
+  }
+  return Ok(())
+}
+
+fn messages(diagnostics: Vec<Diagnostic>) -> Vec<String> {
+  return diagnostics.iter().map(describe_diagnostic).collect();
+}
+
+// A diagnostic as the driver prints it, naming the lint that reported it, if any, so it can be allowed or
+// denied by name
+fn describe_diagnostic(diagnostic: &Diagnostic) -> String {
+  let mut text = format!("{} at {}:{}", diagnostic.message, diagnostic.span.line, diagnostic.span.column);
+  if let Some(lint) = diagnostic.lint {
+    text.push_str(&format!(" [{}]", lint));
+  }
+  for note in &diagnostic.notes {
+    text.push_str(&format!("\n  {}: {}", note.severity, note));
+  }
+  return text;
+}
+
+// Check that every file `program` imports exists, relative to the directory of the file importing it. Nothing
+// compiles the imported files yet, but a missing one is reported now rather than surfacing as an undefined
+// function later
+fn check_imports(file_path: &str, program: &Program) -> Result<(), Vec<String>> {
+  let directory = Path::new(file_path).parent().unwrap_or(Path::new(""));
+  let mut errors: Vec<String> = Vec::new();
+  for import in &program.imports {
+    if let StmtAST::Import(path) = &import.node && !directory.join(path).is_file() {
+      errors.push(format!("{}:{}:{}: Cannot find '{}' to import, looked for {}",
+        file_path, import.span.line, import.span.column, path, directory.join(path).display()));
     }
   }
+  match errors.is_empty() {
+    true => return Ok(()),
+    false => return Err(errors),
+  }
+}
+
+fn describe(file_path: &str, token: Option<&Token>) -> String {
+  match token {
+    Some(logged) => format!("{}:{}:{}: '{}'", file_path, logged.span.line, logged.span.column, logged.kind),
+    None => format!("{}: end of file", file_path),
+  }
+}
+
+// Compare the token streams of two files, returning whether they are equivalent
+fn diff_tokens(first_path: &str, second_path: &str) -> Result<bool, String> {
+  let first_source = read_source(first_path)?;
+  let second_source = read_source(second_path)?;
+  let first = lex(&first_source)?;
+  let second = lex(&second_source)?;
+
+  match first_divergence(&first, &second) {
+    None => {
+      let count = first.iter().filter(|token| !matches!(token.kind, TokenKind::DocComment(_))).count();
+      println!("Token streams are identical ({} tokens)", count);
+      return Ok(true)
+    },
+    Some((first_at, second_at)) => {
+      println!("Token streams diverge at token {} of the first file and token {} of the second", first_at, second_at);
+      println!("  {}", describe(first_path, first.get(first_at)));
+      println!("  {}", describe(second_path, second.get(second_at)));
+      return Ok(false)
+    }
+  }
+}
+
+// The file to compile and how, from the arguments after the program name
+fn parse_options(args: &[String]) -> Result<(String, Options), String> {
+  let mut options = Options { emit: Emit::Nothing, optimize: 0, verbose: false, statement_end: StatementEnd::Newline, lints: LintLevels::default() };
+  let mut file_path: Option<String> = None;
+  for arg in args {
+    match arg.as_str() {
+      "-O0" => options.optimize = 0,
+      "-O1" => options.optimize = 1,
+      "--verbose" => options.verbose = true,
+      "--require-semicolons" => options.statement_end = StatementEnd::Semicolon,
+      "-Werror" => options.lints.warnings_are_errors = true,
+      flag if flag.starts_with("-W") => options.lints.set(&flag["-W".len()..], Level::Warn)?,
+      flag if flag.starts_with("--allow=") => options.lints.set(&flag["--allow=".len()..], Level::Allow)?,
+      flag if flag.starts_with("--deny=") => options.lints.set(&flag["--deny=".len()..], Level::Deny)?,
+      flag if flag.starts_with("--emit=") => {
+        options.emit = match &flag["--emit=".len()..] {
+          "ast" => Emit::Ast,
+          "symbols" => Emit::Symbols,
+          kind => return Err(format!("Unknown --emit kind '{}', expected 'ast' or 'symbols'", kind)),
+        };
+      },
+      flag if flag.starts_with('-') => return Err(format!("Unknown option '{}'", flag)),
+      path => match file_path {
+        None => file_path = Some(path.to_string()),
+        Some(_) => return Err(format!("Expected one file to compile, found '{}' as well", path)),
+      },
+    }
+  }
+  match file_path {
+    Some(file_path) => return Ok((file_path, options)),
+    None => return Err("Please provide a valid SIL file".to_string()),
+  }
 }
 
 fn main() {
   let args: Vec<String> = env::args().collect();
 
-  if args.len() < 2 {
-    println!("Please provide a valid SIL file")
-  } else {
-    let file_path: &String = &args[1];
-    compile(file_path); // To-do, handle unused `Result`
+  let result = match args.get(1).map(String::as_str) {
+    None => {
+      println!("Please provide a valid SIL file");
+      return;
+    },
+    Some("diff-tokens") => {
+      if args.len() != 4 {
+        eprintln!("Usage: {} diff-tokens <a.sil> <b.sil>", args[0]);
+        process::exit(2);
+      }
+      diff_tokens(&args[2], &args[3]).map(|same| if same { 0 } else { 1 }).map_err(|msg| vec![msg])
+    },
+    Some(_) => match parse_options(&args[1..]) {
+      Ok((file_path, options)) => compile(&file_path, &options).map(|_| 0),
+      Err(msg) => {
+        eprintln!("error: {}", msg);
+        eprintln!("Usage: {} [--emit=ast|symbols] [-O0|-O1] [--verbose] [--require-semicolons] [-W<lint>] [-Werror] [--allow=<lint>] [--deny=<lint>] <file.sil>", args[0]);
+        process::exit(2);
+      }
+    },
+  };
+
+  match result {
+    Ok(code) => process::exit(code),
+    Err(errors) => {
+      for msg in errors {
+        eprintln!("error: {}", msg);
+      }
+      process::exit(1);
+    }
   }
 }
@@ -0,0 +1,93 @@
+use crate::diagnostic::Diagnostic;
+use crate::lexer::Span;
+use crate::parser::{ExprAST, NodeId, NodeMap, Program, Stmt, StmtAST};
+use crate::visit::{walk_stmt, Visitor};
+
+// Check that nothing in `program` assigns to a const, using the declarations the resolver found, with a
+// note pointing at the const. Only assigning to the name itself is an error: the elements of a const
+// array can still be changed. Variables and parameters can be assigned to freely
+pub fn check_assignments(program: &Program, declarations: &NodeMap<NodeId>) -> Result<(), Vec<Diagnostic>> {
+  let mut assignments = Assignments { constants: NodeMap::new(), assigned: Vec::new() };
+  for function in &program.functions {
+    assignments.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    assignments.visit_stmt(stmt);
+  }
+
+  // A function can assign to a const declared after it, so the assignments are only checked once every
+  // const is known
+  let mut errors: Vec<Diagnostic> = Vec::new();
+  for (name, id, span) in &assignments.assigned {
+    if let Some(declared_at) = declarations.get(id).and_then(|declaration| assignments.constants.get(declaration)) {
+      let error = Diagnostic::new(format!("Cannot assign to '{}', which is a constant", name), *span)
+        .with_note(format!("'{}' is declared as a constant here", name), *declared_at);
+      errors.push(error);
+    }
+  }
+  errors.sort_by_key(|error| error.span.start);
+  match errors.is_empty() {
+    true => return Ok(()),
+    false => return Err(errors),
+  }
+}
+
+struct Assignments {
+  constants: NodeMap<Span>,                  // Where every const is declared, by the id of its Const statement
+  assigned: Vec<(String, NodeId, Span)>,     // Every variable assigned to, by name, with its id and where it is
+}
+
+impl Visitor for Assignments {
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Const(..) => {
+        self.constants.insert(stmt.id, stmt.span);
+      },
+      StmtAST::Assign(target, _) => {
+        if let ExprAST::Variable(name) = &target.node {
+          self.assigned.push((name.clone(), target.id, target.span));
+        }
+      },
+      _ => {},
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::resolver::resolve;
+
+  fn check(source: &str) -> Vec<String> {
+    let tokens = lex(source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    return check_assignments(&program, &declarations).err().unwrap_or_default().iter().map(|error| error.to_string()).collect();
+  }
+
+  #[test]
+  fn reject_assignment_to_constants() {
+    let mut source: String = r#"
+const limit = 10
+const xs = [1, 2]
+def grow(n) {
+  limit = limit + n
+  n = 0
+  return n
+}
+xs[0] = 3
+xs = [limit]
+"#.to_string();
+    assert_eq!(check(&source), vec![
+      "Cannot assign to 'limit', which is a constant at 5:3\n  note: 'limit' is declared as a constant here at 2:1",
+      "Cannot assign to 'xs', which is a constant at 10:1\n  note: 'xs' is declared as a constant here at 3:1",
+    ]);
+
+    // A variable declared in an inner scope can share a const's name
+    source = "const k = 1\nif k > 0 { let k = 2; k = 3 }\ndef f(k) { k = k + 1; return k }".to_string();
+    assert_eq!(check(&source), Vec::<String>::new());
+  }
+}
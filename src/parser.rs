@@ -1,54 +1,2666 @@
-use crate::lexer::LoggedToken;
+use std::{collections::HashMap, fmt};
 
+use crate::lexer::{escape_string, Keyword, Span, Token, TokenKind};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ExprAST {
-  Number(f64),                                      // Numeric literals (floating point value)
-  Variable(String),                                 // Variable names (identifier string)
-  Binary(LoggedToken, Box<ExprAST>, Box<ExprAST>),  // Binary operator between two expressions (left, right)
-  Call(String, Vec<ExprAST>),                       // Function call (function name, argument list)
-  Function(String, Vec<ExprAST>, Vec<ExprAST>)      // Function definition (function name, list of identifiers/argument, a list of expressions for the body)
-}
-
-pub struct Parser {
-  pub tokens: Vec<LoggedToken>,
-}
-
-impl Parser {
-  pub fn parse(&self) -> Result<Vec<ExprAST>, String> {
-    todo!()
-  }
-
-  pub fn parseExpr(&self) -> Result<ExprAST, String> {
-    let LHS = self.parsePrimaryExpr()?;
-    // // Parse any expression (including both the primary ones and bin-ops)
-    // auto LHS = parsePrimaryExpr();
-  
-    // if (!LHS)
-    //   return nullptr;
-    
-    // auto expr = parseBinaryExpr(0, std::move(LHS));
-    // return expr;
-    todo!()
-  }
-
-  pub fn parsePrimaryExpr(&self) -> Result<ExprAST, String> {
-    todo!()
-  }
-}
-
-// std::unique_ptr<AST::Expr> Parser::parsePrimaryExpr() {
-//   // Parse basic, not bin-op expressions
-//   switch (peek().value().type) {
-//     default:
-//       return nullptr; // Todo: Throw an error
-//     case DecafScanning::TokenType::IDENTIFIER:
-//       return identifierExpr();
-//     case DecafScanning::TokenType::NUMBER:
-//       return numberExpr();
-//     case DecafScanning::TokenType::OPEN_PAREN:
-//       return groupingExpr();
-//     case DecafScanning::TokenType::IF:
-//       return conditionalExpr();
-//     case DecafScanning::TokenType::WHILE:
-//       return whileExpr();
-//   }
-// }
+  Integer(i64),                                             // Integer literals, 42
+  Float(f64),                                               // Floating point literals, 4.2
+  Boolean(bool),                                            // true and false
+  Variable(String),                                         // Variable names (identifier string)
+  Unary(UnaryOp, Box<Expr>),                                // Prefix operator applied to an expression
+  Binary(BinaryOp, Box<Expr>, Box<Expr>),                   // Binary operator between two expressions (left, right)
+  Logical(BinaryOp, Box<Expr>, Box<Expr>),                  // && or ||, which only evaluate the right side if the left doesn't decide the result
+  Conditional(Box<Expr>, Box<Expr>, Box<Expr>),             // cond ? a : b (condition, value if true, value if false)
+  Call(String, Vec<Expr>),                                  // Function call (function name, argument list)
+  ArrayLiteral(Vec<Expr>),                                  // Array of values (elements in order)
+  Index(Box<Expr>, Box<Expr>),                              // Element of an array (array, index)
+  Field(Box<Expr>, String),                                 // Field of a struct value, point.x (struct, field name)
+  MethodCall(Box<Expr>, String, Vec<Expr>),                 // Method called on a value, list.push(3) (receiver, method name, argument list)
+  StringLiteral(String),                                    // String literals (decoded text)
+  Interpolation(Vec<Expr>),                                 // Interpolated string (text pieces and embedded expressions, concatenated in order)
+  Function(String, Vec<Spanned<Param>>, Option<Type>, Vec<Stmt>, Vec<String>),  // Function definition (function name, parameters, declared return type, a list of statements for the body, captured variables)
+  Lambda(Vec<Spanned<Param>>, Option<Type>, Vec<Stmt>, Vec<String>),            // Anonymous function (parameters, declared return type, body, captured variables), a value that can be stored or passed
+  StructLiteral(String, Vec<(String, Expr)>),               // Construction of a struct value (struct name, field names and values in the order written)
+  Tuple(Vec<Expr>),                                         // Fixed group of values, (a, b), also what `return a, b` returns (elements in order)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StmtAST {
+  Let(String, Option<Type>, Expr),                          // Variable declaration (name, declared type, initial value)
+  Const(String, Option<Type>, Expr),                        // Constant declaration, a name that is never reassigned (name, declared type, value)
+  LetTuple(Vec<Spanned<Param>>, Expr),                      // Destructuring declaration, let (x, y) = f() (names with their declared types, tuple value)
+  Assign(Expr, Expr),                                       // Assignment to an existing l-value (target, new value)
+  Return(Option<Expr>),                                     // Return from a function (value, if any)
+  Break,                                                    // Leave the innermost loop
+  Continue,                                                 // Skip to the next iteration of the innermost loop
+  If(Expr, Box<Stmt>, Option<Box<Stmt>>),                   // Conditional (condition, then block, else block or else-if)
+  While(Expr, Box<Stmt>),                                   // Loop (condition, body block)
+  ExprStmt(Expr),                                           // Expression evaluated for its effects, including function definitions
+  Block(Vec<Stmt>),                                         // Braced list of statements
+  Struct(String, Vec<String>),                              // Struct declaration (struct name, field names)
+  Import(String),                                           // Import of another file (its path, `import math` importing "math.sil")
+}
+
+// An AST node together with the stretch of source it was parsed from, from its first token to its last, and
+// the id later passes know it by
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spanned<T> {
+  pub node: T,
+  pub span: Span,
+  pub id: NodeId,
+}
+
+// Identifies one node of a parsed program. The parser numbers every node it builds, parameters and types
+// included, from 0 in the order it finishes them, so a node's children come before it and parsing the same
+// source again gives the same ids
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId(pub u32);
+
+impl NodeId {
+  // The id of a node made outside the parser, such as by a pass rewriting the tree. It never appears in a
+  // NodeMap filled from parsed nodes
+  pub const DUMMY: NodeId = NodeId(u32::MAX);
+}
+
+// Information a pass works out about nodes, kept beside the tree rather than in it: the type of each
+// expression, the declaration each variable refers to and so on
+pub type NodeMap<T> = HashMap<NodeId, T>;
+
+pub type Expr = Spanned<ExprAST>;
+pub type Stmt = Spanned<StmtAST>;
+pub type Type = Spanned<TypeExpr>;
+
+// A type annotation as written. Annotations are optional everywhere, and the type checker checks the ones
+// there are against the types it infers
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TypeExpr {
+  Named(String),                                            // A type by name, built in or a struct: int, bool, Point
+  Array(Box<Type>),                                         // [element type]
+  Function(Vec<Type>, Option<Box<Type>>),                   // def(parameter types) -> return type, if it returns a value
+  Tuple(Vec<Type>),                                         // (element types), for tuples and multiple return values
+}
+
+// A function or lambda parameter, with its declared type if it has one
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Param {
+  pub name: String,
+  pub annotation: Option<Type>,
+}
+
+// A whole SIL file: the files it imports, the structs and functions it defines and the statements that run,
+// in order, when it does. Structs and functions are collected up front, so top-level code can use one defined
+// further down the file
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Program {
+  pub imports: Vec<Stmt>,     // Each a StmtAST::Import, in the order they are written
+  pub structs: Vec<Stmt>,     // Each a StmtAST::Struct, in the order they are declared
+  pub functions: Vec<Expr>,   // Each an ExprAST::Function, in the order they are defined
+  pub top_level: Vec<Stmt>,
+}
+
+impl Program {
+  // Split a file's top-level statements into its imports, its structs, its functions and everything else,
+  // checking that no struct or function is defined twice
+  pub fn from_statements(statements: Vec<Stmt>) -> Result<Program, ParseError> {
+    let mut imports: Vec<Stmt> = Vec::new();
+    let mut structs: Vec<Stmt> = Vec::new();
+    let mut functions: Vec<Expr> = Vec::new();
+    let mut top_level: Vec<Stmt> = Vec::new();
+    for statement in statements {
+      match statement.node {
+        StmtAST::Import(_) => imports.push(statement),
+        StmtAST::Struct(ref name, _) => {
+          if let Some(earlier) = structs.iter().find(|earlier| matches!(&earlier.node, StmtAST::Struct(other, _) if other == name)) {
+            let message = format!("Struct '{}', first defined on line {}, is defined again", name, earlier.span.line);
+            return Err(ParseError::invalid(message, statement.span));
+          }
+          structs.push(statement);
+        },
+        StmtAST::ExprStmt(function) if matches!(function.node, ExprAST::Function(..)) => {
+          if let ExprAST::Function(name, ..) = &function.node
+            && let Some(earlier) = functions.iter().find(|earlier| matches!(&earlier.node, ExprAST::Function(other, ..) if other == name)) {
+            let message = format!("Function '{}', first defined on line {}, is defined again", name, earlier.span.line);
+            return Err(ParseError::invalid(message, function.span));
+          }
+          functions.push(function);
+        },
+        node => top_level.push(Spanned { node, span: statement.span, id: statement.id }),
+      }
+    }
+    return Ok(Program { imports, structs, functions, top_level });
+  }
+}
+
+// Why parsing stopped and where. Display renders it as one line ending in the line and column, quoting the
+// offending token: "Expected ')' after the arguments to 'f' (opened at 3:5), found '{' at 3:14"
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+  pub kind: ParseErrorKind,
+  pub span: Span,   // The offending token, or the node an Invalid error is about
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+  Unexpected { expected: String, found: String },   // A token other than one the grammar allows here (what would have fit, the token as written)
+  EndOfFile { expected: String },                   // The tokens ran out partway through something
+  Invalid(String),                                  // Something that parses but isn't allowed, described in full
+}
+
+impl ParseError {
+  pub fn unexpected(expected: &str, token: &Token) -> Self {
+    let kind = ParseErrorKind::Unexpected { expected: expected.to_string(), found: token.kind.to_string() };
+    return ParseError { kind, span: token.span };
+  }
+
+  pub fn end_of_file(expected: &str, span: Span) -> Self {
+    return ParseError { kind: ParseErrorKind::EndOfFile { expected: expected.to_string() }, span };
+  }
+
+  pub fn invalid(message: String, span: Span) -> Self {
+    return ParseError { kind: ParseErrorKind::Invalid(message), span };
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match &self.kind {
+      ParseErrorKind::Unexpected { expected, found } => write!(f, "Expected {}, found '{}'", expected, found)?,
+      ParseErrorKind::EndOfFile { expected } => write!(f, "Expected {}, found the end of the file", expected)?,
+      ParseErrorKind::Invalid(message) => write!(f, "{}", message)?,
+    }
+    return write!(f, " at {}:{}", self.span.line, self.span.column);
+  }
+}
+
+impl<T> Spanned<T> {
+  pub fn new(node: T, span: Span) -> Self {
+    Spanned { node, span, id: NodeId::DUMMY }
+  }
+}
+
+// Like token streams in first_divergence, trees compare by structure alone, so the same program parsed
+// from differently formatted source compares equal, whatever their spans and ids
+impl<T: PartialEq> PartialEq for Spanned<T> {
+  fn eq(&self, other: &Self) -> bool {
+    return self.node == other.node;
+  }
+}
+
+// The operator of a binary expression. It displays as the token it was parsed from
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BinaryOp {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,   // Rounds toward zero between ints, so -7 / 2 is -3
+  Modulo,   // The remainder Divide leaves, taking the sign of the left operand: -7 % 3 is -1 and 7 % -3 is 1, for floats too
+  Power,
+  Equal,
+  NotEqual,
+  LessThan,
+  GreaterThan,
+  LessThanEqual,
+  GreaterThanEqual,
+  And,
+  Or,
+  BitAnd,
+  BitOr,
+  BitXor,
+  ShiftLeft,
+  ShiftRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Associativity {
+  Left,    // a - b - c is (a - b) - c
+  Right,   // a ** b ** c is a ** (b ** c)
+}
+
+// Every binary operator: the token it's written with, how tightly it binds (higher binds tighter) and which
+// way a chain of operators of the same precedence groups. A new operator only needs a BinaryOp variant and
+// an entry here. The levels are C's, bitwise operators included: `&`, `^` and `|` sit below the comparisons,
+// so `flags & mask == 0` is `flags & (mask == 0)`, and testing a bit needs parentheses as it does in C
+pub static BINARY_OPERATORS: [(TokenKind<'static>, BinaryOp, u8, Associativity); 19] = [
+  (TokenKind::Or, BinaryOp::Or, 1, Associativity::Left),
+  (TokenKind::And, BinaryOp::And, 2, Associativity::Left),
+  (TokenKind::BitOr, BinaryOp::BitOr, 3, Associativity::Left),
+  (TokenKind::BitXor, BinaryOp::BitXor, 4, Associativity::Left),
+  (TokenKind::BitAnd, BinaryOp::BitAnd, 5, Associativity::Left),
+  (TokenKind::EqualEqual, BinaryOp::Equal, 6, Associativity::Left),
+  (TokenKind::NotEqual, BinaryOp::NotEqual, 6, Associativity::Left),
+  (TokenKind::LessThan, BinaryOp::LessThan, 7, Associativity::Left),
+  (TokenKind::GreaterThan, BinaryOp::GreaterThan, 7, Associativity::Left),
+  (TokenKind::LessThanEqual, BinaryOp::LessThanEqual, 7, Associativity::Left),
+  (TokenKind::GreaterThanEqual, BinaryOp::GreaterThanEqual, 7, Associativity::Left),
+  (TokenKind::ShiftLeft, BinaryOp::ShiftLeft, 8, Associativity::Left),
+  (TokenKind::ShiftRight, BinaryOp::ShiftRight, 8, Associativity::Left),
+  (TokenKind::Plus, BinaryOp::Add, 9, Associativity::Left),
+  (TokenKind::Minus, BinaryOp::Subtract, 9, Associativity::Left),
+  (TokenKind::Times, BinaryOp::Multiply, 10, Associativity::Left),
+  (TokenKind::Divide, BinaryOp::Divide, 10, Associativity::Left),
+  (TokenKind::Modulo, BinaryOp::Modulo, 10, Associativity::Left),
+  (TokenKind::Power, BinaryOp::Power, 11, Associativity::Right),
+];
+
+impl BinaryOp {
+  // The operator a token stands for, if it is a binary operator
+  pub fn from_token(kind: &TokenKind) -> Option<BinaryOp> {
+    return BINARY_OPERATORS.iter().find(|entry| entry.0 == *kind).map(|entry| entry.1);
+  }
+
+  fn entry(self) -> &'static (TokenKind<'static>, BinaryOp, u8, Associativity) {
+    return BINARY_OPERATORS.iter().find(|entry| entry.1 == self).unwrap();
+  }
+
+  pub fn token(self) -> &'static TokenKind<'static> {
+    return &self.entry().0;
+  }
+
+  pub fn precedence(self) -> u8 {
+    return self.entry().2;
+  }
+
+  pub fn associativity(self) -> Associativity {
+    return self.entry().3;
+  }
+
+  // Whether the operator short-circuits, skipping its right operand when the left one decides the result.
+  // These parse into Logical nodes rather than Binary ones
+  pub fn is_logical(self) -> bool {
+    return matches!(self, BinaryOp::And | BinaryOp::Or);
+  }
+}
+
+impl fmt::Display for BinaryOp {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return write!(f, "{}", self.token());
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOp {
+  Negate,   // -x
+  Not,      // !x
+  BitNot,   // ~x
+}
+
+// Prefix operators bind tighter than every binary operator except **, so -x * y is (-x) * y but -x ** 2 is
+// -(x ** 2), as in mathematical notation
+pub const UNARY_PRECEDENCE: u8 = 10;
+
+impl UnaryOp {
+  pub fn from_token(kind: &TokenKind) -> Option<UnaryOp> {
+    match kind {
+      TokenKind::Minus => return Some(UnaryOp::Negate),
+      TokenKind::Not => return Some(UnaryOp::Not),
+      TokenKind::BitNot => return Some(UnaryOp::BitNot),
+      _ => return None,
+    }
+  }
+}
+
+impl fmt::Display for UnaryOp {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let token = match self {
+      UnaryOp::Negate => TokenKind::Minus,
+      UnaryOp::Not => TokenKind::Not,
+      UnaryOp::BitNot => TokenKind::BitNot,
+    };
+    return write!(f, "{}", token);
+  }
+}
+
+// Trees print as S-expressions, `(op operands...)`, with every statement of a block or function body on its
+// own line, indented under it
+impl fmt::Display for ExprAST {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return write_expr(f, self, 0);
+  }
+}
+
+impl fmt::Display for StmtAST {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return write_stmt(f, self, 0);
+  }
+}
+
+// Types print as they are written in source
+impl fmt::Display for TypeExpr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TypeExpr::Named(name) => return write!(f, "{}", name),
+      TypeExpr::Array(element) => return write!(f, "[{}]", element),
+      TypeExpr::Function(params, returns) => {
+        let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+        write!(f, "def({})", params.join(", "))?;
+        match returns {
+          Some(returns) => return write!(f, " -> {}", returns),
+          None => return Ok(()),
+        }
+      },
+      TypeExpr::Tuple(elements) => return write_tuple(f, elements),
+    }
+  }
+}
+
+// Tuples and tuple types print as in source, a one-element tuple with a trailing comma to tell it from a grouping
+fn write_tuple<T: fmt::Display>(f: &mut fmt::Formatter, elements: &[T]) -> fmt::Result {
+  let elements: Vec<String> = elements.iter().map(|element| element.to_string()).collect();
+  match elements.len() {
+    1 => return write!(f, "({},)", elements[0]),
+    _ => return write!(f, "({})", elements.join(", ")),
+  }
+}
+
+// A parameter prints as its name, or as (: name type) if it's annotated, the same as the name a let declares
+impl fmt::Display for Param {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return write_binding(f, &self.name, &self.annotation);
+  }
+}
+
+impl<T: fmt::Display> fmt::Display for Spanned<T> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    return self.node.fmt(f);
+  }
+}
+
+// Render a list of statements, one per line
+pub fn dump(statements: &[Stmt]) -> String {
+  let mut text = String::new();
+  for statement in statements {
+    text.push_str(&format!("{}\n", statement));
+  }
+  return text;
+}
+
+// A program prints its imports, then its structs, then its functions, then its top-level statements, one per line
+impl fmt::Display for Program {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", dump(&self.imports))?;
+    write!(f, "{}", dump(&self.structs))?;
+    for function in &self.functions {
+      writeln!(f, "{}", function)?;
+    }
+    return write!(f, "{}", dump(&self.top_level));
+  }
+}
+
+fn write_expr(f: &mut fmt::Formatter, expr: &ExprAST, depth: usize) -> fmt::Result {
+  match expr {
+    ExprAST::Integer(value) => return write!(f, "{}", value),
+    ExprAST::Float(value) => return write!(f, "{:?}", value),
+    ExprAST::Boolean(value) => return write!(f, "{}", value),
+    ExprAST::Variable(name) => return write!(f, "{}", name),
+    ExprAST::StringLiteral(text) => return write!(f, "\"{}\"", escape_string(text)),
+    ExprAST::Unary(op, operand) => {
+      write!(f, "({} ", op)?;
+      write_expr(f, &operand.node, depth)?;
+      return write!(f, ")");
+    },
+    ExprAST::Binary(op, lhs, rhs) | ExprAST::Logical(op, lhs, rhs) => {
+      write!(f, "({} ", op)?;
+      write_expr(f, &lhs.node, depth)?;
+      write!(f, " ")?;
+      write_expr(f, &rhs.node, depth)?;
+      return write!(f, ")");
+    },
+    ExprAST::Conditional(condition, then_value, else_value) => {
+      write!(f, "(? ")?;
+      write_expr(f, &condition.node, depth)?;
+      write!(f, " ")?;
+      write_expr(f, &then_value.node, depth)?;
+      write!(f, " ")?;
+      write_expr(f, &else_value.node, depth)?;
+      return write!(f, ")");
+    },
+    ExprAST::Call(name, args) => {
+      write!(f, "(call {}", name)?;
+      for arg in args {
+        write!(f, " ")?;
+        write_expr(f, &arg.node, depth)?;
+      }
+      return write!(f, ")");
+    },
+    ExprAST::ArrayLiteral(elements) => {
+      write!(f, "(array")?;
+      for element in elements {
+        write!(f, " ")?;
+        write_expr(f, &element.node, depth)?;
+      }
+      return write!(f, ")");
+    },
+    ExprAST::Index(target, index) => {
+      write!(f, "(index ")?;
+      write_expr(f, &target.node, depth)?;
+      write!(f, " ")?;
+      write_expr(f, &index.node, depth)?;
+      return write!(f, ")");
+    },
+    ExprAST::Field(target, field) => {
+      write!(f, "(field ")?;
+      write_expr(f, &target.node, depth)?;
+      return write!(f, " {})", field);
+    },
+    ExprAST::MethodCall(receiver, method, args) => {
+      write!(f, "(method ")?;
+      write_expr(f, &receiver.node, depth)?;
+      write!(f, " {}", method)?;
+      for arg in args {
+        write!(f, " ")?;
+        write_expr(f, &arg.node, depth)?;
+      }
+      return write!(f, ")");
+    },
+    ExprAST::Interpolation(parts) => {
+      write!(f, "(interpolate")?;
+      for part in parts {
+        write!(f, " ")?;
+        write_expr(f, &part.node, depth)?;
+      }
+      return write!(f, ")");
+    },
+    ExprAST::Function(name, params, returns, body, captures) => {
+      let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+      write!(f, "(def {} ({})", name, params.join(" "))?;
+      write_returns(f, returns)?;
+      write_captures(f, captures)?;
+      write_body(f, body, depth)?;
+      return write!(f, ")");
+    },
+    ExprAST::Lambda(params, returns, body, captures) => {
+      let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+      write!(f, "(lambda ({})", params.join(" "))?;
+      write_returns(f, returns)?;
+      write_captures(f, captures)?;
+      write_body(f, body, depth)?;
+      return write!(f, ")");
+    },
+    ExprAST::Tuple(elements) => {
+      write!(f, "(tuple")?;
+      for element in elements {
+        write!(f, " ")?;
+        write_expr(f, &element.node, depth)?;
+      }
+      return write!(f, ")");
+    },
+    ExprAST::StructLiteral(name, fields) => {
+      write!(f, "(new {}", name)?;
+      for (field, value) in fields {
+        write!(f, " ({} ", field)?;
+        write_expr(f, &value.node, depth)?;
+        write!(f, ")")?;
+      }
+      return write!(f, ")");
+    },
+  }
+}
+
+fn write_stmt(f: &mut fmt::Formatter, stmt: &StmtAST, depth: usize) -> fmt::Result {
+  match stmt {
+    StmtAST::Let(name, annotation, value) | StmtAST::Const(name, annotation, value) => {
+      let keyword = match stmt {
+        StmtAST::Const(..) => "const",
+        _ => "let",
+      };
+      write!(f, "({} ", keyword)?;
+      write_binding(f, name, annotation)?;
+      write!(f, " ")?;
+      write_expr(f, &value.node, depth)?;
+      return write!(f, ")");
+    },
+    StmtAST::LetTuple(names, value) => {
+      let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+      write!(f, "(let (tuple {}) ", names.join(" "))?;
+      write_expr(f, &value.node, depth)?;
+      return write!(f, ")");
+    },
+    StmtAST::Assign(target, value) => {
+      write!(f, "(= ")?;
+      write_expr(f, &target.node, depth)?;
+      write!(f, " ")?;
+      write_expr(f, &value.node, depth)?;
+      return write!(f, ")");
+    },
+    StmtAST::Return(None) => return write!(f, "(return)"),
+    StmtAST::Return(Some(value)) => {
+      write!(f, "(return ")?;
+      write_expr(f, &value.node, depth)?;
+      return write!(f, ")");
+    },
+    StmtAST::Break => return write!(f, "(break)"),
+    StmtAST::Continue => return write!(f, "(continue)"),
+    StmtAST::If(condition, then_block, else_block) => {
+      write!(f, "(if ")?;
+      write_expr(f, &condition.node, depth)?;
+      write_child(f, &then_block.node, depth + 1)?;
+      if let Some(else_block) = else_block {
+        write_child(f, &else_block.node, depth + 1)?;
+      }
+      return write!(f, ")");
+    },
+    StmtAST::While(condition, body) => {
+      write!(f, "(while ")?;
+      write_expr(f, &condition.node, depth)?;
+      write_child(f, &body.node, depth + 1)?;
+      return write!(f, ")");
+    },
+    StmtAST::ExprStmt(expr) => return write_expr(f, &expr.node, depth),
+    StmtAST::Block(statements) => {
+      write!(f, "(block")?;
+      write_body(f, statements, depth)?;
+      return write!(f, ")");
+    },
+    StmtAST::Struct(name, fields) => return write!(f, "(struct {} ({}))", name, fields.join(" ")),
+    StmtAST::Import(path) => return write!(f, "(import \"{}\")", escape_string(path)),
+  }
+}
+
+fn write_binding(f: &mut fmt::Formatter, name: &str, annotation: &Option<Type>) -> fmt::Result {
+  match annotation {
+    Some(annotation) => return write!(f, "(: {} {})", name, annotation),
+    None => return write!(f, "{}", name),
+  }
+}
+
+fn write_returns(f: &mut fmt::Formatter, returns: &Option<Type>) -> fmt::Result {
+  match returns {
+    Some(returns) => return write!(f, " (-> {})", returns),
+    None => return Ok(()),
+  }
+}
+
+// Functions that capture nothing, including every one the captures pass hasn't run over, leave the list out
+fn write_captures(f: &mut fmt::Formatter, captures: &[String]) -> fmt::Result {
+  match captures.is_empty() {
+    true => return Ok(()),
+    false => return write!(f, " (captures {})", captures.join(" ")),
+  }
+}
+
+// Write a statement on a new line, indented `depth` levels
+fn write_child(f: &mut fmt::Formatter, stmt: &StmtAST, depth: usize) -> fmt::Result {
+  write!(f, "\n{}", "  ".repeat(depth))?;
+  return write_stmt(f, stmt, depth);
+}
+
+fn write_body(f: &mut fmt::Formatter, statements: &[Stmt], depth: usize) -> fmt::Result {
+  for statement in statements {
+    write_child(f, &statement.node, depth + 1)?;
+  }
+  return Ok(());
+}
+
+// What ends a statement. With Newline, the default, a statement ends at a semicolon or at the end of its
+// line, and an expression carries on to the next line only after an operator left dangling at the end of
+// one. With Semicolon, newlines are whitespace like any other and every statement ends with a semicolon,
+// apart from one ending in a block like an if or a def
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StatementEnd {
+  #[default]
+  Newline,
+  Semicolon,
+}
+
+// Walks a borrowed token stream with a cursor. Productions look at the next token with peek() and check(),
+// step over it with advance() and require it with expect(). Doc comments are there for tools and are stepped
+// over, so the cursor never rests on one
+pub struct Parser<'t, 'src> {
+  tokens: &'t [Token<'src>],
+  position: usize,    // Index of the next token to parse
+  previous: Option<usize>,   // Index of the last token parsed, once there is one
+  depth: usize,       // How many statements and expressions the one being parsed is nested in
+  max_depth: usize,
+  struct_literals: bool,   // Whether `Name {` starts a struct literal here, rather than the block after an if or while condition
+  negate: Option<Span>,    // Where the `--` of `x--y` is, once it's been taken as a minus, for the operand after it to be negated
+  statement_end: StatementEnd,
+  next_id: u32,       // The id the next node built gets
+}
+
+// The parameters, return type and body that functions and lambdas have in common
+type FunctionParts = (Vec<Spanned<Param>>, Option<Type>, Vec<Stmt>);
+
+// How deeply statements and expressions may nest by default. Parsing recurses once per level, so without a
+// limit something like ten thousand `(` in a row would overflow the stack instead of reporting an error
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+impl<'t, 'src> Parser<'t, 'src> {
+  pub fn new(tokens: &'t [Token<'src>]) -> Self {
+    return Parser::with_max_depth(tokens, DEFAULT_MAX_DEPTH);
+  }
+
+  pub fn with_max_depth(tokens: &'t [Token<'src>], max_depth: usize) -> Self {
+    let mut parser = Parser { tokens, position: 0, previous: None, depth: 0, max_depth, struct_literals: true, negate: None, statement_end: StatementEnd::Newline, next_id: 0 };
+    parser.skip_doc_comments();
+    return parser;
+  }
+
+  // Choose what ends a statement, newlines by default
+  pub fn set_statement_end(&mut self, statement_end: StatementEnd) -> &mut Self {
+    self.statement_end = statement_end;
+    return self;
+  }
+
+  // Parse a whole program: a sequence of function definitions and top-level statements
+  pub fn parse(&mut self) -> Result<Program, ParseError> {
+    return Program::from_statements(self.parse_statements()?);
+  }
+
+  // Parse a whole file into its top-level statements in source order, function definitions included, for
+  // tools that care where each one is rather than what the program is
+  pub fn parse_statements(&mut self) -> Result<Vec<Stmt>, ParseError> {
+    let mut statements: Vec<Stmt> = Vec::new();
+    while self.skip_semicolons() {
+      let statement = match self.check(&TokenKind::Keyword(Keyword::Import)) {
+        true => self.parse_import()?,
+        false => self.parse_statement()?,
+      };
+      statements.push(statement);
+      self.expect_statement_end()?;
+    }
+    return Ok(statements);
+  }
+
+  // Parse `import "path/to/file.sil"` or `import name`, which imports "name.sil". Only the top level of a file
+  // can import, so this is called from parse_statements rather than parse_statement
+  fn parse_import(&mut self) -> Result<Stmt, ParseError> {
+    let start = self.peek_span();
+    self.advance();
+    let token = self.next("a module name or path after 'import'")?;
+    let path = match &token.kind {
+      TokenKind::StringLiteral(path) => path.to_string(),
+      TokenKind::Identifier(name) => format!("{}.sil", name),
+      _ => return Err(ParseError::unexpected("a module name or path after 'import'", token)),
+    };
+    return Ok(self.spanned(StmtAST::Import(path), start));
+  }
+
+  pub fn parse_statement(&mut self) -> Result<Stmt, ParseError> {
+    return self.nested(Parser::parse_statement_inner);
+  }
+
+  fn parse_statement_inner(&mut self) -> Result<Stmt, ParseError> {
+    let start = self.peek_span();
+    let statement = match self.peek_kind() {
+      Some(TokenKind::Keyword(Keyword::Def)) if !self.peek_at(1).is_some_and(|token| token.kind == TokenKind::OpenParen) => {
+        StmtAST::ExprStmt(self.parse_function()?)
+      },
+      Some(TokenKind::Keyword(Keyword::Let)) if self.peek_at(1).is_some_and(|token| token.kind == TokenKind::OpenParen) => {
+        self.advance();
+        self.advance();
+        let mut names: Vec<Spanned<Param>> = Vec::new();
+        while !self.check(&TokenKind::CloseParen) {
+          let name_start = self.peek_span();
+          let name = self.expect_identifier("to destructure into")?;
+          let param = Param { name, annotation: self.parse_annotation()? };
+          names.push(self.spanned(param, name_start));
+          if !self.check(&TokenKind::Comma) {
+            break;
+          }
+          self.advance();
+        }
+        self.expect(TokenKind::CloseParen, "after the names being destructured")?;
+        self.expect(TokenKind::Equal, "after the names being destructured")?;
+        StmtAST::LetTuple(names, self.parse_expr()?)
+      },
+      Some(TokenKind::Keyword(Keyword::Let)) => {
+        self.advance();
+        let name = self.expect_identifier("after 'let'")?;
+        let annotation = self.parse_annotation()?;
+        self.expect(TokenKind::Equal, "after the variable name")?;
+        StmtAST::Let(name, annotation, self.parse_expr()?)
+      },
+      Some(TokenKind::Keyword(Keyword::Const)) => {
+        self.advance();
+        let name = self.expect_identifier("after 'const'")?;
+        let annotation = self.parse_annotation()?;
+        self.expect(TokenKind::Equal, "after the constant name")?;
+        StmtAST::Const(name, annotation, self.parse_expr()?)
+      },
+      Some(TokenKind::Keyword(Keyword::Return)) => {
+        self.advance();
+        // A bare `return` is the end of its statement, and `return a, b` returns the tuple (a, b)
+        match self.at_statement_end() {
+          true => StmtAST::Return(None),
+          false => {
+            let value = self.parse_expr()?;
+            match self.check(&TokenKind::Comma) {
+              true => {
+                let start = value.span;
+                let mut elements: Vec<Expr> = vec![value];
+                while self.check(&TokenKind::Comma) {
+                  self.advance();
+                  elements.push(self.parse_expr()?);
+                }
+                StmtAST::Return(Some(self.spanned(ExprAST::Tuple(elements), start)))
+              },
+              false => StmtAST::Return(Some(value)),
+            }
+          },
+        }
+      },
+      Some(TokenKind::Keyword(Keyword::Break)) => {
+        self.advance();
+        StmtAST::Break
+      },
+      Some(TokenKind::Keyword(Keyword::Continue)) => {
+        self.advance();
+        StmtAST::Continue
+      },
+      Some(TokenKind::Keyword(Keyword::If)) => {
+        self.advance();
+        let condition = self.parse_condition()?;
+        let then_block = self.parse_block("after the if condition")?;
+        let mut else_block = None;
+        if self.check(&TokenKind::Keyword(Keyword::Else)) {
+          self.advance();
+          // `else if` chains nest, each if being the else branch of the one before it
+          let else_branch = match self.peek_kind() {
+            Some(TokenKind::Keyword(Keyword::If)) => self.parse_statement()?,
+            _ => self.parse_block("after 'else'")?,
+          };
+          else_block = Some(Box::new(else_branch));
+        }
+        StmtAST::If(condition, Box::new(then_block), else_block)
+      },
+      Some(TokenKind::Keyword(Keyword::While)) => {
+        self.advance();
+        let condition = self.parse_condition()?;
+        let body = self.parse_block("after the while condition")?;
+        StmtAST::While(condition, Box::new(body))
+      },
+      Some(TokenKind::Keyword(Keyword::Struct)) => {
+        self.advance();
+        let name = self.expect_identifier("as the struct name")?;
+        self.expect(TokenKind::OpenCurly, "after the struct name")?;
+        let mut fields: Vec<String> = Vec::new();
+        while !self.check(&TokenKind::CloseCurly) {
+          let field_span = self.peek_span();
+          let field = self.expect_identifier("as a field name")?;
+          if fields.contains(&field) {
+            return Err(ParseError::invalid(format!("Field '{}' of struct '{}' is declared twice", field, name), field_span));
+          }
+          fields.push(field);
+          if !self.check(&TokenKind::Comma) {
+            break;
+          }
+          self.advance();
+        }
+        self.expect(TokenKind::CloseCurly, "after the fields")?;
+        StmtAST::Struct(name, fields)
+      },
+      Some(TokenKind::Keyword(Keyword::Import)) => {
+        return Err(ParseError::invalid("Imports are only allowed at the top level of a file".to_string(), start));
+      },
+      Some(TokenKind::OpenCurly) => return self.parse_block("to start the block"),
+      _ => self.parse_expr_statement()?,
+    };
+    return Ok(self.spanned(statement, start));
+  }
+
+  // Parse a statement that starts with an expression: an assignment, an increment or decrement, or the
+  // expression on its own. Kept out of parse_statement_inner, which every nested statement goes through, so
+  // its frame stays small
+  fn parse_expr_statement(&mut self) -> Result<StmtAST, ParseError> {
+    // ++x and --x, which are the same as x++ and x--, as they can only be statements
+    if let Some(TokenKind::PlusPlus | TokenKind::MinusMinus) = self.peek_kind() {
+      let op = self.advance().unwrap();
+      let target_at = self.position;
+      let target = self.parse_postfix_expr()?;
+      return self.parse_step(target, target_at, op);
+    }
+    let expr_at = self.position;
+    let expr = self.parse_expr()?;
+    match self.peek_kind() {
+      Some(TokenKind::Equal) => {
+        check_assignable(&expr)?;
+        self.advance();
+        return Ok(StmtAST::Assign(expr, self.parse_expr()?));
+      },
+      Some(TokenKind::PlusPlus | TokenKind::MinusMinus) if !self.on_new_line() => {
+        let op = self.advance().unwrap();
+        return self.parse_step(expr, expr_at, op);
+      },
+      _ => return Ok(StmtAST::ExprStmt(expr)),
+    }
+  }
+
+  // Turn `target++` or `target--`, `op` being the operator, into `target = target + 1` or `target = target - 1`.
+  // The target is parsed a second time from `at`, where it starts, for the copy read on the right, so each
+  // of its nodes has an id of its own
+  fn parse_step(&mut self, target: Expr, at: usize, op: &Token) -> Result<StmtAST, ParseError> {
+    if check_assignable(&target).is_err() {
+      return Err(ParseError::invalid(format!("Cannot apply '{}' to this expression", op.kind), target.span));
+    }
+    let end = self.position;
+    self.position = at;
+    let read = self.parse_postfix_expr()?;
+    self.position = end;
+    let one = self.node_at(ExprAST::Integer(1), op.span);
+    let binary_op = match op.kind {
+      TokenKind::PlusPlus => BinaryOp::Add,
+      _ => BinaryOp::Subtract,
+    };
+    let span = read.span.to(op.span);
+    let value = self.node_at(ExprAST::Binary(binary_op, Box::new(read), Box::new(one)), span);
+    return Ok(StmtAST::Assign(target, value));
+  }
+
+  // Parse `{ statements }` into a Block
+  fn parse_block(&mut self, context: &str) -> Result<Stmt, ParseError> {
+    let start = self.peek_span();
+    let statements = self.parse_braced(context)?;
+    return Ok(self.spanned(StmtAST::Block(statements), start));
+  }
+
+  // Parse the statements between a pair of braces, `context` saying where the opening brace was expected
+  fn parse_braced(&mut self, context: &str) -> Result<Vec<Stmt>, ParseError> {
+    self.expect(TokenKind::OpenCurly, context)?;
+    let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+    let mut statements: Vec<Stmt> = Vec::new();
+    while self.skip_semicolons() && !self.check(&TokenKind::CloseCurly) {
+      statements.push(self.parse_statement()?);
+      self.expect_statement_end()?;
+    }
+    self.struct_literals = struct_literals;
+    self.expect(TokenKind::CloseCurly, "to end the block")?;
+    return Ok(statements);
+  }
+
+  // Parse the condition of an if or while. The `{` after it opens the block, so a struct literal in a
+  // condition has to be inside parentheses or brackets: `if p == (Point { x: 0, y: 0 }) { }`
+  fn parse_condition(&mut self) -> Result<Expr, ParseError> {
+    self.struct_literals = false;
+    let condition = self.parse_expr();
+    self.struct_literals = true;
+    return condition;
+  }
+
+  // Parse an expression between delimiters, where struct literals are allowed again even in a condition
+  fn parse_delimited_expr(&mut self) -> Result<Expr, ParseError> {
+    let struct_literals = std::mem::replace(&mut self.struct_literals, true);
+    let expr = self.parse_expr();
+    self.struct_literals = struct_literals;
+    return expr;
+  }
+
+  // Parse any expression, including both the primary ones and binary operations
+  pub fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+    return self.parse_conditional_expr();
+  }
+
+  // Parse `cond ? a : b`, which binds looser than every binary operator. It groups to the right, so
+  // `a ? b : c ? d : e` picks between b and `c ? d : e`
+  fn parse_conditional_expr(&mut self) -> Result<Expr, ParseError> {
+    let condition = self.parse_binary_expr(0)?;
+    if !self.check(&TokenKind::Question) || self.on_new_line() {
+      return Ok(condition);
+    }
+    self.advance();
+    let then_value = self.parse_expr()?;
+    self.expect(TokenKind::Colon, "between the branches of the conditional")?;
+    let else_value = self.nested(Parser::parse_conditional_expr)?;
+    let start = condition.span;
+    let expr = ExprAST::Conditional(Box::new(condition), Box::new(then_value), Box::new(else_value));
+    return Ok(self.spanned(expr, start));
+  }
+
+  // Parse the basic building blocks of expressions: literals, variables, calls and parenthesized groupings
+  pub fn parse_primary_expr(&mut self) -> Result<Expr, ParseError> {
+    let token = self.next("an expression")?;
+    let start = token.span;
+    let expr = match &token.kind {
+      TokenKind::Integer(num) => ExprAST::Integer(*num),
+      TokenKind::Float(num) => ExprAST::Float(*num),
+      TokenKind::Keyword(Keyword::True) => ExprAST::Boolean(true),
+      TokenKind::Keyword(Keyword::False) => ExprAST::Boolean(false),
+      TokenKind::StringLiteral(text) => ExprAST::StringLiteral(text.to_string()),
+      TokenKind::StringStart(text) => {
+        let first = self.node_at(ExprAST::StringLiteral(text.to_string()), start);
+        self.parse_interpolation(first)?
+      },
+      TokenKind::Identifier(name) => {
+        let name = name.to_string();
+        match self.peek_kind() {
+          Some(TokenKind::OpenParen) if !self.on_new_line() => self.parse_call(name)?,
+          Some(TokenKind::OpenCurly) if self.struct_literals && !self.on_new_line() => self.parse_struct_literal(name)?,
+          _ => ExprAST::Variable(name),
+        }
+      },
+      TokenKind::Keyword(Keyword::Def) => {
+        self.expect(TokenKind::OpenParen, "after 'def' in an anonymous function")?;
+        let (params, returns, body) = self.parse_params_and_body()?;
+        ExprAST::Lambda(params, returns, body, Vec::new())
+      },
+      TokenKind::OpenBracket => {
+        let elements = self.parse_comma_list(TokenKind::CloseBracket)?;
+        self.expect(TokenKind::CloseBracket, "to close the array")?;
+        ExprAST::ArrayLiteral(elements)
+      },
+      // A grouping's span takes in its parentheses. With a comma in it, it's a tuple instead: (), (a,), (a, b)
+      TokenKind::OpenParen if self.check(&TokenKind::CloseParen) => {
+        self.advance();
+        ExprAST::Tuple(Vec::new())
+      },
+      TokenKind::OpenParen => {
+        let expr = self.parse_delimited_expr()?;
+        match self.check(&TokenKind::Comma) {
+          true => {
+            self.advance();
+            let mut elements: Vec<Expr> = vec![expr];
+            elements.extend(self.parse_comma_list(TokenKind::CloseParen)?);
+            self.expect(TokenKind::CloseParen, "to close the tuple")?;
+            ExprAST::Tuple(elements)
+          },
+          false => {
+            self.expect(TokenKind::CloseParen, "to close the grouping")?;
+            expr.node
+          },
+        }
+      },
+      _ => return Err(ParseError::unexpected("an expression", token)),
+    };
+    return Ok(self.spanned(expr, start));
+  }
+
+  // Parse an expression whose binary operators all bind tighter than `min_precedence`. Each operator's right
+  // operand takes the operators binding tighter than it, or, for a right-associative operator, as tight
+  pub fn parse_binary_expr(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+    return self.nested(|parser| parser.parse_binary_expr_inner(min_precedence));
+  }
+
+  fn parse_binary_expr_inner(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+    let mut lhs = self.parse_unary_expr()?;
+    loop {
+      // `x--y` is x - (-y), as it was before `--` was a token of its own. A `--` with nothing after it is a
+      // decrement, which is left to the statement
+      let (op, negate) = match self.peek_kind() {
+        Some(TokenKind::MinusMinus) if self.operand_follows() => (Some(BinaryOp::Subtract), true),
+        kind => (kind.and_then(BinaryOp::from_token), false),
+      };
+      let op = match op {
+        Some(op) if op.precedence() > min_precedence && !self.on_new_line() => op,
+        _ => return Ok(lhs),
+      };
+      // A right-associative operator recurses for its right operand, which counts its level already
+      if op.associativity() == Associativity::Left {
+        self.deepen()?;
+      }
+      let token = self.advance().unwrap();
+      if negate {
+        self.negate = Some(token.span);
+      }
+
+      let rhs_precedence = match op.associativity() {
+        Associativity::Left => op.precedence(),
+        Associativity::Right => op.precedence() - 1,
+      };
+      let rhs = self.parse_binary_expr(rhs_precedence)?;
+      let start = lhs.span;
+      let expr = match op.is_logical() {
+        true => ExprAST::Logical(op, Box::new(lhs), Box::new(rhs)),
+        false => ExprAST::Binary(op, Box::new(lhs), Box::new(rhs)),
+      };
+      lhs = self.spanned(expr, start);
+    }
+  }
+
+  // Parse a primary expression with any number of prefix operators in front of it
+  pub fn parse_unary_expr(&mut self) -> Result<Expr, ParseError> {
+    if let Some(start) = self.negate.take() {
+      let operand = self.parse_binary_expr(UNARY_PRECEDENCE)?;
+      return Ok(self.spanned(ExprAST::Unary(UnaryOp::Negate, Box::new(operand)), start));
+    }
+    if let Some(TokenKind::PlusPlus | TokenKind::MinusMinus) = self.peek_kind() {
+      return Err(self.misplaced_step());
+    }
+    match self.peek_kind().and_then(UnaryOp::from_token) {
+      Some(op) => {
+        let start = self.peek_span();
+        self.advance();
+        let operand = self.parse_binary_expr(UNARY_PRECEDENCE)?;
+        return Ok(self.spanned(ExprAST::Unary(op, Box::new(operand)), start));
+      },
+      None => return self.parse_postfix_expr(),
+    }
+  }
+
+  // Parse a primary expression followed by any number of indexes, field accesses and method calls, which bind
+  // tighter than every prefix and binary operator: m[i][j], line.end.x, list.push(3)
+  fn parse_postfix_expr(&mut self) -> Result<Expr, ParseError> {
+    let depth = self.depth;
+    let mut expr = self.parse_primary_expr()?;
+    while !self.on_new_line() {
+      let start = expr.span;
+      let postfix = match self.peek_kind() {
+        Some(TokenKind::OpenBracket) => {
+          self.deepen()?;
+          self.advance();
+          let index = self.parse_delimited_expr()?;
+          self.expect(TokenKind::CloseBracket, "to close the index")?;
+          ExprAST::Index(Box::new(expr), Box::new(index))
+        },
+        Some(TokenKind::Dot) => {
+          self.deepen()?;
+          self.advance();
+          let name = self.expect_identifier("after '.'")?;
+          match self.check(&TokenKind::OpenParen) && !self.on_new_line() {
+            true => {
+              let (name, args) = self.parse_arguments(name)?;
+              ExprAST::MethodCall(Box::new(expr), name, args)
+            },
+            false => ExprAST::Field(Box::new(expr), name),
+          }
+        },
+        // A `++` or `--` ending the statement is left to the statement, and one starting an operand is the
+        // minus of `x--y`. Anywhere else, it's trying to change a value inside an expression
+        Some(TokenKind::PlusPlus | TokenKind::MinusMinus) => {
+          let subtraction = self.check(&TokenKind::MinusMinus) && self.operand_follows();
+          let ends_statement = match (self.peek(), self.peek_at(1)) {
+            (_, None) => true,
+            (Some(op), Some(next)) => matches!(next.kind, TokenKind::Semicolon | TokenKind::CloseCurly) || next.span.line > op.span.line,
+            (None, Some(_)) => false,
+          };
+          if !subtraction && !ends_statement {
+            return Err(self.misplaced_step());
+          }
+          break;
+        },
+        _ => break,
+      };
+      expr = self.spanned(postfix, start);
+    }
+    self.depth = depth;
+    return Ok(expr);
+  }
+
+  // Parse the arguments of a call to `name`, whose opening parenthesis is next
+  // Arguments are separated by commas, and a trailing comma is allowed: f(), f(a), f(a, b,)
+  fn parse_call(&mut self, name: String) -> Result<ExprAST, ParseError> {
+    let (name, args) = self.parse_arguments(name)?;
+    return Ok(ExprAST::Call(name, args));
+  }
+
+  // Parse the parenthesized arguments of a call or method call to `name`, handing the name back with them
+  fn parse_arguments(&mut self, name: String) -> Result<(String, Vec<Expr>), ParseError> {
+    let open = self.peek_span();
+    self.advance();
+    let args = self.parse_comma_list(TokenKind::CloseParen)?;
+
+    // A missing `)` is usually the fault of the call, not of whatever token follows its arguments, so point
+    // at where the call starts too
+    let context = format!("after the arguments to '{}' (opened at {}:{})", name, open.line, open.column);
+    self.expect(TokenKind::CloseParen, &context)?;
+    return Ok((name, args));
+  }
+
+  // Parse comma-separated expressions up to, but not including, `close`. A trailing comma is allowed
+  fn parse_comma_list(&mut self, close: TokenKind) -> Result<Vec<Expr>, ParseError> {
+    let mut exprs: Vec<Expr> = Vec::new();
+    while !self.check(&close) {
+      exprs.push(self.parse_delimited_expr()?);
+      if !self.check(&TokenKind::Comma) {
+        break;
+      }
+      self.advance();
+    }
+    return Ok(exprs);
+  }
+
+  // Parse the rest of an interpolated string, after its StringStart
+  fn parse_interpolation(&mut self, first: Expr) -> Result<ExprAST, ParseError> {
+    let mut parts: Vec<Expr> = vec![first];
+    loop {
+      parts.push(self.parse_delimited_expr()?);
+      let token = self.next("the rest of the string")?;
+      let span = token.span;
+      match &token.kind {
+        TokenKind::StringMiddle(text) => parts.push(self.node_at(ExprAST::StringLiteral(text.to_string()), span)),
+        TokenKind::StringEnd(text) => {
+          parts.push(self.node_at(ExprAST::StringLiteral(text.to_string()), span));
+          return Ok(ExprAST::Interpolation(parts));
+        },
+        _ => return Err(ParseError::unexpected("'}' to end the interpolated expression", token)),
+      }
+    }
+  }
+
+  // Parse the fields of a struct literal, `Point { x: 1, y: 2 }`, whose opening brace is next. A trailing
+  // comma is allowed
+  fn parse_struct_literal(&mut self, name: String) -> Result<ExprAST, ParseError> {
+    self.advance();
+    let mut fields: Vec<(String, Expr)> = Vec::new();
+    while !self.check(&TokenKind::CloseCurly) {
+      let field_span = self.peek_span();
+      let field = self.expect_identifier("as a field name")?;
+      if fields.iter().any(|(other, _)| *other == field) {
+        return Err(ParseError::invalid(format!("Field '{}' is given twice in '{}'", field, name), field_span));
+      }
+      self.expect(TokenKind::Colon, "after the field name")?;
+      fields.push((field, self.parse_delimited_expr()?));
+      if !self.check(&TokenKind::Comma) {
+        break;
+      }
+      self.advance();
+    }
+    self.expect(TokenKind::CloseCurly, &format!("to close the '{}' literal", name))?;
+    return Ok(ExprAST::StructLiteral(name, fields));
+  }
+
+  // Parse `def name(params) { body }`
+  fn parse_function(&mut self) -> Result<Expr, ParseError> {
+    let start = self.peek_span();
+    self.advance();
+    let name = self.expect_identifier("as the function name")?;
+
+    self.expect(TokenKind::OpenParen, "after the function name")?;
+    let (params, returns, body) = self.parse_params_and_body()?;
+    return Ok(self.spanned(ExprAST::Function(name, params, returns, body, Vec::new()), start));
+  }
+
+  // Parse the parameters, return type and body of a function or lambda, from after the `(` opening its
+  // parameters: `a: int, b) -> int { body }`
+  fn parse_params_and_body(&mut self) -> Result<FunctionParts, ParseError> {
+    let mut params: Vec<Spanned<Param>> = Vec::new();
+    while !self.check(&TokenKind::CloseParen) {
+      let param_start = self.peek_span();
+      let name = self.expect_identifier("as a parameter name")?;
+      let param = Param { name, annotation: self.parse_annotation()? };
+      params.push(self.spanned(param, param_start));
+      if !self.check(&TokenKind::Comma) {
+        break;
+      }
+      self.advance();
+    }
+    self.expect(TokenKind::CloseParen, "after the parameters")?;
+    let returns = match self.check(&TokenKind::Arrow) {
+      true => {
+        self.advance();
+        Some(self.parse_type()?)
+      },
+      false => None,
+    };
+
+    let body = self.parse_braced("to start the function body")?;
+    return Ok((params, returns, body));
+  }
+
+  // Parse the `: type` after a name being declared, if it has one
+  fn parse_annotation(&mut self) -> Result<Option<Type>, ParseError> {
+    if !self.check(&TokenKind::Colon) {
+      return Ok(None);
+    }
+    self.advance();
+    return Ok(Some(self.parse_type()?));
+  }
+
+  // Parse a type: a name, `[element]`, `(elements)` or `def(params) -> returns`
+  fn parse_type(&mut self) -> Result<Type, ParseError> {
+    return self.nested(Parser::parse_type_inner);
+  }
+
+  fn parse_type_inner(&mut self) -> Result<Type, ParseError> {
+    let token = self.next("a type")?;
+    let start = token.span;
+    let ty = match &token.kind {
+      TokenKind::Identifier(name) => TypeExpr::Named(name.to_string()),
+      TokenKind::OpenBracket => {
+        let element = self.parse_type()?;
+        self.expect(TokenKind::CloseBracket, "to close the array type")?;
+        TypeExpr::Array(Box::new(element))
+      },
+      // Like expressions, a single type in parentheses is only a tuple with a comma after it
+      TokenKind::OpenParen => {
+        let mut elements: Vec<Type> = Vec::new();
+        let mut comma = false;
+        while !self.check(&TokenKind::CloseParen) {
+          elements.push(self.parse_type()?);
+          if !self.check(&TokenKind::Comma) {
+            break;
+          }
+          self.advance();
+          comma = true;
+        }
+        self.expect(TokenKind::CloseParen, "to close the tuple type")?;
+        match (elements.len(), comma) {
+          (1, false) => elements.pop().unwrap().node,
+          _ => TypeExpr::Tuple(elements),
+        }
+      },
+      TokenKind::Keyword(Keyword::Def) => {
+        self.expect(TokenKind::OpenParen, "after 'def' in a function type")?;
+        let mut params: Vec<Type> = Vec::new();
+        while !self.check(&TokenKind::CloseParen) {
+          params.push(self.parse_type()?);
+          if !self.check(&TokenKind::Comma) {
+            break;
+          }
+          self.advance();
+        }
+        self.expect(TokenKind::CloseParen, "after the parameter types")?;
+        let mut returns = None;
+        if self.check(&TokenKind::Arrow) {
+          self.advance();
+          returns = Some(Box::new(self.parse_type()?));
+        }
+        TypeExpr::Function(params, returns)
+      },
+      _ => return Err(ParseError::unexpected("a type", token)),
+    };
+    return Ok(self.spanned(ty, start));
+  }
+
+  // Run `parse` one level deeper, failing instead if that goes past the depth limit. Every recursive
+  // production passes through parse_statement or parse_binary_expr, which is where the levels are counted,
+  // apart from the else branch of a conditional, which counts its own. Any levels `parse` adds with deepen
+  // are dropped again once it's done
+  fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+    let depth = self.depth;
+    self.deepen()?;
+    let result = parse(self);
+    self.depth = depth;
+    return result;
+  }
+
+  // Count a level for the next node, failing if that goes past the depth limit. Chains like `1 + 2 + 3` and
+  // `m[i][j]` are parsed in a loop rather than by recursing, but each link nests the chain so far one level
+  // deeper in the tree, and every pass after parsing recurses through all of them
+  fn deepen(&mut self) -> Result<(), ParseError> {
+    if self.depth >= self.max_depth {
+      return Err(ParseError::invalid(format!("Nesting too deep, the limit is {} levels", self.max_depth), self.peek_span()));
+    }
+    self.depth += 1;
+    return Ok(());
+  }
+
+  // Wrap a node parsed from the token at `start` up to the last token consumed
+  fn spanned<T>(&mut self, node: T, start: Span) -> Spanned<T> {
+    let end = self.previous().span;
+    return self.node_at(node, start.to(end));
+  }
+
+  // Wrap a node parsed from exactly `span`, giving it the next id
+  fn node_at<T>(&mut self, node: T, span: Span) -> Spanned<T> {
+    let id = NodeId(self.next_id);
+    self.next_id += 1;
+    return Spanned { node, span, id };
+  }
+
+  // Span of the next token, or an empty span just past the last one at the end of the file
+  fn peek_span(&self) -> Span {
+    match self.peek() {
+      Some(token) => return token.span,
+      None => return self.tokens.last().map(|token| just_past(token.span)).unwrap_or(Span { start: 0, end: 0, line: 1, column: 1 }),
+    }
+  }
+
+  fn peek(&self) -> Option<&'t Token<'src>> {
+    return self.peek_at(0);
+  }
+
+  // The token `ahead` tokens after the next one
+  fn peek_at(&self, ahead: usize) -> Option<&'t Token<'src>> {
+    let mut tokens = self.tokens[self.position..].iter().filter(|token| !matches!(token.kind, TokenKind::DocComment(_)));
+    return tokens.nth(ahead);
+  }
+
+  fn peek_kind(&self) -> Option<&'t TokenKind<'src>> {
+    return self.peek().map(|token| &token.kind);
+  }
+
+  // Whether the next token is a `kind`
+  fn check(&self, kind: &TokenKind) -> bool {
+    return self.peek_kind() == Some(kind);
+  }
+
+  // Step past the next token, returning it
+  fn advance(&mut self) -> Option<&'t Token<'src>> {
+    let token = self.peek();
+    if token.is_some() {
+      self.previous = Some(self.position);
+      self.position += 1;
+      self.skip_doc_comments();
+    }
+    return token;
+  }
+
+  // The last token stepped past, which only statements and expressions already parsed ask for
+  fn previous(&self) -> &'t Token<'src> {
+    return &self.tokens[self.previous.unwrap()];
+  }
+
+  fn skip_doc_comments(&mut self) {
+    while let Some(TokenKind::DocComment(_)) = self.tokens.get(self.position).map(|token| &token.kind) {
+      self.position += 1;
+    }
+  }
+
+  // Take the next token, `expected` describing what should have been there if the tokens have run out
+  fn next(&mut self, expected: &str) -> Result<&'t Token<'src>, ParseError> {
+    let span = self.peek_span();
+    return self.advance().ok_or_else(|| ParseError::end_of_file(expected, span));
+  }
+
+  fn expect(&mut self, kind: TokenKind, context: &str) -> Result<(), ParseError> {
+    let expected = format!("'{}' {}", kind, context);
+    let token = self.next(&expected)?;
+    if token.kind != kind {
+      return Err(ParseError::unexpected(&expected, token));
+    }
+    return Ok(());
+  }
+
+  fn expect_identifier(&mut self, context: &str) -> Result<String, ParseError> {
+    let expected = format!("an identifier {}", context);
+    let token = self.next(&expected)?;
+    match &token.kind {
+      TokenKind::Identifier(name) => return Ok(name.to_string()),
+      _ => return Err(ParseError::unexpected(&expected, token)),
+    }
+  }
+
+  // Whether the next token starts a new line that ends the statement before it. An operator, call or index
+  // there begins the next statement rather than continuing the expression on the line before, so a line
+  // ending in an operator is the only way to carry an expression on to the next one. When statements end
+  // with semicolons, a new line is never significant
+  fn on_new_line(&self) -> bool {
+    match (self.peek(), self.previous, self.statement_end) {
+      (Some(token), Some(previous), StatementEnd::Newline) => return token.span.line > self.tokens[previous].span.line,
+      _ => return false,
+    }
+  }
+
+  // Whether the statement being parsed can end here. Statements end at a semicolon, at the end of a line, at
+  // the `}` closing their block or at the end of the file
+  fn at_statement_end(&self) -> bool {
+    match self.peek_kind() {
+      None | Some(TokenKind::Semicolon | TokenKind::CloseCurly) => return true,
+      Some(_) => return self.on_new_line(),
+    }
+  }
+
+  // Require the end of a statement after one, unless it finished with a block: `if x { } y = 1` needs no
+  // separator, `x = 1 y = 2` does, and so does `x = 1` at the end of a line when semicolons are required
+  fn expect_statement_end(&self) -> Result<(), ParseError> {
+    let after_block = self.previous().kind == TokenKind::CloseCurly;
+    let ended = match self.statement_end {
+      StatementEnd::Newline => self.at_statement_end(),
+      StatementEnd::Semicolon => self.check(&TokenKind::Semicolon),
+    };
+    if after_block || ended {
+      return Ok(());
+    }
+    if let Some(TokenKind::PlusPlus | TokenKind::MinusMinus) = self.peek_kind() {
+      return Err(self.misplaced_step());
+    }
+    match self.statement_end {
+      StatementEnd::Newline => return Err(ParseError::unexpected("';' or a new line after the statement", &self.tokens[self.position])),
+      // Point at where the semicolon goes, which is easier to act on than the token after it
+      StatementEnd::Semicolon => {
+        let found = self.peek().map_or("the end of the file".to_string(), |token| format!("'{}'", token.kind));
+        return Err(ParseError::invalid(format!("Missing ';' after the statement, before {}", found), just_past(self.previous().span)));
+      },
+    }
+  }
+
+  // Whether the token after the next one is on the same line and can start an expression
+  fn operand_follows(&self) -> bool {
+    let (Some(op), Some(next)) = (self.peek(), self.peek_at(1)) else { return false };
+    if next.span.line > op.span.line {
+      return false;
+    }
+    return matches!(next.kind,
+      TokenKind::Integer(_) | TokenKind::Float(_) | TokenKind::StringLiteral(_) | TokenKind::StringStart(_) | TokenKind::Identifier(_) |
+      TokenKind::Keyword(Keyword::True | Keyword::False | Keyword::Def) | TokenKind::OpenParen | TokenKind::OpenBracket |
+      TokenKind::Minus | TokenKind::Not | TokenKind::BitNot);
+  }
+
+  // The error for the `++` or `--` next, which is somewhere other than a statement of its own. There are no
+  // assignments inside expressions, so `let y = x++` and `f(--x)` can't be written
+  fn misplaced_step(&self) -> ParseError {
+    let token = &self.tokens[self.position];
+    let message = format!("'{}' can only be used as a statement of its own, as in 'x{}' or '{}x'", token.kind, token.kind, token.kind);
+    return ParseError::invalid(message, token.span);
+  }
+
+  // Step over any semicolons separating statements, returning whether there are tokens left
+  fn skip_semicolons(&mut self) -> bool {
+    while self.check(&TokenKind::Semicolon) {
+      self.advance();
+    }
+    return self.position < self.tokens.len();
+  }
+}
+
+// An empty span right after `span`, where something missing after it would go. Its column counts the bytes of
+// `span`, which are its characters unless it holds non-ASCII text
+fn just_past(span: Span) -> Span {
+  return Span { start: span.end, column: span.column + (span.end - span.start) as u32, ..span };
+}
+
+// Whether `target` is something a value can be stored into: a variable, an element of an array or a field
+// of a struct
+fn check_assignable(target: &Expr) -> Result<(), ParseError> {
+  match &target.node {
+    ExprAST::Variable(_) | ExprAST::Index(..) | ExprAST::Field(..) => return Ok(()),
+    _ => return Err(ParseError::invalid("Cannot assign to this expression".to_string(), target.span)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+
+  fn parse(source: &str) -> Result<Vec<Stmt>, String> {
+    let tokens = lex(source)?;
+    return Parser::new(&tokens).parse_statements().map_err(|err| err.to_string());
+  }
+
+  // Parse a program made only of expression statements into its expressions
+  fn parse_exprs(source: &str) -> Result<Vec<Expr>, String> {
+    let statements = parse(source)?;
+    return Ok(statements.into_iter().map(|statement| match statement.node {
+      StmtAST::ExprStmt(expr) => expr,
+      other => panic!("expected an expression statement, found {:?}", other),
+    }).collect());
+  }
+
+  // Wrap a node in a placeholder span, for expected trees, which compare without their spans
+  fn s<T>(node: T) -> Spanned<T> {
+    Spanned::new(node, Span { start: 0, end: 0, line: 0, column: 0 })
+  }
+
+  fn num(value: i64) -> Expr {
+    s(ExprAST::Integer(value))
+  }
+
+  fn float(value: f64) -> Expr {
+    s(ExprAST::Float(value))
+  }
+
+  fn var(name: &str) -> Expr {
+    s(ExprAST::Variable(name.to_string()))
+  }
+
+  fn param(name: &str) -> Spanned<Param> {
+    s(Param { name: name.to_string(), annotation: None })
+  }
+
+  // A binary expression as the parser builds it, a Logical node for && and || and a Binary one otherwise
+  fn bin(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+    match op.is_logical() {
+      true => s(ExprAST::Logical(op, Box::new(lhs), Box::new(rhs))),
+      false => s(ExprAST::Binary(op, Box::new(lhs), Box::new(rhs))),
+    }
+  }
+
+  #[test]
+  fn parse_precedence() {
+    let mut source: String = "4 + 5 * 6".to_string();
+    let result = parse_exprs(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![bin(BinaryOp::Add, num(4), bin(BinaryOp::Multiply, num(5), num(6)))]);
+
+    source = "a * (b - c) < d == e || f".to_string();
+    assert_eq!(parse_exprs(&source).unwrap(), vec![
+      bin(BinaryOp::Or,
+        bin(BinaryOp::Equal,
+          bin(BinaryOp::LessThan, bin(BinaryOp::Multiply, var("a"), bin(BinaryOp::Subtract, var("b"), var("c"))), var("d")),
+          var("e")),
+        var("f")),
+    ]);
+  }
+
+  #[test]
+  fn parse_logical_operators() {
+    let source: String = "a && b || !c & d".to_string();
+    let result = parse_exprs(&source);
+    assert_eq!(result.is_ok(), true);
+    let logical = |op, lhs, rhs| s(ExprAST::Logical(op, Box::new(lhs), Box::new(rhs)));
+    let binary = |op, lhs, rhs| s(ExprAST::Binary(op, Box::new(lhs), Box::new(rhs)));
+    assert_eq!(result.unwrap(), vec![
+      logical(BinaryOp::Or,
+        logical(BinaryOp::And, var("a"), var("b")),
+        binary(BinaryOp::BitAnd, unary(UnaryOp::Not, var("c")), var("d"))),
+    ]);
+    assert_ne!(logical(BinaryOp::And, var("a"), var("b")), binary(BinaryOp::And, var("a"), var("b")));
+  }
+
+  #[test]
+  fn parse_conditionals() {
+    let mut source: String = "x = a < b ? a : b\ny = a ? b : c ? d : e\nz = a ? b ? c : d : e\nw = -(a || b ? 1 : 2) + f(c ? d : e)".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(dump(&result.unwrap()), "(= x (? (< a b) a b))\n(= y (? a b (? c d e)))\n(= z (? a (? b c d) e))\n(= w (+ (- (? (|| a b) 1 2)) (call f (? c d e))))\n");
+
+    source = "a ? b".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ':' between the branches of the conditional, found the end of the file at 1:6");
+    source = "a ? b c".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ':' between the branches of the conditional, found 'c' at 1:7");
+    source = "a ? b : c = 1".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Cannot assign to this expression at 1:1");
+
+    source = format!("x = {}1", "a ? b : ".repeat(10000));
+    assert_eq!(parse(&source).err().unwrap(), "Nesting too deep, the limit is 256 levels at 1:2045");
+  }
+
+  #[test]
+  fn parse_every_operator_pair() {
+    for (_, first, first_precedence, _) in &BINARY_OPERATORS {
+      for (_, second, second_precedence, associativity) in &BINARY_OPERATORS {
+        let source = format!("a {} b {} c", first, second);
+        let groups_left = first_precedence > second_precedence
+          || (first_precedence == second_precedence && *associativity == Associativity::Left);
+        let expected = match groups_left {
+          true => bin(*second, bin(*first, var("a"), var("b")), var("c")),
+          false => bin(*first, var("a"), bin(*second, var("b"), var("c"))),
+        };
+        assert_eq!(parse_exprs(&source).unwrap(), vec![expected], "{}", source);
+      }
+    }
+
+    // The table's levels are C's, loosest first, so each operator groups under every one on a looser level
+    let levels = [vec!["||"], vec!["&&"], vec!["|"], vec!["^"], vec!["&"], vec!["==", "!="], vec!["<", ">", "<=", ">="],
+      vec!["<<", ">>"], vec!["+", "-"], vec!["*", "/", "%"], vec!["**"]];
+    for (level, looser) in levels.iter().enumerate() {
+      for tighter in levels[level + 1..].iter().flatten() {
+        for loose in looser {
+          let source: String = format!("a {} b {} c\na {} b {} c", loose, tighter, tighter, loose);
+          let expected = format!("({} a ({} b c))\n({} ({} a b) c)\n", loose, tighter, loose, tighter);
+          assert_eq!(dump(&parse(&source).unwrap()), expected);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn parse_associativity() {
+    let mut source: String = "1 - 2 - 3".to_string();
+    assert_eq!(parse_exprs(&source).unwrap(), vec![bin(BinaryOp::Subtract, bin(BinaryOp::Subtract, num(1), num(2)), num(3))]);
+
+    source = "2 ** 3 ** 2".to_string();
+    assert_eq!(parse_exprs(&source).unwrap(), vec![bin(BinaryOp::Power, num(2), bin(BinaryOp::Power, num(3), num(2)))]);
+
+    source = "1 + 2 ** 3 * 4".to_string();
+    assert_eq!(parse_exprs(&source).unwrap(), vec![
+      bin(BinaryOp::Add, num(1), bin(BinaryOp::Multiply, bin(BinaryOp::Power, num(2), num(3)), num(4))),
+    ]);
+  }
+
+  fn unary(op: UnaryOp, operand: Expr) -> Expr {
+    s(ExprAST::Unary(op, Box::new(operand)))
+  }
+
+  #[test]
+  fn parse_unary_operators() {
+    let mut source: String = "-x; !flag; -(a + b); ~mask".to_string();
+    let result = parse_exprs(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      unary(UnaryOp::Negate, var("x")),
+      unary(UnaryOp::Not, var("flag")),
+      unary(UnaryOp::Negate, bin(BinaryOp::Add, var("a"), var("b"))),
+      unary(UnaryOp::BitNot, var("mask")),
+    ]);
+
+    // Unary minus binds tighter than binary minus and *, but not **
+    source = "a - -b * c; -x ** 2; 2 ** -1".to_string();
+    assert_eq!(parse_exprs(&source).unwrap(), vec![
+      bin(BinaryOp::Subtract, var("a"), bin(BinaryOp::Multiply, unary(UnaryOp::Negate, var("b")), var("c"))),
+      unary(UnaryOp::Negate, bin(BinaryOp::Power, var("x"), num(2))),
+      bin(BinaryOp::Power, num(2), unary(UnaryOp::Negate, num(1))),
+    ]);
+
+    source = "!!done && - - 1".to_string();
+    assert_eq!(parse_exprs(&source).unwrap(), vec![
+      bin(BinaryOp::And, unary(UnaryOp::Not, unary(UnaryOp::Not, var("done"))), unary(UnaryOp::Negate, unary(UnaryOp::Negate, num(1)))),
+    ]);
+  }
+
+  #[test]
+  fn parse_calls() {
+    let source: String = "fib(x-1) + fib(x-2); f(); g(1.5, (2 + 3) * 4)".to_string();
+    let result = parse_exprs(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      bin(BinaryOp::Add,
+        call("fib", vec![bin(BinaryOp::Subtract, var("x"), num(1))]),
+        call("fib", vec![bin(BinaryOp::Subtract, var("x"), num(2))])),
+      call("f", vec![]),
+      call("g", vec![float(1.5), bin(BinaryOp::Multiply, bin(BinaryOp::Add, num(2), num(3)), num(4))]),
+    ]);
+  }
+
+  #[test]
+  fn parse_call_arguments() {
+    let mut source: String = "f(a,); g(h(i(1), j()),\n  k,\n)".to_string();
+    let result = parse_exprs(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      call("f", vec![var("a")]),
+      call("g", vec![call("h", vec![call("i", vec![num(1)]), call("j", vec![])]), var("k")]),
+    ]);
+
+    source = "def add(a, b,) { a + b }".to_string();
+    assert_eq!(parse(&source).is_ok(), true);
+
+    // Only one trailing comma, and only after an argument
+    source = "f(,)".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an expression, found ',' at 1:3");
+    source = "f(a,,)".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an expression, found ',' at 1:5");
+
+    source = "x = max(\n  fib(1),\n  fib(2)\n  fib(3))".to_string();
+    assert_eq!(parse(&source).err().unwrap(),
+      "Expected ')' after the arguments to 'max' (opened at 1:8), found 'fib' at 4:3");
+  }
+
+  fn index(target: Expr, index: Expr) -> Expr {
+    s(ExprAST::Index(Box::new(target), Box::new(index)))
+  }
+
+  #[test]
+  fn parse_arrays() {
+    let mut source: String = "[]; [1, [2, 3],]; m[i][j + 1]; -a[0] ** 2; f(x)[0]; [1, 2][0]".to_string();
+    let result = parse_exprs(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      s(ExprAST::ArrayLiteral(vec![])),
+      s(ExprAST::ArrayLiteral(vec![num(1), s(ExprAST::ArrayLiteral(vec![num(2), num(3)]))])),
+      index(index(var("m"), var("i")), bin(BinaryOp::Add, var("j"), num(1))),
+      unary(UnaryOp::Negate, bin(BinaryOp::Power, index(var("a"), num(0)), num(2))),
+      index(call("f", vec![var("x")]), num(0)),
+      index(s(ExprAST::ArrayLiteral(vec![num(1), num(2)])), num(0)),
+    ]);
+
+    source = "m[i][j] = m[j][i]".to_string();
+    assert_eq!(parse(&source).unwrap(), vec![
+      s(StmtAST::Assign(index(index(var("m"), var("i")), var("j")), index(index(var("m"), var("j")), var("i")))),
+    ]);
+
+    source = "[1 2]".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ']' to close the array, found '2' at 1:4");
+    source = "a[1 2]".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ']' to close the index, found '2' at 1:5");
+    source = "[a][]".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an expression, found ']' at 1:5");
+    source = "[a] = 1".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Cannot assign to this expression at 1:1");
+  }
+
+  #[test]
+  fn parse_function_definitions() {
+    let source: String = "def add(a, b) {\n  a + b\n}\ndef zero() { 0 }\nadd(zero(), 2)".to_string();
+    let result = parse_exprs(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      s(ExprAST::Function("add".to_string(), vec![param("a"), param("b")], None, vec![s(StmtAST::ExprStmt(bin(BinaryOp::Add, var("a"), var("b"))))], vec![])),
+      s(ExprAST::Function("zero".to_string(), vec![], None, vec![s(StmtAST::ExprStmt(num(0)))], vec![])),
+      call("add", vec![call("zero", vec![]), num(2)]),
+    ]);
+  }
+
+  #[test]
+  fn parse_lambdas() {
+    let mut source: String = "let add = def(a, b) { return a + b }\nmap(def(x) { x * 2 }, xs)\ndef() { }".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(dump(&result.unwrap()), "(let add (lambda (a b)\n  (return (+ a b))))\n(call map (lambda (x)\n  (* x 2)) xs)\n(lambda ())\n");
+
+    // Lambdas nest, and their bodies are indented under them
+    source = "let f = def(x) {\n  return def(y) { return x + y }\n}".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(let f (lambda (x)\n  (return (lambda (y)\n    (return (+ x y))))))\n");
+
+    source = "let f = def g(x) { x }".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected '(' after 'def' in an anonymous function, found 'g' at 1:13");
+    source = "let f = def(x) x".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected '{' to start the function body, found 'x' at 1:16");
+  }
+
+  fn field(target: Expr, name: &str) -> Expr {
+    s(ExprAST::Field(Box::new(target), name.to_string()))
+  }
+
+  #[test]
+  fn parse_fields_and_methods() {
+    let mut source: String = "p.x + line.end.y * 2; list.push(3); m[i].row.get(j, k,)[0]".to_string();
+    let result = parse_exprs(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      bin(BinaryOp::Add, field(var("p"), "x"), bin(BinaryOp::Multiply, field(field(var("line"), "end"), "y"), num(2))),
+      s(ExprAST::MethodCall(Box::new(var("list")), "push".to_string(), vec![num(3)])),
+      index(s(ExprAST::MethodCall(Box::new(field(index(var("m"), var("i")), "row")), "get".to_string(), vec![var("j"), var("k")])), num(0)),
+    ]);
+
+    // Fields bind tighter than prefix operators, work on any value, and can be assigned to
+    source = "-p.x; f(a).b; (1).x; Point { x: 1 }.x; p.x = q.y.z".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(- (field p x))\n(field (call f a) b)\n(field 1 x)\n(field (new Point (x 1)) x)\n(= (field p x) (field (field q y) z))\n");
+
+    // A dot or parenthesis on the next line starts a new statement
+    source = "let a = p\n.x".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an expression, found '.' at 2:1");
+    source = "let a = p.len\n(1)".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(let a (field p len))\n1\n");
+
+    source = "p.1".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an identifier after '.', found '1' at 1:3");
+    source = "p.".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an identifier after '.', found the end of the file at 1:3");
+    source = "xs.push(1 2)".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ')' after the arguments to 'push' (opened at 1:8), found '2' at 1:11");
+    source = "p.x() = 1".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Cannot assign to this expression at 1:1");
+  }
+
+  #[test]
+  fn parse_type_annotations() {
+    let mut source: String = "let x: int = 5\ndef add(a: int, b) -> int { return a + b }\nlet f = def(n: [int]) -> def(int) -> bool { }".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    let named = |name: &str| s(TypeExpr::Named(name.to_string()));
+    assert_eq!(result.unwrap(), vec![
+      s(StmtAST::Let("x".to_string(), Some(named("int")), num(5))),
+      s(StmtAST::ExprStmt(s(ExprAST::Function("add".to_string(), vec![
+        s(Param { name: "a".to_string(), annotation: Some(named("int")) }),
+        param("b"),
+      ], Some(named("int")), vec![s(StmtAST::Return(Some(bin(BinaryOp::Add, var("a"), var("b")))))], vec![])))),
+      s(StmtAST::Let("f".to_string(), None, s(ExprAST::Lambda(vec![
+        s(Param { name: "n".to_string(), annotation: Some(s(TypeExpr::Array(Box::new(named("int"))))) }),
+      ], Some(s(TypeExpr::Function(vec![named("int")], Some(Box::new(named("bool")))))), vec![], vec![])))),
+    ]);
+
+    // Types print the way they're written
+    source = "let callback: def([Point], def()) -> [[int]] = f\ndef main(args: [string]) { }".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(let (: callback def([Point], def()) -> [[int]]) f)\n(def main ((: args [string])))\n");
+
+    source = "let x: = 5".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected a type, found '=' at 1:8");
+    source = "let xs: [int int] = []".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ']' to close the array type, found 'int' at 1:14");
+    source = "def f() -> { }".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected a type, found '{' at 1:12");
+  }
+
+  #[test]
+  fn parse_constants() {
+    let mut source: String = "const limit = 100\nconst scale: float = limit / 3\nlet x = limit".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      s(StmtAST::Const("limit".to_string(), None, num(100))),
+      s(StmtAST::Const("scale".to_string(), Some(s(TypeExpr::Named("float".to_string()))), bin(BinaryOp::Divide, var("limit"), num(3)))),
+      s(StmtAST::Let("x".to_string(), None, var("limit"))),
+    ]);
+    assert_ne!(s(StmtAST::Const("x".to_string(), None, num(1))), s(StmtAST::Let("x".to_string(), None, num(1))));
+
+    source = "def area(r) { const pi: float = 3.14; return pi * r * r }".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(def area (r)\n  (const (: pi float) 3.14)\n  (return (* (* pi r) r)))\n");
+
+    source = "const limit".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected '=' after the constant name, found the end of the file at 1:12");
+    source = "const = 1".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an identifier after 'const', found '=' at 1:7");
+  }
+
+  #[test]
+  fn parse_tuples() {
+    let mut source: String = "let pair = (1, x)\nlet (a, b: int) = divide(7, 2)\nreturn a, b + 1".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      s(StmtAST::Let("pair".to_string(), None, s(ExprAST::Tuple(vec![num(1), var("x")])))),
+      s(StmtAST::LetTuple(vec![
+        param("a"),
+        s(Param { name: "b".to_string(), annotation: Some(s(TypeExpr::Named("int".to_string()))) }),
+      ], call("divide", vec![num(7), num(2)]))),
+      s(StmtAST::Return(Some(s(ExprAST::Tuple(vec![var("a"), bin(BinaryOp::Add, var("b"), num(1))]))))),
+    ]);
+
+    // Only a comma makes a tuple, so (x) is still a grouping
+    source = "(x)\n(x,)\n()\n((a, b), c)[0]".to_string();
+    assert_eq!(parse_exprs(&source).unwrap(), vec![
+      var("x"),
+      s(ExprAST::Tuple(vec![var("x")])),
+      s(ExprAST::Tuple(vec![])),
+      index(s(ExprAST::Tuple(vec![s(ExprAST::Tuple(vec![var("a"), var("b")])), var("c")])), num(0)),
+    ]);
+
+    source = "def divide(a: int, b: int) -> (int, int) { return a / b, a % b }\nlet t: ((int), (bool,)) = f()".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(def divide ((: a int) (: b int)) (-> (int, int))\n  (return (tuple (/ a b) (% a b))))\n(let (: t (int, (bool,))) (call f))\n");
+
+    source = "let (a, 1) = t".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an identifier to destructure into, found '1' at 1:9");
+    source = "let (a, b) t".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected '=' after the names being destructured, found 't' at 1:12");
+  }
+
+  #[test]
+  fn parse_structs() {
+    let mut source: String = "struct Point { x, y }\nlet p = Point { x: 1, y: f(2) }\nstruct Empty {}\nlet e = Empty {}".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      s(StmtAST::Struct("Point".to_string(), vec!["x".to_string(), "y".to_string()])),
+      s(StmtAST::Let("p".to_string(), None, s(ExprAST::StructLiteral("Point".to_string(), vec![
+        ("x".to_string(), num(1)),
+        ("y".to_string(), call("f", vec![num(2)])),
+      ])))),
+      s(StmtAST::Struct("Empty".to_string(), vec![])),
+      s(StmtAST::Let("e".to_string(), None, s(ExprAST::StructLiteral("Empty".to_string(), vec![])))),
+    ]);
+
+    // Fields can go on their own lines, with a trailing comma, and literals nest
+    source = "struct Line {\n  from,\n  to,\n}\nlet l = Line {\n  from: Point { x: 0, y: 0 },\n  to: p,\n}".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(struct Line (from to))\n(let l (new Line (from (new Point (x 0) (y 0))) (to p)))\n");
+
+    // The brace after an if or while condition opens its block, unless the literal is inside parentheses
+    source = "if p { }\nwhile p == (Point { x: 0 }) { }\nif f(Point { x: 0 }) { }".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(if p\n  (block))\n(while (== p (new Point (x 0)))\n  (block))\n(if (call f (new Point (x 0)))\n  (block))\n");
+    source = "if p == Point { x: 0 } { }".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ';' or a new line after the statement, found ':' at 1:18");
+
+    // A brace on the next line starts a block, not a literal
+    source = "let p = q\n{ x }".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(let p q)\n(block\n  x)\n");
+
+    source = "struct Point { x, y, x }".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Field 'x' of struct 'Point' is declared twice at 1:22");
+    source = "let p = Point { x: 1,\n  x: 2 }".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Field 'x' is given twice in 'Point' at 2:3");
+    source = "let p = Point { x 1 }".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ':' after the field name, found '1' at 1:19");
+    source = "struct Point { x y }".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected '}' after the fields, found 'y' at 1:18");
+  }
+
+  #[test]
+  fn parse_increments() {
+    let mut source: String = "i++\n--n\nxs[i]--; ++p.count\nwhile i < 10 { i++ }".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), r#"(= i (+ i 1))
+(= n (- n 1))
+(= (index xs i) (- (index xs i) 1))
+(= (field p count) (+ (field p count) 1))
+(while (< i 10)
+  (block
+    (= i (+ i 1))))
+"#);
+    // The copy of the target read on the right has ids of its own
+    let tokens = lex("xs[i]++").unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    let StmtAST::Assign(target, value) = &program.top_level[0].node else { panic!() };
+    let ExprAST::Binary(_, read, _) = &value.node else { panic!() };
+    assert_eq!(target.id == read.id, false);
+
+    // `--` between two operands is a minus and a negation, as it was before it was a token
+    source = "x--y\nx--2 * y\nx -- -y".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(- x (- y))\n(- x (* (- 2) y))\n(- x (- (- y)))\n");
+
+    source = "let y = x++".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "'++' can only be used as a statement of its own, as in 'x++' or '++x' at 1:10");
+    source = "let y = --x".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "'--' can only be used as a statement of its own, as in 'x--' or '--x' at 1:9");
+    source = "f(i++)".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "'++' can only be used as a statement of its own, as in 'x++' or '++x' at 1:4");
+    source = "x++y".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "'++' can only be used as a statement of its own, as in 'x++' or '++x' at 1:2");
+    source = "f()++".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Cannot apply '++' to this expression at 1:1");
+    source = "a + b--".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Cannot apply '--' to this expression at 1:1");
+  }
+
+  #[test]
+  fn parse_strings() {
+    let source: String = r#""plain" + "sum is ${a + b}, or ${c}""#.to_string();
+    assert_eq!(parse_exprs(&source).unwrap(), vec![
+      bin(BinaryOp::Add,
+        s(ExprAST::StringLiteral("plain".to_string())),
+        s(ExprAST::Interpolation(vec![
+          s(ExprAST::StringLiteral("sum is ".to_string())),
+          bin(BinaryOp::Add, var("a"), var("b")),
+          s(ExprAST::StringLiteral(", or ".to_string())),
+          var("c"),
+          s(ExprAST::StringLiteral("".to_string())),
+        ]))),
+    ]);
+  }
+
+  fn call(name: &str, args: Vec<Expr>) -> Expr {
+    s(ExprAST::Call(name.to_string(), args))
+  }
+
+  fn block(statements: Vec<Stmt>) -> Box<Stmt> {
+    Box::new(s(StmtAST::Block(statements)))
+  }
+
+  #[test]
+  fn parse_fibonacci_program() {
+    let source: String = r#"
+# Compute the x'th Fibonacci number.
+def fib(x) {
+  if (x < 3) {
+    return 1
+  }
+  else {
+    return fib(x-1) + fib(x-2)
+  }
+}
+
+# This expression will compute the 40th number.
+fib(40)
+"#.to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      s(StmtAST::ExprStmt(s(ExprAST::Function("fib".to_string(), vec![param("x")], None, vec![
+        s(StmtAST::If(bin(BinaryOp::LessThan, var("x"), num(3)),
+          block(vec![s(StmtAST::Return(Some(num(1))))]),
+          Some(block(vec![s(StmtAST::Return(Some(bin(BinaryOp::Add,
+            call("fib", vec![bin(BinaryOp::Subtract, var("x"), num(1))]),
+            call("fib", vec![bin(BinaryOp::Subtract, var("x"), num(2))])))))])))),
+      ], vec![])))),
+      s(StmtAST::ExprStmt(call("fib", vec![num(40)]))),
+    ]);
+  }
+
+  #[test]
+  fn parse_fibonacci_with_while_loop() {
+    let source: String = r#"
+# Compute Fibonacci iteratively.
+def fib(x) {
+  let a = 0
+  let b = 1
+  while (x > 0) {
+    let temp = a
+    a = b
+    b = temp + b
+    x = x - 1
+  }
+  return a
+}
+
+# Compute the 10th Fibonacci number.
+fib(10)
+"#.to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      s(StmtAST::ExprStmt(s(ExprAST::Function("fib".to_string(), vec![param("x")], None, vec![
+        s(StmtAST::Let("a".to_string(), None, num(0))),
+        s(StmtAST::Let("b".to_string(), None, num(1))),
+        s(StmtAST::While(bin(BinaryOp::GreaterThan, var("x"), num(0)), block(vec![
+          s(StmtAST::Let("temp".to_string(), None, var("a"))),
+          s(StmtAST::Assign(var("a"), var("b"))),
+          s(StmtAST::Assign(var("b"), bin(BinaryOp::Add, var("temp"), var("b")))),
+          s(StmtAST::Assign(var("x"), bin(BinaryOp::Subtract, var("x"), num(1)))),
+        ]))),
+        s(StmtAST::Return(Some(var("a")))),
+      ], vec![])))),
+      s(StmtAST::ExprStmt(call("fib", vec![num(10)]))),
+    ]);
+  }
+
+  #[test]
+  fn parse_else_if_chains() {
+    let mut source: String = "if x < 0 { 5 } else if x == 0 { 0 } else if x < 10 { 1 } else { 2 }".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      s(StmtAST::If(bin(BinaryOp::LessThan, var("x"), num(0)), block(vec![s(StmtAST::ExprStmt(num(5)))]),
+        Some(Box::new(s(StmtAST::If(bin(BinaryOp::Equal, var("x"), num(0)), block(vec![s(StmtAST::ExprStmt(num(0)))]),
+          Some(Box::new(s(StmtAST::If(bin(BinaryOp::LessThan, var("x"), num(10)), block(vec![s(StmtAST::ExprStmt(num(1)))]),
+            Some(block(vec![s(StmtAST::ExprStmt(num(2)))])))))))))))),
+    ]);
+
+    source = "if a { } else if b { }".to_string();
+    assert_eq!(parse(&source).unwrap(), vec![
+      s(StmtAST::If(var("a"), block(vec![]), Some(Box::new(s(StmtAST::If(var("b"), block(vec![]), None)))))),
+    ]);
+
+    source = "if a { } else while b { }".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected '{' after 'else', found 'while' at 1:15");
+  }
+
+  #[test]
+  fn parse_loop_control_and_blocks() {
+    let source: String = "while true { if done { break } continue; }\n{ let x = 1; return }".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(result.unwrap(), vec![
+      s(StmtAST::While(s(ExprAST::Boolean(true)), block(vec![
+        s(StmtAST::If(var("done"), block(vec![s(StmtAST::Break)]), None)),
+        s(StmtAST::Continue),
+      ]))),
+      s(StmtAST::Block(vec![s(StmtAST::Let("x".to_string(), None, num(1))), s(StmtAST::Return(None))])),
+    ]);
+  }
+
+  #[test]
+  fn parse_program() {
+    let mut source: String = "let x = twice(3)\ndef twice(n) { return n * 2 }\nprint(x)\ndef print(v) { }".to_string();
+    let tokens = lex(&source).unwrap();
+    let result = Parser::new(&tokens).parse();
+    assert_eq!(result.is_ok(), true);
+    let program = result.unwrap();
+    assert_eq!(program.functions, vec![
+      s(ExprAST::Function("twice".to_string(), vec![param("n")], None, vec![s(StmtAST::Return(Some(bin(BinaryOp::Multiply, var("n"), num(2)))))], vec![])),
+      s(ExprAST::Function("print".to_string(), vec![param("v")], None, vec![], vec![])),
+    ]);
+    assert_eq!(program.top_level, vec![
+      s(StmtAST::Let("x".to_string(), None, call("twice", vec![num(3)]))),
+      s(StmtAST::ExprStmt(call("print", vec![var("x")]))),
+    ]);
+    assert_eq!(program.to_string(), "(def twice (n)\n  (return (* n 2)))\n(def print (v))\n(let x (call twice 3))\n(call print x)\n");
+
+    // Functions nested in blocks and lambdas stay where they are
+    source = "{ def f() { } }\nlet g = def() { }".to_string();
+    let tokens = lex(&source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    assert_eq!(program.functions.len(), 0);
+    assert_eq!(program.top_level.len(), 2);
+
+    // Structs are collected like functions, and print before them
+    source = "print(Point { x: 1 })\ndef print(v) { }\nstruct Point { x }".to_string();
+    let tokens = lex(&source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    assert_eq!(program.structs, vec![s(StmtAST::Struct("Point".to_string(), vec!["x".to_string()]))]);
+    assert_eq!(program.to_string(), "(struct Point (x))\n(def print (v))\n(call print (new Point (x 1)))\n");
+
+    source = "struct Point { x }\nstruct Point { x, y }".to_string();
+    let tokens = lex(&source).unwrap();
+    assert_eq!(Parser::new(&tokens).parse().err().unwrap().to_string(), "Struct 'Point', first defined on line 1, is defined again at 2:1");
+
+    source = "def f() { }\n\ndef g() { }\ndef f(x) { x }".to_string();
+    let tokens = lex(&source).unwrap();
+    assert_eq!(Parser::new(&tokens).parse().err().unwrap().to_string(), "Function 'f', first defined on line 1, is defined again at 4:1");
+  }
+
+  #[test]
+  fn parse_imports() {
+    let mut source: String = "import math\nimport \"lib/strings.sil\"; import io\nprint(pi)".to_string();
+    let tokens = lex(&source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    assert_eq!(program.imports, vec![
+      s(StmtAST::Import("math.sil".to_string())),
+      s(StmtAST::Import("lib/strings.sil".to_string())),
+      s(StmtAST::Import("io.sil".to_string())),
+    ]);
+    assert_eq!(program.to_string(), "(import \"math.sil\")\n(import \"lib/strings.sil\")\n(import \"io.sil\")\n(call print pi)\n");
+
+    source = "def f() {\n  import math\n}".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Imports are only allowed at the top level of a file at 2:3");
+    source = "import 42".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected a module name or path after 'import', found '42' at 1:8");
+    source = "import".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected a module name or path after 'import', found the end of the file at 1:7");
+  }
+
+  #[test]
+  fn parse_statement_separators() {
+    let mut source: String = "x = 1; y = 2\nz = x +\n  y\nif x { y = 1 } z = 2\nwhile x { break } { }\n;;".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(dump(&result.unwrap()), "(= x 1)\n(= y 2)\n(= z (+ x y))\n(if x\n  (block\n    (= y 1)))\n(= z 2)\n(while x\n  (block\n    (break)))\n(block)\n");
+
+    // A return at the end of a line returns nothing, rather than the value on the next line
+    source = "def f() {\n  return\n  1\n}\ndef g() { return; 1 }".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(def f ()\n  (return)\n  1)\n(def g ()\n  (return)\n  1)\n");
+
+    // An operator, call or index starting a line starts a new statement
+    source = "x = a\n-b\nf\n(1)\nxs\n[0]\ny\n? 1 : 2".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an expression, found '?' at 8:1");
+    source = "x = a\n-b\nf\n(1)\nxs\n[0]".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(= x a)\n(- b)\nf\n1\nxs\n(array 0)\n");
+
+    source = "x = 1 y = 2".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ';' or a new line after the statement, found 'y' at 1:7");
+    source = "{\n  f(x) g(y)\n}".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ';' or a new line after the statement, found 'g' at 2:8");
+    source = "let x = a b".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ';' or a new line after the statement, found 'b' at 1:11");
+  }
+
+  #[test]
+  fn parse_doc_comments() {
+    let mut source: String = "## Doubles x\ndef f(x) {\n  ## Returns twice x\n  return x * 2 ## not x + x\n}\n## The end".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(def f (x)\n  (return (* x 2)))\n");
+
+    // A doc comment between two lines doesn't join them, or split an expression carried on to the next
+    source = "x = a\n## note\n-b\ny = 1 +\n  ## two\n  2\n##".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(= x a)\n(- b)\n(= y (+ 1 2))\n");
+    source = "## only a comment".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "");
+    source = "x = 1 ## one\ny = ".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an expression, found the end of the file at 2:4");
+  }
+
+  // Parse with semicolons ending statements rather than newlines
+  fn parse_with_semicolons(source: &str) -> Result<Vec<Stmt>, String> {
+    let tokens = lex(source)?;
+    return Parser::new(&tokens).set_statement_end(StatementEnd::Semicolon).parse_statements().map_err(|err| err.to_string());
+  }
+
+  #[test]
+  fn parse_required_semicolons() {
+    let mut source: String = "x = a\n  - b;\nf\n(1);\nreturn\n  1;\nif x { y = 1; } while x { break; }\ndef f() { return; }".to_string();
+    assert_eq!(dump(&parse_with_semicolons(&source).unwrap()), "(= x (- a b))\n(call f 1)\n(return 1)\n(if x\n  (block\n    (= y 1)))\n(while x\n  (block\n    (break)))\n(def f ()\n  (return))\n");
+    // The same source with newlines ending statements
+    assert_eq!(dump(&parse(&source).unwrap()), "(= x a)\n(- b)\nf\n1\n(return)\n1\n(if x\n  (block\n    (= y 1)))\n(while x\n  (block\n    (break)))\n(def f ()\n  (return))\n");
+
+    source = "x = 1\ny = 2;".to_string();
+    assert_eq!(parse_with_semicolons(&source).err().unwrap(), "Missing ';' after the statement, before 'y' at 1:6");
+    source = "if x { y = 1 }".to_string();
+    assert_eq!(parse_with_semicolons(&source).err().unwrap(), "Missing ';' after the statement, before '}' at 1:13");
+    source = "let x = f(1)".to_string();
+    assert_eq!(parse_with_semicolons(&source).err().unwrap(), "Missing ';' after the statement, before the end of the file at 1:13");
+    source = "x\n++y;".to_string();
+    assert_eq!(parse_with_semicolons(&source).err().unwrap(), "'++' can only be used as a statement of its own, as in 'x++' or '++x' at 2:1");
+  }
+
+  #[test]
+  fn parse_statement_errors() {
+    let mut source: String = "let = 5".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), false);
+    assert_eq!(result.err().unwrap(), "Expected an identifier after 'let', found '=' at 1:5");
+
+    source = "let x 5".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected '=' after the variable name, found '5' at 1:7");
+
+    source = "if x return 1".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected '{' after the if condition, found 'return' at 1:6");
+
+    source = "while x {\n  x = \n}".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected an expression, found '}' at 3:1");
+
+    source = "1 + 2 = 3".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Cannot assign to this expression at 1:1");
+    source = "x = 1\n  f(x) = 2".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Cannot assign to this expression at 2:3");
+    source = "x = y = 1".to_string();
+    assert_eq!(parse(&source).err().unwrap(), "Expected ';' or a new line after the statement, found '=' at 1:7");
+  }
+
+  #[test]
+  fn parse_errors() {
+    let mut source: String = "fib(40 1)".to_string();
+    let result = parse_exprs(&source);
+    assert_eq!(result.is_ok(), false);
+    assert_eq!(result.err().unwrap(), "Expected ')' after the arguments to 'fib' (opened at 1:4), found '1' at 1:8");
+
+    // The error keeps what was expected, the token found and where it is apart, for tools that want them
+    let tokens = lex("f(a b)").unwrap();
+    let error = Parser::new(&tokens).parse_expr().err().unwrap();
+    assert_eq!(error.kind, ParseErrorKind::Unexpected {
+      expected: "')' after the arguments to 'f' (opened at 1:2)".to_string(),
+      found: "b".to_string(),
+    });
+    assert_eq!((error.span.line, error.span.column, error.span.start), (1, 5, 4));
+    assert_eq!(error.to_string(), "Expected ')' after the arguments to 'f' (opened at 1:2), found 'b' at 1:5");
+
+    source = "1 +".to_string();
+    assert_eq!(parse_exprs(&source).err().unwrap(), "Expected an expression, found the end of the file at 1:4");
+
+    source = "1 +\n* 2".to_string();
+    assert_eq!(parse_exprs(&source).err().unwrap(), "Expected an expression, found '*' at 2:1");
+
+    source = "def 1(x) { x }".to_string();
+    assert_eq!(parse_exprs(&source).err().unwrap(), "Expected an identifier as the function name, found '1' at 1:5");
+
+    source = "def f(x y) { x }".to_string();
+    assert_eq!(parse_exprs(&source).err().unwrap(), "Expected ')' after the parameters, found 'y' at 1:9");
+
+    source = "(1 + 2 3)".to_string();
+    assert_eq!(parse_exprs(&source).err().unwrap(), "Expected ')' to close the grouping, found '3' at 1:8");
+  }
+
+  // Records the id of every expression and statement, in the order they're visited
+  struct Ids {
+    ids: Vec<NodeId>,
+  }
+
+  impl Visitor for Ids {
+    fn visit_expr(&mut self, expr: &Expr) {
+      walk_expr(self, expr);
+      self.ids.push(expr.id);
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+      walk_stmt(self, stmt);
+      self.ids.push(stmt.id);
+    }
+  }
+
+  #[test]
+  fn parse_node_ids() {
+    let source: String = "def f(a: int) -> int { return a + 1 }\nlet x = f(\"${y}!\")\nwhile x { x = x - 1 }".to_string();
+    let tokens = lex(&source).unwrap();
+    let program = Parser::new(&tokens).parse_statements().unwrap();
+    let mut ids = Ids { ids: Vec::new() };
+    for stmt in &program {
+      ids.visit_stmt(stmt);
+    }
+
+    // Children are numbered before their parents, so walking in post-order sees the ids rising, and every
+    // node has its own
+    assert_eq!(ids.ids.windows(2).all(|pair| pair[0] < pair[1]), true);
+    assert_eq!(ids.ids.contains(&NodeId::DUMMY), false);
+    let StmtAST::ExprStmt(function) = &program[0].node else { panic!("expected a function") };
+    let ExprAST::Function(_, params, returns, _, _) = &function.node else { panic!("expected a function") };
+    assert_eq!(params[0].node.annotation.as_ref().unwrap().id, NodeId(0));
+    assert_eq!(params[0].id, NodeId(1));
+    assert_eq!(returns.as_ref().unwrap().id, NodeId(2));
+
+    // Parsing the same source again gives the same ids, so a side table filled from one parse reads back
+    // against another
+    let mut printed: NodeMap<String> = NodeMap::new();
+    for stmt in &program {
+      printed.insert(stmt.id, stmt.to_string());
+    }
+    let again = Parser::new(&tokens).parse_statements().unwrap();
+    assert_eq!(printed[&again[2].id], "(while x\n  (block\n    (= x (- x 1))))");
+  }
+
+  #[test]
+  fn parse_spans() {
+    let source: String = "let y = -(a + b) * f(1, 2)\nwhile x {\n  x = 0\n}".to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    let program = result.unwrap();
+    let text = |span: Span| &source[span.start..span.end];
+
+    assert_eq!(text(program[0].span), "let y = -(a + b) * f(1, 2)");
+    let StmtAST::Let(_, _, value) = &program[0].node else { panic!("expected a let") };
+    assert_eq!(text(value.span), "-(a + b) * f(1, 2)");
+    let ExprAST::Binary(_, lhs, rhs) = &value.node else { panic!("expected a binary expression") };
+    assert_eq!(text(lhs.span), "-(a + b)");
+    assert_eq!(text(rhs.span), "f(1, 2)");
+    let ExprAST::Unary(_, operand) = &lhs.node else { panic!("expected a unary expression") };
+    assert_eq!(text(operand.span), "(a + b)");
+
+    assert_eq!(text(program[1].span), "while x {\n  x = 0\n}");
+    assert_eq!((program[1].span.line, program[1].span.column), (2, 1));
+    let StmtAST::While(_, body) = &program[1].node else { panic!("expected a while loop") };
+    let StmtAST::Block(statements) = &body.node else { panic!("expected a block") };
+    assert_eq!(text(statements[0].span), "x = 0");
+    assert_eq!((statements[0].span.line, statements[0].span.column), (3, 3));
+
+    // Trees compare without their spans
+    assert_eq!(parse("f( 1 )").unwrap(), parse("\n\nf(1)").unwrap());
+  }
+
+  #[test]
+  fn dump_program() {
+    let mut source: String = r#"
+def fib(x) {
+  if x < 3 { return 1 } else if x == 3 { return 2 } else {
+    return fib(x - 1) + fib(x - 2)
+  }
+}
+let n = -fib(10) ** 2
+while n > 0 { n = n - 1.5; break }
+print("n is ${n}\t", !true)
+a[0] = [1, b[2]]
+"#.to_string();
+    let result = parse(&source);
+    assert_eq!(result.is_ok(), true);
+    assert_eq!(dump(&result.unwrap()), r#"(def fib (x)
+  (if (< x 3)
+    (block
+      (return 1))
+    (if (== x 3)
+      (block
+        (return 2))
+      (block
+        (return (+ (call fib (- x 1)) (call fib (- x 2))))))))
+(let n (- (** (call fib 10) 2)))
+(while (> n 0)
+  (block
+    (= n (- n 1.5))
+    (break)))
+(call print (interpolate "n is " n "\t") (! true))
+(= (index a 0) (array 1 (index b 2)))
+"#);
+
+    // Empty blocks and bare returns
+    source = "{ return }\n{}".to_string();
+    assert_eq!(dump(&parse(&source).unwrap()), "(block\n  (return))\n(block)\n");
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn serde_round_trip() {
+    let source: String = "def f(x) {\n  return \"x is ${-x}\"\n}".to_string();
+    let tokens = lex(&source).unwrap();
+    let json = serde_json::to_string(&tokens).unwrap();
+    let decoded: Vec<Token> = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded.len(), tokens.len());
+    for (decoded, token) in decoded.iter().zip(&tokens) {
+      assert_eq!(decoded.kind, token.kind);
+      assert_eq!(decoded.span, token.span);
+    }
+
+    let program = Parser::new(&tokens).parse_statements().unwrap();
+    let json = serde_json::to_string(&program).unwrap();
+    let decoded: Vec<Stmt> = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, program);
+    assert_eq!(decoded[0].span, program[0].span);
+    assert_eq!(decoded[0].id, program[0].id);
+
+    let program = parse("x + 1").unwrap();
+    let StmtAST::ExprStmt(expr) = &program[0].node else { panic!("expected an expression statement") };
+    assert_eq!(serde_json::to_string(&expr.node).unwrap(),
+      r#"{"Binary":["Add",{"node":{"Variable":"x"},"span":{"start":0,"end":1,"line":1,"column":1},"id":0},{"node":{"Integer":1},"span":{"start":4,"end":5,"line":1,"column":5},"id":1}]}"#);
+  }
+
+  #[test]
+  fn parse_depth_limit() {
+    // Unoptimized builds use several times the stack per level that release builds do, more than a test
+    // thread's default allows at the default limit, so these run on a thread with a main thread's 8MB
+    let deep = std::thread::Builder::new().stack_size(8 * 1024 * 1024).spawn(|| {
+      let mut source: String = format!("{}1{}", "(".repeat(10000), ")".repeat(10000));
+      let result = parse(&source);
+      assert_eq!(result.is_ok(), false);
+      assert_eq!(result.err().unwrap(), "Nesting too deep, the limit is 256 levels at 1:256");
+
+      source = format!("x = {}y", "- ".repeat(10000));
+      assert_eq!(parse(&source).err().unwrap(), "Nesting too deep, the limit is 256 levels at 1:515");
+
+      source = format!("{}\n{}", "{ while x ".repeat(10000), "}".repeat(10000));
+      assert_eq!(parse(&source).err().unwrap(), "Nesting too deep, the limit is 256 levels at 1:2549");
+
+      source = format!("{}1{}", "(".repeat(DEFAULT_MAX_DEPTH - 2), ")".repeat(DEFAULT_MAX_DEPTH - 2));
+      assert_eq!(parse(&source).is_ok(), true);
+    });
+    assert_eq!(deep.unwrap().join().is_ok(), true);
+
+    // Each link of a long flat chain nests the rest one level deeper, as parentheses do
+    let mut source: String = format!("x = 0{}", " + 1".repeat(200));
+    assert_eq!(parse(&source).is_ok(), true);
+    source = format!("x = 0{}", " + 1".repeat(5000));
+    assert_eq!(parse(&source).err().unwrap(), "Nesting too deep, the limit is 256 levels at 1:1021");
+    source = format!("x = 2{}", " ** 2".repeat(5000));
+    assert_eq!(parse(&source).err().unwrap(), "Nesting too deep, the limit is 256 levels at 1:1280");
+    source = format!("x = m{}.y", "[0]".repeat(5000));
+    assert_eq!(parse(&source).err().unwrap(), "Nesting too deep, the limit is 256 levels at 1:766");
+    source = format!("x = p{}", ".next".repeat(300));
+    assert_eq!(parse(&source).err().unwrap(), "Nesting too deep, the limit is 256 levels at 1:1276");
+
+    // Right up to the limit is fine, one level past is not
+    let tokens = lex("f([(1)])").unwrap();
+    assert_eq!(Parser::with_max_depth(&tokens, 5).parse().is_ok(), true);
+    assert_eq!(Parser::with_max_depth(&tokens, 4).parse().err().unwrap().to_string(), "Nesting too deep, the limit is 4 levels at 1:5");
+  }
+
+  #[test]
+  fn binary_ops_from_tokens() {
+    let source: String = "+ - * / % ** == != < > <= >= && || & | ^ << >>".to_string();
+    let tokens = lex(&source).unwrap();
+    for token in &tokens {
+      let op = BinaryOp::from_token(&token.kind).unwrap();
+      assert_eq!(*op.token(), token.kind);
+      assert_eq!(op.to_string(), source[token.span.start..token.span.end]);
+    }
+
+    assert_eq!(BinaryOp::from_token(&TokenKind::Equal), None);
+    assert_eq!(BinaryOp::from_token(&TokenKind::Not), None);
+  }
+
+  // Property tests
+
+  use proptest::strategy::{Just, Strategy};
+  use proptest::{collection, option, prop_oneof, sample};
+  use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+  const NAMES: [&str; 5] = ["a", "b", "count", "f", "xs"];
+  const TEXT: &str = "[a-z \n\"$\\\\]{0,6}";
+
+  // Expressions the parser can produce, other than functions and lambdas
+  fn arb_expr() -> impl Strategy<Value = Expr> {
+    let leaf = prop_oneof![
+      (0..1000i64).prop_map(num),
+      (0..1000u32).prop_map(|n| float(n as f64 / 4.0)),
+      proptest::bool::ANY.prop_map(|value| s(ExprAST::Boolean(value))),
+      sample::select(&NAMES[..]).prop_map(var),
+      TEXT.prop_map(|text| s(ExprAST::StringLiteral(text))),
+    ];
+    let binary_ops: Vec<BinaryOp> = BINARY_OPERATORS.iter().map(|entry| entry.1).collect();
+    return leaf.prop_recursive(4, 32, 3, move |inner| prop_oneof![
+      (sample::select(vec![UnaryOp::Negate, UnaryOp::Not, UnaryOp::BitNot]), inner.clone())
+        .prop_map(|(op, operand)| unary(op, operand)),
+      (sample::select(binary_ops.clone()), inner.clone(), inner.clone()).prop_map(|(op, lhs, rhs)| bin(op, lhs, rhs)),
+      (inner.clone(), inner.clone(), inner.clone())
+        .prop_map(|(condition, a, b)| s(ExprAST::Conditional(Box::new(condition), Box::new(a), Box::new(b)))),
+      (sample::select(&NAMES[..]), collection::vec(inner.clone(), 0..3)).prop_map(|(name, args)| call(name, args)),
+      collection::vec(inner.clone(), 0..3).prop_map(|elements| s(ExprAST::ArrayLiteral(elements))),
+      collection::vec(inner.clone(), 0..3).prop_map(|elements| s(ExprAST::Tuple(elements))),
+      (inner.clone(), inner.clone()).prop_map(|(target, i)| index(target, i)),
+      // A field access, or a method call if there are arguments
+      (inner.clone(), sample::select(&NAMES[..]), option::of(collection::vec(inner.clone(), 0..3))).prop_map(|(target, name, args)| match args {
+        Some(args) => s(ExprAST::MethodCall(Box::new(target), name.to_string(), args)),
+        None => s(ExprAST::Field(Box::new(target), name.to_string())),
+      }),
+      // Interpolated text and expressions alternate, starting and ending with text
+      (collection::vec((TEXT, inner.clone()), 1..3), TEXT).prop_map(|(pieces, last)| {
+        let mut parts: Vec<Expr> = Vec::new();
+        for (text, expr) in pieces {
+          parts.push(s(ExprAST::StringLiteral(text)));
+          parts.push(expr);
+        }
+        parts.push(s(ExprAST::StringLiteral(last)));
+        return s(ExprAST::Interpolation(parts));
+      }),
+      (sample::select(&NAMES[..]), collection::vec((sample::select(&NAMES[..]), inner), 0..3)).prop_map(|(name, values)| {
+        let mut fields: Vec<(String, Expr)> = Vec::new();
+        for (field, value) in values {
+          if !fields.iter().any(|(other, _)| other == field) {
+            fields.push((field.to_string(), value));
+          }
+        }
+        return s(ExprAST::StructLiteral(name.to_string(), fields));
+      }),
+    ]);
+  }
+
+  fn arb_type() -> impl Strategy<Value = Type> {
+    let leaf = sample::select(vec!["int", "bool", "Point"]).prop_map(|name| s(TypeExpr::Named(name.to_string())));
+    return leaf.prop_recursive(3, 12, 3, |inner| prop_oneof![
+      inner.clone().prop_map(|element| s(TypeExpr::Array(Box::new(element)))),
+      collection::vec(inner.clone(), 0..3).prop_map(|elements| s(TypeExpr::Tuple(elements))),
+      (collection::vec(inner.clone(), 0..3), option::of(inner)).prop_map(|(params, returns)| s(TypeExpr::Function(params, returns.map(Box::new)))),
+    ]);
+  }
+
+  fn arb_stmt() -> impl Strategy<Value = Stmt> {
+    // A variable with indexes and fields after it
+    let target = (sample::select(&NAMES[..]), collection::vec((arb_expr(), option::of(sample::select(&NAMES[..]))), 0..2))
+      .prop_map(|(name, steps)| steps.into_iter().fold(var(name), |target, step| match step {
+        (i, None) => index(target, i),
+        (_, Some(field)) => s(ExprAST::Field(Box::new(target), field.to_string())),
+      }));
+    let leaf = prop_oneof![
+      (sample::select(&NAMES[..]), option::of(arb_type()), arb_expr())
+        .prop_map(|(name, annotation, value)| s(StmtAST::Let(name.to_string(), annotation, value))),
+      (sample::select(&NAMES[..]), option::of(arb_type()), arb_expr())
+        .prop_map(|(name, annotation, value)| s(StmtAST::Const(name.to_string(), annotation, value))),
+      (sample::subsequence(&NAMES[..], 1..3), arb_expr()).prop_map(|(names, value)| {
+        s(StmtAST::LetTuple(names.iter().map(|name| param(name)).collect(), value))
+      }),
+      (target, arb_expr()).prop_map(|(target, value)| s(StmtAST::Assign(target, value))),
+      option::of(arb_expr()).prop_map(|value| s(StmtAST::Return(value))),
+      Just(()).prop_map(|_| s(StmtAST::Break)),
+      Just(()).prop_map(|_| s(StmtAST::Continue)),
+      (sample::select(&NAMES[..]), sample::subsequence(&NAMES[..], 0..3))
+        .prop_map(|(name, fields)| s(StmtAST::Struct(name.to_string(), fields.iter().map(|field| field.to_string()).collect()))),
+      arb_expr().prop_map(|expr| s(StmtAST::ExprStmt(expr))),
+    ];
+    return leaf.prop_recursive(3, 24, 3, |inner| {
+      let body = collection::vec(inner.clone(), 0..3);
+      // An else branch is a block or another if
+      let else_branch = inner.clone().prop_map(|stmt| match stmt.node {
+        StmtAST::If(..) => stmt,
+        _ => *block(vec![stmt]),
+      });
+      return prop_oneof![
+        body.clone().prop_map(|statements| s(StmtAST::Block(statements))),
+        (arb_expr(), body.clone(), option::of(else_branch))
+          .prop_map(|(condition, then_block, else_block)| s(StmtAST::If(condition, block(then_block), else_block.map(Box::new)))),
+        (arb_expr(), body).prop_map(|(condition, statements)| s(StmtAST::While(condition, block(statements)))),
+      ];
+    });
+  }
+
+  // How tightly an expression holds together when printed: operands binding looser than their operator
+  // need parentheses
+  fn binding(expr: &ExprAST) -> u8 {
+    match expr {
+      ExprAST::Conditional(..) => return 0,
+      ExprAST::Binary(op, _, _) | ExprAST::Logical(op, _, _) => return op.precedence(),
+      ExprAST::Unary(..) => return UNARY_PRECEDENCE,
+      _ => return 12,
+    }
+  }
+
+  // Print an expression as SIL source, parenthesized if it binds looser than `min_binding`
+  fn print_expr(expr: &Expr, min_binding: u8) -> String {
+    let list = |exprs: &[Expr]| exprs.iter().map(|expr| print_expr(expr, 0)).collect::<Vec<String>>().join(", ");
+    let text = match &expr.node {
+      ExprAST::Integer(value) => value.to_string(),
+      ExprAST::Float(value) => format!("{:?}", value),
+      ExprAST::Boolean(value) => value.to_string(),
+      ExprAST::Variable(name) => name.clone(),
+      ExprAST::StringLiteral(text) => format!("\"{}\"", escape_string(text)),
+      // A space keeps `- -x` from printing as the `--` of a decrement
+      ExprAST::Unary(op, operand) => match (op, print_expr(operand, UNARY_PRECEDENCE + 1)) {
+        (UnaryOp::Negate, operand) if operand.starts_with('-') => format!("- {}", operand),
+        (op, operand) => format!("{}{}", op, operand),
+      },
+      ExprAST::Binary(op, lhs, rhs) | ExprAST::Logical(op, lhs, rhs) => {
+        let (lhs_binding, rhs_binding) = match op.associativity() {
+          Associativity::Left => (op.precedence(), op.precedence() + 1),
+          Associativity::Right => (op.precedence() + 1, op.precedence()),
+        };
+        format!("{} {} {}", print_expr(lhs, lhs_binding), op, print_expr(rhs, rhs_binding))
+      },
+      ExprAST::Conditional(condition, a, b) => format!("{} ? {} : {}", print_expr(condition, 1), print_expr(a, 0), print_expr(b, 0)),
+      ExprAST::Call(name, args) => format!("{}({})", name, list(args)),
+      ExprAST::ArrayLiteral(elements) => format!("[{}]", list(elements)),
+      ExprAST::Tuple(elements) if elements.len() == 1 => format!("({},)", list(elements)),
+      ExprAST::Tuple(elements) => format!("({})", list(elements)),
+      ExprAST::Index(target, i) => format!("{}[{}]", print_expr(target, 12), print_expr(i, 0)),
+      ExprAST::Field(target, field) => format!("{}.{}", print_receiver(target), field),
+      ExprAST::MethodCall(receiver, method, args) => format!("{}.{}({})", print_receiver(receiver), method, list(args)),
+      ExprAST::Interpolation(parts) => {
+        let mut text = String::from("\"");
+        for (i, part) in parts.iter().enumerate() {
+          match (i % 2, &part.node) {
+            (0, ExprAST::StringLiteral(piece)) => text.push_str(&escape_string(piece)),
+            _ => text.push_str(&format!("${{{}}}", print_expr(part, 0))),
+          }
+        }
+        text.push('"');
+        text
+      },
+      ExprAST::StructLiteral(name, fields) => {
+        let fields: Vec<String> = fields.iter().map(|(field, value)| format!("{}: {}", field, print_expr(value, 0))).collect();
+        format!("{} {{ {} }}", name, fields.join(", "))
+      },
+      ExprAST::Function(..) | ExprAST::Lambda(..) => unreachable!("not generated"),
+    };
+    match binding(&expr.node) < min_binding {
+      true => return format!("({})", text),
+      false => return text,
+    }
+  }
+
+  // Print the value before a `.`, in parentheses if it's a number, which would otherwise take the dot as its
+  // decimal point
+  fn print_receiver(receiver: &Expr) -> String {
+    match receiver.node {
+      ExprAST::Integer(_) | ExprAST::Float(_) => return format!("({})", print_expr(receiver, 0)),
+      _ => return print_expr(receiver, 12),
+    }
+  }
+
+  // Print a statement as SIL source, one statement per line and each block indented under its opener
+  fn print_stmt(stmt: &Stmt, depth: usize) -> String {
+    let indent = "  ".repeat(depth);
+    let print_block = |stmt: &Stmt| match &stmt.node {
+      StmtAST::Block(statements) => {
+        let lines: Vec<String> = statements.iter().map(|stmt| format!("{}\n", print_stmt(stmt, depth + 1))).collect();
+        format!("{{\n{}{}}}", lines.concat(), indent)
+      },
+      _ => unreachable!("not a block"),
+    };
+    let text = match &stmt.node {
+      StmtAST::Let(name, None, value) => format!("let {} = {}", name, print_expr(value, 0)),
+      StmtAST::Let(name, Some(annotation), value) => format!("let {}: {} = {}", name, annotation, print_expr(value, 0)),
+      StmtAST::Const(name, None, value) => format!("const {} = {}", name, print_expr(value, 0)),
+      StmtAST::Const(name, Some(annotation), value) => format!("const {}: {} = {}", name, annotation, print_expr(value, 0)),
+      StmtAST::LetTuple(names, value) => {
+        let names: Vec<String> = names.iter().map(|name| name.node.name.clone()).collect();
+        format!("let ({}) = {}", names.join(", "), print_expr(value, 0))
+      },
+      StmtAST::Assign(target, value) => format!("{} = {}", print_expr(target, 0), print_expr(value, 0)),
+      StmtAST::Return(None) => "return".to_string(),
+      StmtAST::Return(Some(value)) => format!("return {}", print_expr(value, 0)),
+      StmtAST::Break => "break".to_string(),
+      StmtAST::Continue => "continue".to_string(),
+      StmtAST::If(condition, then_block, else_block) => {
+        let mut text = format!("if {} {}", print_condition(condition), print_block(then_block));
+        match else_block.as_deref() {
+          Some(else_if @ Spanned { node: StmtAST::If(..), .. }) => text.push_str(&format!(" else {}", print_stmt(else_if, depth).trim_start())),
+          Some(else_block) => text.push_str(&format!(" else {}", print_block(else_block))),
+          None => {},
+        }
+        text
+      },
+      StmtAST::While(condition, body) => format!("while {} {}", print_condition(condition), print_block(body)),
+      StmtAST::ExprStmt(expr) => print_expr(expr, 0),
+      StmtAST::Block(_) => print_block(stmt),
+      StmtAST::Struct(name, fields) => format!("struct {} {{ {} }}", name, fields.join(", ")),
+      // Only allowed at the top level, and generated statements can end up nested in blocks
+      StmtAST::Import(_) => unreachable!("not generated"),
+    };
+    return format!("{}{}", indent, text);
+  }
+
+  // Print an if or while condition, in parentheses if a struct literal in it would otherwise open the block
+  fn print_condition(condition: &Expr) -> String {
+    struct StructLiterals(bool);
+    impl Visitor for StructLiterals {
+      fn visit_expr(&mut self, expr: &Expr) {
+        self.0 |= matches!(expr.node, ExprAST::StructLiteral(..));
+        walk_expr(self, expr);
+      }
+    }
+    let mut found = StructLiterals(false);
+    found.visit_expr(condition);
+    match found.0 {
+      true => return format!("({})", print_expr(condition, 0)),
+      false => return print_expr(condition, 0),
+    }
+  }
+
+  proptest::proptest! {
+    #[test]
+    fn print_then_parse_round_trips(program in collection::vec(arb_stmt(), 0..4)) {
+      let lines: Vec<String> = program.iter().map(|stmt| print_stmt(stmt, 0)).collect();
+      let source = lines.join("\n");
+      let reparsed = parse(&source);
+      assert_eq!(reparsed.as_ref().err(), None, "{}", source);
+      assert!(reparsed.unwrap() == program, "{}\nreparsed differently, as\n{}", source, dump(&parse(&source).unwrap()));
+    }
+  }
+}
@@ -0,0 +1,437 @@
+use std::collections::HashSet;
+
+use crate::callgraph::{unbounded_recursion, CallGraph};
+use crate::calls::check_calls;
+use crate::captures::resolve_captures;
+use crate::dce::{eliminate_dead_branches, eliminate_dead_functions};
+use crate::diagnostic::{Diagnostic, LintLevels, Severity};
+use crate::escape::{escape_analysis, Capture};
+use crate::flow::{check_placement, check_returns, unreachable_code};
+use crate::fold::fold_constants;
+use crate::lints::{shadowed_variables, unused_variables};
+use crate::mutability::check_assignments;
+use crate::parser::{NodeId, NodeMap, Program};
+use crate::purity::pure_functions;
+use crate::resolver::resolve;
+use crate::typeck::{check_types, Ty};
+
+// What the passes over a program share: the tables earlier passes work out for later ones, and what they
+// all report
+#[derive(Debug, Default)]
+pub struct CompilationContext {
+  pub declarations: NodeMap<NodeId>,   // What declares each variable and call, from the resolve pass
+  pub types: NodeMap<Ty>,              // The type of each expression and declaration, from the typecheck pass
+  pub call_graph: CallGraph,           // Which functions use which, from the call-graph pass
+  pub pure: HashSet<NodeId>,           // The functions that do nothing but return a value, from the purity pass
+  pub captures: NodeMap<Vec<Capture>>, // Where each function's captured variables live, from the escape pass
+  pub errors: Vec<Diagnostic>,
+  pub warnings: Vec<Diagnostic>,
+  pub notes: Vec<Diagnostic>,          // What the optimization passes changed, shown with --verbose
+  pub lints: LintLevels,               // Which warnings to report, and which to make errors
+}
+
+impl CompilationContext {
+  // Record what a pass that can fail found, keeping the table it produced if it succeeded
+  fn report<T: Default>(&mut self, result: Result<T, Vec<Diagnostic>>) -> T {
+    match result {
+      Ok(table) => return table,
+      Err(errors) => {
+        self.errors.extend(errors);
+        return T::default();
+      },
+    }
+  }
+
+  // Record the warnings a lint found at its level, dropping those it allows and making those it denies errors
+  fn warn(&mut self, warnings: Vec<Diagnostic>) {
+    for warning in warnings {
+      match self.lints.apply(warning) {
+        Some(error) if error.severity == Severity::Error => self.errors.push(error),
+        Some(warning) => self.warnings.push(warning),
+        None => {},
+      }
+    }
+  }
+}
+
+// One step of compiling a program, which reads or rewrites it and records what it finds in the context
+pub trait Pass {
+  // A short name for the pass, as used on the command line
+  fn name(&self) -> &'static str;
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext);
+}
+
+// Runs passes over a program in the order they were added. A pass that reports errors stops the passes
+// after it, since they rely on what it should have worked out, but warnings made errors by denying their
+// lint don't, as nothing relies on them
+#[derive(Default)]
+pub struct PassManager {
+  passes: Vec<Box<dyn Pass>>,
+  lints: LintLevels,
+}
+
+impl PassManager {
+  pub fn new() -> Self {
+    return PassManager { passes: Vec::new(), lints: LintLevels::default() };
+  }
+
+  // The passes the compiler runs: resolving names, the semantic checks, type checking, the lints and the
+  // call graph, then constant folding. Folding is run for its diagnostics at every level, but only -O1 and
+  // above keep the folded program and go on to remove dead code. Escape analysis comes last, so it sees the
+  // functions and captures that are left
+  pub fn standard(optimize: u8) -> Self {
+    let mut passes = PassManager::new();
+    passes.add(Captures)
+      .add(Resolve)
+      .add(Calls)
+      .add(Mutability)
+      .add(Placement)
+      .add(TypeCheck)
+      .add(Returns)
+      .add(UnreachableCode)
+      .add(UnusedVariables)
+      .add(ShadowedVariables)
+      .add(BuildCallGraph)
+      .add(Purity)
+      .add(ConstantFolding { keep: optimize >= 1 });
+    if optimize >= 1 {
+      passes.add(DeadCode);
+    }
+    passes.add(Escape);
+    return passes;
+  }
+
+  pub fn add(&mut self, pass: impl Pass + 'static) -> &mut Self {
+    self.passes.push(Box::new(pass));
+    return self;
+  }
+
+  // Set which warnings the passes report, and which they make errors
+  pub fn set_lints(&mut self, lints: LintLevels) -> &mut Self {
+    self.lints = lints;
+    return self;
+  }
+
+  pub fn names(&self) -> Vec<&'static str> {
+    return self.passes.iter().map(|pass| pass.name()).collect();
+  }
+
+  pub fn run(&mut self, program: &mut Program) -> CompilationContext {
+    let mut context = CompilationContext { lints: self.lints.clone(), ..CompilationContext::default() };
+    for pass in &mut self.passes {
+      pass.run(program, &mut context);
+      if context.errors.iter().any(|error| error.lint.is_none()) {
+        break;
+      }
+    }
+    return context;
+  }
+}
+
+// Fills in what each function captures
+pub struct Captures;
+
+impl Pass for Captures {
+  fn name(&self) -> &'static str {
+    return "captures";
+  }
+
+  fn run(&mut self, program: &mut Program, _context: &mut CompilationContext) {
+    resolve_captures(program);
+  }
+}
+
+// Works out what every variable refers to, filling in the declarations table
+pub struct Resolve;
+
+impl Pass for Resolve {
+  fn name(&self) -> &'static str {
+    return "resolve";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.declarations = context.report(resolve(program));
+  }
+}
+
+// Checks calls are to defined functions with the right number of arguments
+pub struct Calls;
+
+impl Pass for Calls {
+  fn name(&self) -> &'static str {
+    return "calls";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.report(check_calls(program, &context.declarations));
+  }
+}
+
+// Checks nothing assigns to a const
+pub struct Mutability;
+
+impl Pass for Mutability {
+  fn name(&self) -> &'static str {
+    return "mutability";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.report(check_assignments(program, &context.declarations));
+  }
+}
+
+// Checks break, continue and return are where they can be
+pub struct Placement;
+
+impl Pass for Placement {
+  fn name(&self) -> &'static str {
+    return "placement";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.report(check_placement(program));
+  }
+}
+
+// Infers and checks types, filling in the types table
+pub struct TypeCheck;
+
+impl Pass for TypeCheck {
+  fn name(&self) -> &'static str {
+    return "typecheck";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.types = context.report(check_types(program, &context.declarations));
+  }
+}
+
+// Checks functions that return a value do on every path
+pub struct Returns;
+
+impl Pass for Returns {
+  fn name(&self) -> &'static str {
+    return "returns";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.report(check_returns(program));
+  }
+}
+
+// Warns about statements that can never run
+pub struct UnreachableCode;
+
+impl Pass for UnreachableCode {
+  fn name(&self) -> &'static str {
+    return "unreachable-code";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.warn(unreachable_code(program));
+  }
+}
+
+// Warns about variables and parameters nothing reads
+pub struct UnusedVariables;
+
+impl Pass for UnusedVariables {
+  fn name(&self) -> &'static str {
+    return "unused-variables";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    let warnings = unused_variables(program, &context.declarations);
+    context.warn(warnings);
+  }
+}
+
+// Warns about declarations that hide another of the same name, which is off unless -Wshadow turns it on
+pub struct ShadowedVariables;
+
+impl Pass for ShadowedVariables {
+  fn name(&self) -> &'static str {
+    return "shadowed-variables";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.warn(shadowed_variables(program));
+  }
+}
+
+// Works out which functions use which, filling in the call graph, and warns about functions that can only
+// recurse forever
+pub struct BuildCallGraph;
+
+impl Pass for BuildCallGraph {
+  fn name(&self) -> &'static str {
+    return "call-graph";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.call_graph = CallGraph::build(program, &context.declarations);
+    let warnings = unbounded_recursion(program, &context.declarations, &context.call_graph);
+    context.warn(warnings);
+  }
+}
+
+// Works out which functions are pure, for folding calls to them
+pub struct Purity;
+
+impl Pass for Purity {
+  fn name(&self) -> &'static str {
+    return "purity";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.pure = pure_functions(program, &context.declarations, &context.call_graph);
+  }
+}
+
+// Folds constants, reporting division by zero and overflow. Unless `keep` is set, a copy of the program is
+// folded and thrown away, for the diagnostics alone
+pub struct ConstantFolding {
+  pub keep: bool,
+}
+
+impl Pass for ConstantFolding {
+  fn name(&self) -> &'static str {
+    return "const-fold";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    let mut folded = program.clone();
+    let warnings = context.report(fold_constants(&mut folded, &context.declarations, &context.pure));
+    context.warn(warnings);
+    if self.keep && context.errors.is_empty() {
+      *program = folded;
+      // Folding can remove the only use of a captured variable
+      resolve_captures(program);
+    }
+  }
+}
+
+// Removes branches that never run and functions that are never called, rebuilding the call graph from
+// what's left. Meant to run after constant folding, which works out the conditions that never change
+pub struct DeadCode;
+
+impl Pass for DeadCode {
+  fn name(&self) -> &'static str {
+    return "dead-code";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    let mut notes = eliminate_dead_branches(program);
+    let graph = CallGraph::build(program, &context.declarations);
+    notes.extend(eliminate_dead_functions(program, &graph));
+    notes.sort_by_key(|note| note.span.start);
+    context.notes.extend(notes);
+    context.call_graph = CallGraph::build(program, &context.declarations);
+  }
+}
+
+// Works out which captured variables have to live on the heap, filling in the captures table
+pub struct Escape;
+
+impl Pass for Escape {
+  fn name(&self) -> &'static str {
+    return "escape";
+  }
+
+  fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+    context.captures = escape_analysis(program, &context.declarations);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::diagnostic::Level;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+
+  fn parse(source: &str) -> Program {
+    let tokens = lex(source).unwrap();
+    return Parser::new(&tokens).parse().unwrap();
+  }
+
+  fn messages(diagnostics: &[Diagnostic]) -> Vec<String> {
+    return diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+  }
+
+  #[test]
+  fn run_standard_passes() {
+    let source: String = "def f(x, y) { return x * 2 }\nconst k = 3 + 4\nlet unused = f(k, true)".to_string();
+    let mut program = parse(&source);
+    let f = program.functions[0].id;
+    let mut passes = PassManager::standard(1);
+    assert_eq!(passes.names(), vec![
+      "captures", "resolve", "calls", "mutability", "placement", "typecheck", "returns", "unreachable-code", "unused-variables",
+      "shadowed-variables", "call-graph", "purity", "const-fold", "dead-code", "escape",
+    ]);
+    let context = passes.run(&mut program);
+    assert_eq!(context.errors, vec![]);
+    assert_eq!(messages(&context.warnings), vec!["Parameter 'y' is never read at 1:10", "Variable 'unused' is never read at 3:1"]);
+    assert_eq!(context.types[&f].to_string(), "def(int, bool) -> int");
+    assert_eq!(context.pure, HashSet::from([f]));
+    // The call to f is run, and f is removed now nothing calls it
+    assert_eq!(program.to_string(), "(const k 7)\n(let unused 14)\n");
+    assert_eq!(messages(&context.notes), vec!["Removed function 'f', which is never called at 1:1"]);
+
+    // Without optimizing, the program is left as it was written
+    program = parse(&source);
+    PassManager::standard(0).run(&mut program);
+    assert_eq!(program.to_string(), "(def f (x y)\n  (return (* x 2)))\n(const k (+ 3 4))\n(let unused (call f k true))\n");
+  }
+
+  #[test]
+  fn apply_lint_levels() {
+    let source: String = "let x = 1\n{ let x = 2; print(x) }".to_string();
+    let mut lints = LintLevels::default();
+    lints.set("shadow", Level::Warn).unwrap();
+    lints.set("unused", Level::Deny).unwrap();
+    let context = PassManager::standard(0).set_lints(lints.clone()).run(&mut parse(&source));
+    assert_eq!(messages(&context.errors), vec!["Variable 'x' is never read at 1:1"]);
+    assert_eq!(context.errors[0].severity, Severity::Error);
+    assert_eq!(messages(&context.warnings), vec!["Variable 'x' shadows an earlier declaration at 2:3\n  note: 'x' is declared here at 1:1"]);
+
+    // With -Werror every warning is an error, and allowed lints still report nothing
+    lints.warnings_are_errors = true;
+    lints.set("unused", Level::Allow).unwrap();
+    let context = PassManager::standard(0).set_lints(lints).run(&mut parse(&source));
+    assert_eq!(messages(&context.errors), vec!["Variable 'x' shadows an earlier declaration at 2:3\n  note: 'x' is declared here at 1:1"]);
+    assert_eq!(context.warnings, vec![]);
+  }
+
+  // Warns about every statement at the top level, to show it ran and saw the declarations table
+  struct TopLevel;
+
+  impl Pass for TopLevel {
+    fn name(&self) -> &'static str {
+      return "top-level";
+    }
+
+    fn run(&mut self, program: &mut Program, context: &mut CompilationContext) {
+      for stmt in &program.top_level {
+        let message = format!("Statement with {} names resolved so far", context.declarations.len());
+        context.warnings.push(Diagnostic::new(message, stmt.span));
+      }
+    }
+  }
+
+  #[test]
+  fn run_custom_passes() {
+    let mut passes = PassManager::new();
+    passes.add(Resolve).add(TopLevel);
+    assert_eq!(passes.names(), vec!["resolve", "top-level"]);
+
+    let context = passes.run(&mut parse("let a = 1\nlet b = a + a"));
+    assert_eq!(messages(&context.warnings), vec!["Statement with 2 names resolved so far at 1:1", "Statement with 2 names resolved so far at 2:1"]);
+
+    // A pass that fails stops the ones after it
+    let context = passes.run(&mut parse("let a = b"));
+    assert_eq!(messages(&context.errors), vec!["Use of undefined variable 'b' at 1:9"]);
+    assert_eq!(context.warnings, vec![]);
+  }
+}
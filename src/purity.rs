@@ -0,0 +1,207 @@
+use std::collections::HashSet;
+
+use crate::callgraph::CallGraph;
+use crate::parser::{Expr, ExprAST, NodeId, NodeMap, Param, Program, Spanned, Stmt, StmtAST};
+use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+// The functions in `program` that are pure, calling one doing nothing but work out the value it returns, by
+// the id of what declares them as in the call graph. A pure function doesn't assign to anything declared
+// outside it, doesn't change an array or struct it didn't make itself, doesn't print, and only calls or
+// refers to other pure functions. Calls through parameters and variables could be to anything, and so could
+// method calls, so a function making one isn't pure. A let holding a lambda can be given another one, so
+// only defs and consts holding lambdas are considered
+pub fn pure_functions(program: &Program, declarations: &NodeMap<NodeId>, graph: &CallGraph) -> HashSet<NodeId> {
+  let mut functions = Functions { declarations, graph, pure: HashSet::new() };
+  for function in &program.functions {
+    functions.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    functions.visit_stmt(stmt);
+  }
+
+  // A function that uses an impure function is impure itself, which can make the functions using it impure
+  let mut pure = functions.pure;
+  loop {
+    let impure: Vec<NodeId> = pure.iter()
+      .filter(|function| !graph.uses(**function).iter().all(|used| pure.contains(used)))
+      .copied()
+      .collect();
+    if impure.is_empty() {
+      return pure;
+    }
+    for function in impure {
+      pure.remove(&function);
+    }
+  }
+}
+
+// Finds the functions whose own bodies have no effects, leaving what they use to be checked after
+struct Functions<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  graph: &'a CallGraph,
+  pure: HashSet<NodeId>,
+}
+
+impl Functions<'_> {
+  fn check(&mut self, id: NodeId, params: &[Spanned<Param>], body: &[Stmt]) {
+    let locals = params.iter().map(|param| param.id).collect();
+    let mut effects = Effects { declarations: self.declarations, graph: self.graph, locals, arrays: HashSet::new(), found: false };
+    for stmt in body {
+      effects.visit_stmt(stmt);
+    }
+    if !effects.found {
+      self.pure.insert(id);
+    }
+  }
+}
+
+impl Visitor for Functions<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Function(_, params, _, body, _) = &expr.node {
+      self.check(expr.id, params, body);
+    }
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    if let StmtAST::Const(_, _, value) = &stmt.node && let ExprAST::Lambda(params, _, body, _) = &value.node {
+      self.check(stmt.id, params, body);
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+// Looks for anything in a function's body that has an effect outside it
+struct Effects<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  graph: &'a CallGraph,
+  locals: HashSet<NodeId>,   // What the function itself declares, which it can assign to as it likes
+  arrays: HashSet<NodeId>,   // The variables holding an array or struct literal the function made, which it can change as it likes
+  found: bool,
+}
+
+impl Effects<'_> {
+  fn declared_in(&self, set: &HashSet<NodeId>, variable: &Expr) -> bool {
+    return self.declarations.get(&variable.id).is_some_and(|declaration| set.contains(declaration));
+  }
+
+  // Assigning to a variable only changes the variable, but assigning to an element of an array or a field of
+  // a struct, or pushing onto an array, changes the value, which a parameter or outer variable could share
+  // with the caller
+  fn changes(&mut self, target: &Expr, value: Option<&Expr>) {
+    match &target.node {
+      ExprAST::Variable(_) if value.is_some() => {
+        if !self.declared_in(&self.locals, target) {
+          self.found = true;
+          return;
+        }
+        let declaration = self.declarations[&target.id];
+        match value.map(|value| &value.node) {
+          Some(ExprAST::ArrayLiteral(_) | ExprAST::StructLiteral(..)) => self.arrays.insert(declaration),
+          _ => self.arrays.remove(&declaration),
+        };
+      },
+      ExprAST::Variable(_) | ExprAST::Index(..) | ExprAST::Field(..) => {
+        let mut array = target;
+        while let ExprAST::Index(inner, _) | ExprAST::Field(inner, _) = &array.node {
+          array = inner;
+        }
+        if !matches!(array.node, ExprAST::Variable(_)) || !self.declared_in(&self.arrays, array) {
+          self.found = true;
+        }
+      },
+      _ => self.found = true,
+    }
+  }
+}
+
+impl Visitor for Effects<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    match &expr.node {
+      ExprAST::Function(_, params, ..) | ExprAST::Lambda(params, ..) => {
+        self.locals.insert(expr.id);
+        self.locals.extend(params.iter().map(|param| param.id));
+      },
+      ExprAST::Call(name, args) => match self.declarations.get(&expr.id) {
+        Some(declaration) if self.graph.names.contains_key(declaration) => {},
+        Some(_) => self.found = true,
+        None => match name.as_str() {
+          "print" => self.found = true,
+          "push" => self.changes(&args[0], None),
+          _ => {},
+        },
+      },
+      ExprAST::MethodCall(..) => self.found = true,
+      _ => {},
+    }
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Let(_, _, value) | StmtAST::Const(_, _, value) => {
+        self.locals.insert(stmt.id);
+        if let ExprAST::ArrayLiteral(_) | ExprAST::StructLiteral(..) = value.node {
+          self.arrays.insert(stmt.id);
+        }
+      },
+      StmtAST::LetTuple(names, _) => self.locals.extend(names.iter().map(|name| name.id)),
+      StmtAST::Assign(target, value) => self.changes(target, Some(value)),
+      _ => {},
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::resolver::resolve;
+
+  fn pure(source: &str) -> Vec<String> {
+    let tokens = lex(source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    let graph = CallGraph::build(&program, &declarations);
+    let mut names: Vec<String> = pure_functions(&program, &declarations, &graph).iter().map(|function| graph.names[function].clone()).collect();
+    names.sort();
+    return names;
+  }
+
+  #[test]
+  fn find_pure_functions() {
+    let source: String = r#"
+struct Point { x, y }
+let total = 0
+def square(x) { return x * x }
+def sum_squares(n) {
+  let sum = 0
+  let i = 0
+  while i < n { sum = sum + square(i); i = i + 1 }
+  return sum
+}
+def fill(n) {
+  let xs = []
+  push(xs, n)
+  xs[0] = n
+  return xs
+}
+def shout(x) { print(x); return x }
+def count(x) { total = total + x; return total }
+def grow(xs) { push(xs, 1); return xs }
+def alias(xs) { let ys = xs; ys[0] = 1; return ys }
+def rebind(xs) { xs = [0]; xs[0] = 1; return xs }
+def loud_square(x) { return shout(x) * x }
+def apply(f, x) { return f(x) }
+const cube = def(x) { return square(x) * x }
+let twice = def(x) { return x * 2 }
+def quadruple(x) { return twice(twice(x)) }
+def origin() { let p = Point { x: 0, y: 0 }; p.x = 1; return p }
+def move(p) { p.x = 1; return p }
+def size(xs) { return xs.len() }
+"#.to_string();
+    assert_eq!(pure(&source), vec!["cube", "fill", "origin", "rebind", "square", "sum_squares"]);
+  }
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+
+use crate::builtins::{builtin, BUILTINS};
+use crate::diagnostic::{closest_match, Diagnostic};
+use crate::lexer::Span;
+use crate::parser::{Expr, ExprAST, NodeId, NodeMap, Program, Stmt, StmtAST};
+use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+// Work out which declaration every variable in `program` refers to, returning a table from each Variable
+// node's id to the id of the node declaring it: the Let or Const statement, the parameter, or the Function.
+// Calls whose name is in scope, like a call to a parameter holding a lambda, go in the table too, and a call
+// to a name that isn't is an error unless it's a builtin. Errors suggest the name in scope closest to the
+// one used, or point at the function of that name if there's one somewhere out of scope.
+//
+// Scoping is lexical. Blocks and function bodies open scopes, and a let is in scope from the statement after
+// it to the end of its block, so `let x = x` reads an outer x. Top-level functions are in scope everywhere,
+// so they can be called before they're defined, and each function is in scope in its own body
+pub fn resolve(program: &Program) -> Result<NodeMap<NodeId>, Vec<Diagnostic>> {
+  let mut functions = Functions { found: Vec::new() };
+  for function in &program.functions {
+    functions.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    functions.visit_stmt(stmt);
+  }
+
+  let mut resolver = Resolver { scopes: vec![HashMap::new()], functions: functions.found, declarations: NodeMap::new(), errors: Vec::new() };
+  for function in &program.functions {
+    if let ExprAST::Function(name, ..) = &function.node {
+      resolver.declare(name, function.id);
+    }
+  }
+
+  // Functions and top-level statements are walked in the order they're written, so a function sees the
+  // globals declared above it
+  let mut functions = program.functions.iter().peekable();
+  for stmt in &program.top_level {
+    while let Some(function) = functions.next_if(|function| function.span.start < stmt.span.start) {
+      resolver.visit_expr(function);
+    }
+    resolver.visit_stmt(stmt);
+  }
+  for function in functions {
+    resolver.visit_expr(function);
+  }
+
+  match resolver.errors.is_empty() {
+    true => return Ok(resolver.declarations),
+    false => return Err(resolver.errors),
+  }
+}
+
+struct Resolver {
+  scopes: Vec<HashMap<String, NodeId>>,   // The names in scope and what declares them, innermost scope last
+  functions: Vec<(String, Span)>,         // Every function in the program, in scope or not, and where it's declared
+  declarations: NodeMap<NodeId>,
+  errors: Vec<Diagnostic>,
+}
+
+impl Resolver {
+  fn declare(&mut self, name: &str, declaration: NodeId) {
+    self.scopes.last_mut().unwrap().insert(name.to_string(), declaration);
+  }
+
+  fn lookup(&self, name: &str) -> Option<NodeId> {
+    return self.scopes.iter().rev().find_map(|scope| scope.get(name).copied());
+  }
+
+  // An error for using `name` where nothing by that name is in scope
+  fn undefined(&self, message: String, name: &str, span: Span, builtins: bool) -> Diagnostic {
+    let error = Diagnostic::new(message, span);
+    if let Some((_, declared_at)) = self.functions.iter().find(|(function, _)| function == name) {
+      return error.with_note(format!("'{}' is defined here, but isn't in scope where it's used", name), *declared_at);
+    }
+
+    // Innermost scope first, and in the order they're declared within a scope, so of two names as close
+    // as each other the nearest is suggested
+    let mut candidates: Vec<&str> = Vec::new();
+    for scope in self.scopes.iter().rev() {
+      let mut names: Vec<(&String, &NodeId)> = scope.iter().collect();
+      names.sort_by_key(|(_, declaration)| **declaration);
+      candidates.extend(names.into_iter().map(|(name, _)| name.as_str()));
+    }
+    if builtins {
+      candidates.extend(BUILTINS.iter().map(|builtin| builtin.name));
+    }
+    match closest_match(name, candidates) {
+      Some(closest) if self.lookup(closest).is_none() => return error.with_help(format!("did you mean the builtin '{}'?", closest), span),
+      Some(closest) => return error.with_help(format!("did you mean '{}'?", closest), span),
+      None => return error,
+    }
+  }
+
+  // Run `walk` inside a new scope
+  fn scoped(&mut self, walk: impl FnOnce(&mut Self)) {
+    self.scopes.push(HashMap::new());
+    walk(self);
+    self.scopes.pop();
+  }
+}
+
+impl Visitor for Resolver {
+  fn visit_expr(&mut self, expr: &Expr) {
+    match &expr.node {
+      ExprAST::Variable(name) => match self.lookup(name) {
+        Some(declaration) => {
+          self.declarations.insert(expr.id, declaration);
+        },
+        None => {
+          let error = self.undefined(format!("Use of undefined variable '{}'", name), name, expr.span, false);
+          self.errors.push(error);
+        },
+      },
+      ExprAST::Call(name, _) => {
+        match self.lookup(name) {
+          Some(declaration) => {
+            self.declarations.insert(expr.id, declaration);
+          },
+          None if builtin(name).is_none() => {
+            let error = self.undefined(format!("Call to undefined function '{}'", name), name, expr.span, true);
+            self.errors.push(error);
+          },
+          None => {},
+        }
+        walk_expr(self, expr);
+      },
+      ExprAST::Function(name, params, _, body, _) => {
+        self.declare(name, expr.id);
+        self.scoped(|resolver| {
+          for param in params {
+            resolver.declare(&param.node.name, param.id);
+          }
+          for stmt in body {
+            resolver.visit_stmt(stmt);
+          }
+        });
+      },
+      ExprAST::Lambda(params, _, body, _) => self.scoped(|resolver| {
+        for param in params {
+          resolver.declare(&param.node.name, param.id);
+        }
+        for stmt in body {
+          resolver.visit_stmt(stmt);
+        }
+      }),
+      _ => walk_expr(self, expr),
+    }
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Let(name, _, value) | StmtAST::Const(name, _, value) => {
+        self.visit_expr(value);
+        self.declare(name, stmt.id);
+      },
+      StmtAST::LetTuple(names, value) => {
+        self.visit_expr(value);
+        for name in names {
+          self.declare(&name.node.name, name.id);
+        }
+      },
+      StmtAST::Block(_) => self.scoped(|resolver| walk_stmt(resolver, stmt)),
+      _ => walk_stmt(self, stmt),
+    }
+  }
+}
+
+// Collects the name and location of every function, and of every let or const whose value is a lambda
+struct Functions {
+  found: Vec<(String, Span)>,
+}
+
+impl Visitor for Functions {
+  fn visit_expr(&mut self, expr: &Expr) {
+    if let ExprAST::Function(name, ..) = &expr.node {
+      self.found.push((name.clone(), expr.span));
+    }
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    if let StmtAST::Let(name, _, value) | StmtAST::Const(name, _, value) = &stmt.node && let ExprAST::Lambda(..) = value.node {
+      self.found.push((name.clone(), stmt.span));
+    }
+    walk_stmt(self, stmt);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+
+  fn parse(source: &str) -> Program {
+    let tokens = lex(source).unwrap();
+    return Parser::new(&tokens).parse().unwrap();
+  }
+
+  // A line and column
+  type At = (u32, u32);
+
+  // Every variable in `program` with where it is and where its declaration is
+  fn resolutions(program: &Program, declarations: &NodeMap<NodeId>) -> Vec<(String, At, At)> {
+    struct Uses<'a> {
+      declarations: &'a NodeMap<NodeId>,
+      spans: HashMap<NodeId, At>,
+      found: Vec<(String, NodeId, At)>,
+    }
+    impl Visitor for Uses<'_> {
+      fn visit_expr(&mut self, expr: &Expr) {
+        self.spans.insert(expr.id, (expr.span.line, expr.span.column));
+        match &expr.node {
+          ExprAST::Variable(name) => self.found.push((name.clone(), self.declarations[&expr.id], (expr.span.line, expr.span.column))),
+          ExprAST::Function(_, params, ..) | ExprAST::Lambda(params, ..) => {
+            for param in params {
+              self.spans.insert(param.id, (param.span.line, param.span.column));
+            }
+          },
+          _ => {},
+        }
+        walk_expr(self, expr);
+      }
+
+      fn visit_stmt(&mut self, stmt: &Stmt) {
+        self.spans.insert(stmt.id, (stmt.span.line, stmt.span.column));
+        if let StmtAST::LetTuple(names, _) = &stmt.node {
+          for name in names {
+            self.spans.insert(name.id, (name.span.line, name.span.column));
+          }
+        }
+        walk_stmt(self, stmt);
+      }
+    }
+
+    let mut uses = Uses { declarations, spans: HashMap::new(), found: Vec::new() };
+    for function in &program.functions {
+      uses.visit_expr(function);
+    }
+    for stmt in &program.top_level {
+      uses.visit_stmt(stmt);
+    }
+    return uses.found.into_iter().map(|(name, declaration, at)| (name, at, uses.spans[&declaration])).collect();
+  }
+
+  #[test]
+  fn resolve_to_declarations() {
+    let source: String = r#"
+let total = 0
+def add(n) {
+  let (sum, count) = (total + n, 1)
+  { let total = sum; total = total * 2 }
+  return def(x) { return x + total + add }
+}
+const limit = add(1)
+let total = total + limit
+"#.to_string();
+    let program = parse(&source);
+    let declarations = resolve(&program).unwrap();
+    let found: Vec<(String, At, At)> = resolutions(&program, &declarations);
+    let expected = [
+      ("total", (4, 23), (2, 1)),    // A global declared above the function
+      ("n", (4, 31), (3, 9)),        // A parameter
+      ("sum", (5, 17), (4, 8)),      // A destructured name
+      ("total", (5, 22), (5, 5)),    // The block's own total shadows the global
+      ("total", (5, 30), (5, 5)),
+      ("x", (6, 26), (6, 14)),       // A lambda parameter
+      ("total", (6, 30), (2, 1)),    // Out of the block, total is the global again
+      ("add", (6, 38), (3, 1)),      // A function in its own body
+      ("total", (9, 13), (2, 1)),    // A let's value reads the total from before it
+      ("limit", (9, 21), (8, 1)),
+    ];
+    assert_eq!(found, expected.map(|(name, at, declared)| (name.to_string(), at, declared)));
+  }
+
+  #[test]
+  fn report_undefined_variables() {
+    let mut source: String = "def f() { return later + g() }\nlet later = 1\n{ let inner = 2 }\nprint(inner)\nlet self_ref = self_ref".to_string();
+    let errors = resolve(&parse(&source)).err().unwrap();
+    let messages: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+    assert_eq!(messages, vec![
+      "Use of undefined variable 'later' at 1:18",
+      "Call to undefined function 'g' at 1:26\n  help: did you mean 'f'?",
+      "Use of undefined variable 'inner' at 4:7",
+      "Use of undefined variable 'self_ref' at 5:16",
+    ]);
+
+    // Top-level functions can be called from above their definition, but nested ones can't be used before theirs
+    source = "let x = twice(1)\ndef twice(n) { return n * 2 }\ndef outer() {\n  let f = inner\n  def inner() { }\n}".to_string();
+    let messages: Vec<String> = resolve(&parse(&source)).err().unwrap().iter().map(|error| error.to_string()).collect();
+    assert_eq!(messages, vec!["Use of undefined variable 'inner' at 4:11\n  note: 'inner' is defined here, but isn't in scope where it's used at 5:3"]);
+  }
+
+  #[test]
+  fn suggest_names_in_scope() {
+    let source: String = r#"
+def fibonacci(n) { return n }
+fibonaci(3)
+prnt(1)
+undefined_thing()
+def outer() { inner(); def inner() { } }
+def scale(count, factor) {
+  let total = 0
+  { let counter = 1; total = countr + factor }
+  return cont * factr
+}
+print(count)
+"#.to_string();
+    let messages: Vec<String> = resolve(&parse(&source)).err().unwrap().iter().map(|error| error.to_string()).collect();
+    assert_eq!(messages, vec![
+      "Call to undefined function 'fibonaci' at 3:1\n  help: did you mean 'fibonacci'?",
+      "Call to undefined function 'prnt' at 4:1\n  help: did you mean the builtin 'print'?",
+      "Call to undefined function 'undefined_thing' at 5:1",
+      "Call to undefined function 'inner' at 6:15\n  note: 'inner' is defined here, but isn't in scope where it's used at 6:24",
+      "Use of undefined variable 'countr' at 9:30\n  help: did you mean 'counter'?",
+      "Use of undefined variable 'cont' at 10:10\n  help: did you mean 'count'?",
+      "Use of undefined variable 'factr' at 10:17\n  help: did you mean 'factor'?",
+      "Use of undefined variable 'count' at 12:7",
+    ]);
+  }
+}
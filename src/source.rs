@@ -0,0 +1,296 @@
+use std::{fs, io::BufRead};
+
+const UTF8_BOM: [u8; 3] = [0xEF, 0xBB, 0xBF];
+const UTF16_LE_BOM: [u8; 2] = [0xFF, 0xFE];
+const UTF16_BE_BOM: [u8; 2] = [0xFE, 0xFF];
+
+// Read a SIL source file from disk, decoding it into a string the lexer can consume
+pub fn read_source(file_path: &str) -> Result<String, String> {
+  match fs::read(file_path) {
+    Err(msg) => return Err(format!("{}: {}", file_path, msg)),
+    Ok(bytes) => return decode(&bytes).map_err(|msg| format!("{}: {}", file_path, msg)),
+  }
+}
+
+// Decode raw source bytes. UTF-8 (with or without a byte-order mark) is the expected encoding, but UTF-16
+// files, as produced by some Windows editors, are detected and transcoded rather than rejected
+pub fn decode(bytes: &[u8]) -> Result<String, String> {
+  if let Some(rest) = bytes.strip_prefix(&UTF8_BOM) {
+    return decode_utf8(rest);
+  }
+  if let Some(rest) = bytes.strip_prefix(&UTF16_LE_BOM) {
+    return decode_utf16(rest, u16::from_le_bytes);
+  }
+  if let Some(rest) = bytes.strip_prefix(&UTF16_BE_BOM) {
+    return decode_utf16(rest, u16::from_be_bytes);
+  }
+
+  // Without a BOM, UTF-16 still gives itself away: a NUL byte never appears in real SIL source, but every
+  // ASCII character encoded as UTF-16 has one
+  if bytes.len() >= 2 {
+    if bytes[0] != 0 && bytes[1] == 0 {
+      return decode_utf16(bytes, u16::from_le_bytes);
+    }
+    if bytes[0] == 0 && bytes[1] != 0 {
+      return decode_utf16(bytes, u16::from_be_bytes);
+    }
+  }
+
+  return decode_utf8(bytes);
+}
+
+fn decode_utf8(bytes: &[u8]) -> Result<String, String> {
+  match std::str::from_utf8(bytes) {
+    Ok(contents) => return Ok(contents.to_string()),
+    Err(err) => {
+      let offset = err.valid_up_to();
+      let line_num = bytes[..offset].iter().filter(|b| **b == b'\n').count() + 1;
+      return Err(format!("Invalid UTF-8 byte 0x{:02X} at offset {} (line {}), SIL source files must be UTF-8 encoded",
+        bytes[offset], offset, line_num));
+    }
+  }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> Result<String, String> {
+  if !bytes.len().is_multiple_of(2) {
+    return Err("Truncated UTF-16 input (odd number of bytes), SIL source files should be saved as UTF-8".to_string());
+  }
+
+  let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| to_unit([pair[0], pair[1]])).collect();
+  match String::from_utf16(&units) {
+    Ok(contents) => return Ok(contents),
+    Err(_) => return Err("Invalid UTF-16 input (unpaired surrogate), SIL source files should be saved as UTF-8".to_string()),
+  }
+}
+
+// Number of bytes pulled from the underlying reader at a time when streaming a source
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Iterator over the characters of a UTF-8 source read in fixed-size chunks, so only one chunk (plus the
+// bytes of a character split across the chunk boundary) is ever held in memory
+pub struct SourceChars<R: BufRead> {
+  reader: R,
+  chunk_size: usize,
+  chunk: String,
+  index: usize,
+  carry: Vec<u8>,         // Leading bytes of a character cut off at the end of the previous chunk
+  offset: usize,          // Bytes consumed from the reader so far, for error messages
+  started: bool,          // Whether the start of the input has been checked for a byte-order mark
+  error: Option<String>,
+}
+
+impl<R: BufRead> SourceChars<R> {
+  pub fn new(reader: R) -> Self {
+    SourceChars::with_chunk_size(reader, CHUNK_SIZE)
+  }
+
+  pub fn with_chunk_size(reader: R, chunk_size: usize) -> Self {
+    SourceChars { reader, chunk_size, chunk: String::new(), index: 0, carry: Vec::new(), offset: 0, started: false, error: None }
+  }
+
+  // Report any read or decoding error that cut the character stream short
+  pub fn finish(&mut self) -> Result<(), String> {
+    match self.error.take() {
+      Some(msg) => return Err(msg),
+      None => return Ok(()),
+    }
+  }
+
+  // Pull in enough of the input to see whether it starts with a byte-order mark. A UTF-8 BOM is dropped, so
+  // offsets in the character stream line up with the content after it, while UTF-16 input is rejected: it
+  // can only be transcoded by reading the whole file, which is what streaming avoids
+  fn check_encoding(&mut self) -> bool {
+    self.started = true;
+    while self.carry.len() < UTF8_BOM.len() {
+      let bytes = match self.reader.fill_buf() {
+        Ok(bytes) => bytes,
+        Err(msg) => {
+          self.error = Some(msg.to_string());
+          return false;
+        }
+      };
+      if bytes.is_empty() {
+        break;
+      }
+
+      let taken = bytes.len().min(UTF8_BOM.len() - self.carry.len());
+      self.carry.extend_from_slice(&bytes[..taken]);
+      self.reader.consume(taken);
+      self.offset += taken;
+    }
+
+    if self.carry.starts_with(&UTF8_BOM) {
+      self.carry.drain(..UTF8_BOM.len());
+    }
+    let nul_heuristic = self.carry.len() >= 2 && (self.carry[0] == 0) != (self.carry[1] == 0);
+    if self.carry.starts_with(&UTF16_LE_BOM) || self.carry.starts_with(&UTF16_BE_BOM) || nul_heuristic {
+      self.error = Some("Input looks like UTF-16, which can't be streamed, SIL source files should be saved as UTF-8".to_string());
+      return false;
+    }
+    return true;
+  }
+
+  // Decode the next chunk into `self.chunk`, returning false once the input is exhausted (or broken)
+  fn refill(&mut self) -> bool {
+    if !self.started && !self.check_encoding() {
+      return false;
+    }
+
+    loop {
+      let taken = match self.reader.fill_buf() {
+        Ok(bytes) => {
+          let taken = bytes.len().min(self.chunk_size);
+          self.carry.extend_from_slice(&bytes[..taken]);
+          taken
+        },
+        Err(msg) => {
+          self.error = Some(msg.to_string());
+          return false;
+        }
+      };
+      self.reader.consume(taken);
+      self.offset += taken;
+
+      if self.carry.is_empty() {
+        return false;
+      }
+
+      let valid_up_to = match std::str::from_utf8(&self.carry) {
+        Ok(_) => self.carry.len(),
+        // An incomplete character at the very end is carried over into the next chunk
+        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+        Err(err) => {
+          let bad_offset = self.offset - self.carry.len() + err.valid_up_to();
+          self.error = Some(format!("Invalid UTF-8 byte 0x{:02X} at offset {}, SIL source files must be UTF-8 encoded",
+            self.carry[err.valid_up_to()], bad_offset));
+          return false;
+        }
+      };
+
+      if valid_up_to == 0 {
+        // Nothing more is coming to complete the character
+        if taken == 0 {
+          self.error = Some(format!("Truncated UTF-8 character at offset {}", self.offset - self.carry.len()));
+          return false;
+        }
+        continue;
+      }
+
+      let rest = self.carry.split_off(valid_up_to);
+      self.chunk = String::from_utf8(std::mem::replace(&mut self.carry, rest)).unwrap();
+      self.index = 0;
+      return true;
+    }
+  }
+}
+
+impl<R: BufRead> Iterator for SourceChars<R> {
+  type Item = char;
+
+  fn next(&mut self) -> Option<char> {
+    if self.index >= self.chunk.len() && (self.error.is_some() || !self.refill()) {
+      return None;
+    }
+
+    let ch = self.chunk[self.index..].chars().next()?;
+    self.index += ch.len_utf8();
+    return Some(ch);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decode_plain_utf8() {
+    let result = decode("fib(40) # ünïcode comment".as_bytes());
+    assert_eq!(result.unwrap(), "fib(40) # ünïcode comment");
+  }
+
+  #[test]
+  fn decode_strips_utf8_bom() {
+    let mut bytes: Vec<u8> = UTF8_BOM.to_vec();
+    bytes.extend_from_slice(b"let x = 5");
+    assert_eq!(decode(&bytes).unwrap(), "let x = 5");
+  }
+
+  #[test]
+  fn decode_utf16_with_bom() {
+    let mut little_endian: Vec<u8> = UTF16_LE_BOM.to_vec();
+    let mut big_endian: Vec<u8> = UTF16_BE_BOM.to_vec();
+    for unit in "fib(40)".encode_utf16() {
+      little_endian.extend_from_slice(&unit.to_le_bytes());
+      big_endian.extend_from_slice(&unit.to_be_bytes());
+    }
+    assert_eq!(decode(&little_endian).unwrap(), "fib(40)");
+    assert_eq!(decode(&big_endian).unwrap(), "fib(40)");
+  }
+
+  #[test]
+  fn decode_utf16_without_bom() {
+    let bytes: Vec<u8> = "x = 1".encode_utf16().flat_map(|unit| unit.to_le_bytes()).collect();
+    assert_eq!(decode(&bytes).unwrap(), "x = 1");
+  }
+
+  #[test]
+  fn decode_reports_invalid_utf8_offset() {
+    let result = decode(b"let x = 5\nlet y = \xFF");
+    assert_eq!(result.is_ok(), false);
+    assert_eq!(result.err().unwrap(), "Invalid UTF-8 byte 0xFF at offset 18 (line 2), SIL source files must be UTF-8 encoded");
+  }
+
+  #[test]
+  fn source_chars_across_chunk_boundaries() {
+    let source = "déf ƒib(x) # 💡";
+    for chunk_size in 1..8 {
+      let mut chars = SourceChars::with_chunk_size(source.as_bytes(), chunk_size);
+      let streamed: String = chars.by_ref().collect();
+      assert_eq!(streamed, source);
+      assert_eq!(chars.finish().is_ok(), true);
+    }
+  }
+
+  #[test]
+  fn source_chars_reports_truncated_character() {
+    let mut chars = SourceChars::with_chunk_size(&b"x \xF0\x9F"[..], 2);
+    let streamed: String = chars.by_ref().collect();
+    assert_eq!(streamed, "x ");
+    assert_eq!(chars.finish().err().unwrap(), "Truncated UTF-8 character at offset 2");
+  }
+
+  #[test]
+  fn source_chars_strips_utf8_bom() {
+    let mut bytes: Vec<u8> = UTF8_BOM.to_vec();
+    bytes.extend_from_slice("é = 1".as_bytes());
+    for chunk_size in 1..5 {
+      let mut chars = SourceChars::with_chunk_size(&bytes[..], chunk_size);
+      let streamed: String = chars.by_ref().collect();
+      assert_eq!(streamed, "é = 1");
+      assert_eq!(chars.finish().is_ok(), true);
+    }
+
+    // Inputs shorter than a BOM still come through whole
+    let mut chars = SourceChars::new(&b"x"[..]);
+    assert_eq!(chars.by_ref().collect::<String>(), "x");
+    assert_eq!(chars.finish().is_ok(), true);
+  }
+
+  #[test]
+  fn source_chars_rejects_utf16() {
+    let mut with_bom: Vec<u8> = UTF16_LE_BOM.to_vec();
+    with_bom.extend_from_slice(&[b'x', 0]);
+    let without_bom: Vec<u8> = "x = 1".encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect();
+    for bytes in [with_bom, without_bom] {
+      let mut chars = SourceChars::new(&bytes[..]);
+      assert_eq!(chars.by_ref().count(), 0);
+      assert_eq!(chars.finish().err().unwrap(), "Input looks like UTF-16, which can't be streamed, SIL source files should be saved as UTF-8");
+    }
+  }
+
+  #[test]
+  fn decode_rejects_truncated_utf16() {
+    let result = decode(&[0xFF, 0xFE, b'x']);
+    assert_eq!(result.is_ok(), false);
+  }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use crate::lexer::Span;
+use crate::parser::{Expr, ExprAST, NodeMap, Program, Stmt, StmtAST};
+use crate::typeck::Ty;
+use crate::visit::{walk_expr, walk_stmt, Visitor};
+
+// A function in the symbol table: every `def`, and every let or const whose value is a lambda, as in the
+// call graph
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+  pub name: String,
+  pub mangled: String,   // The name it's given once it's compiled, unique across the program
+  pub arity: usize,
+  pub ty: Ty,            // Its type, from the typecheck pass
+  pub span: Span,
+}
+
+// Every function in `program`, in the order they're declared, with the types the type checker worked out.
+//
+// Names are mangled so that functions with the same name in different places get different symbols: `_S`,
+// then the name of each function it's declared in, outermost first, and its own, each as its length in
+// bytes followed by the name, then `A` and the number of parameters it takes. `def inner(x)` inside
+// `def outer()` is `_S5outer5innerA1`. The lengths keep the names apart, and a name can't start with a digit,
+// so a mangled name can always be split back into its parts. Functions that would still get the same
+// symbol, like two in sibling blocks, are told apart by `_1`, `_2` and so on after all but the first
+pub fn symbol_table(program: &Program, types: &NodeMap<Ty>) -> Vec<Symbol> {
+  let mut symbols = Symbols { types, enclosing: Vec::new(), symbols: Vec::new() };
+  for function in &program.functions {
+    symbols.visit_expr(function);
+  }
+  for stmt in &program.top_level {
+    symbols.visit_stmt(stmt);
+  }
+
+  let mut table = symbols.symbols;
+  table.sort_by_key(|symbol| symbol.span.start);
+  let mut seen: HashMap<String, usize> = HashMap::new();
+  for symbol in &mut table {
+    let count = seen.entry(symbol.mangled.clone()).or_insert(0);
+    if *count > 0 {
+      symbol.mangled.push_str(&format!("_{}", count));
+    }
+    *count += 1;
+  }
+  return table;
+}
+
+// The mangled name of a function called `name` taking `arity` parameters, declared inside the functions
+// `enclosing`, outermost first
+pub fn mangle(enclosing: &[String], name: &str, arity: usize) -> String {
+  let mut mangled = "_S".to_string();
+  for part in enclosing.iter().map(String::as_str).chain([name]) {
+    mangled.push_str(&format!("{}{}", part.len(), part));
+  }
+  mangled.push_str(&format!("A{}", arity));
+  return mangled;
+}
+
+// The symbol table as a JSON array, one symbol to a line, for tools that link against or inspect what the
+// compiler produces
+pub fn symbols_to_json(symbols: &[Symbol]) -> String {
+  if symbols.is_empty() {
+    return "[]\n".to_string();
+  }
+  let entries: Vec<String> = symbols.iter().map(|symbol| format!(
+    "  {{\"name\": {}, \"mangled\": {}, \"arity\": {}, \"type\": {}, \"span\": {{\"start\": {}, \"end\": {}, \"line\": {}, \"column\": {}}}}}",
+    json_string(&symbol.name), json_string(&symbol.mangled), symbol.arity, json_string(&symbol.ty.to_string()),
+    symbol.span.start, symbol.span.end, symbol.span.line, symbol.span.column,
+  )).collect();
+  return format!("[\n{}\n]\n", entries.join(",\n"));
+}
+
+fn json_string(text: &str) -> String {
+  let mut escaped = "\"".to_string();
+  for ch in text.chars() {
+    match ch {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      '\n' => escaped.push_str("\\n"),
+      '\t' => escaped.push_str("\\t"),
+      ch if ch.is_control() => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+      ch => escaped.push(ch),
+    }
+  }
+  escaped.push('"');
+  return escaped;
+}
+
+struct Symbols<'a> {
+  types: &'a NodeMap<Ty>,
+  enclosing: Vec<String>,   // The names of the functions being walked, innermost last
+  symbols: Vec<Symbol>,
+}
+
+impl Symbols<'_> {
+  fn declare(&mut self, name: &str, arity: usize, ty: Option<&Ty>, span: Span, walk: impl FnOnce(&mut Self)) {
+    let mangled = mangle(&self.enclosing, name, arity);
+    let ty = ty.cloned().unwrap_or(Ty::Unknown);
+    self.symbols.push(Symbol { name: name.to_string(), mangled, arity, ty, span });
+    self.enclosing.push(name.to_string());
+    walk(self);
+    self.enclosing.pop();
+  }
+}
+
+impl Visitor for Symbols<'_> {
+  fn visit_expr(&mut self, expr: &Expr) {
+    match &expr.node {
+      ExprAST::Function(name, params, ..) => {
+        self.declare(name, params.len(), self.types.get(&expr.id), expr.span, |symbols| walk_expr(symbols, expr));
+      },
+      _ => walk_expr(self, expr),
+    }
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Let(name, _, value) | StmtAST::Const(name, _, value) if let ExprAST::Lambda(params, ..) = &value.node => {
+        self.declare(name, params.len(), self.types.get(&stmt.id), stmt.span, |symbols| walk_stmt(symbols, stmt));
+      },
+      _ => walk_stmt(self, stmt),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::resolver::resolve;
+  use crate::typeck::check_types;
+
+  fn symbols(source: &str) -> Vec<Symbol> {
+    let tokens = lex(source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    let types = check_types(&program, &declarations).unwrap();
+    return symbol_table(&program, &types);
+  }
+
+  #[test]
+  fn mangle_function_names() {
+    let source: String = r#"
+def outer(n) {
+  def inner(x) { return x + n }
+  return inner(1)
+}
+const twice = def(x) { return x * 2.0 }
+if true { def helper() { print("a") }; helper() }
+if false { def helper() { print("b") }; helper() }
+print(outer(1))
+print(twice(1.5))
+"#.to_string();
+    let table: Vec<(String, String, usize, String)> = symbols(&source).into_iter()
+      .map(|symbol| (symbol.name, symbol.mangled, symbol.arity, symbol.ty.to_string()))
+      .collect();
+    assert_eq!(table, vec![
+      ("outer".to_string(), "_S5outerA1".to_string(), 1, "def(int) -> int".to_string()),
+      ("inner".to_string(), "_S5outer5innerA1".to_string(), 1, "def(int) -> int".to_string()),
+      ("twice".to_string(), "_S5twiceA1".to_string(), 1, "def(float) -> float".to_string()),
+      ("helper".to_string(), "_S6helperA0".to_string(), 0, "def()".to_string()),
+      ("helper".to_string(), "_S6helperA0_1".to_string(), 0, "def()".to_string()),
+    ]);
+  }
+
+  #[test]
+  fn write_symbols_as_json() {
+    let table = symbols("def greet(name) { print(name) }\ngreet(\"you\")");
+    assert_eq!(symbols_to_json(&table), r#"[
+  {"name": "greet", "mangled": "_S5greetA1", "arity": 1, "type": "def(string)", "span": {"start": 0, "end": 31, "line": 1, "column": 1}}
+]
+"#);
+    assert_eq!(symbols_to_json(&[]), "[]\n");
+    assert_eq!(json_string("say \"hi\"\\\n\u{7}"), r#""say \"hi\"\\\n\u0007""#);
+  }
+}
@@ -0,0 +1,776 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::diagnostic::Diagnostic;
+use crate::parser::{BinaryOp, Expr, ExprAST, NodeId, NodeMap, Program, Spanned, Param, Stmt, StmtAST, Type, TypeExpr, UnaryOp};
+
+// The type of a value, as worked out by the type checker
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ty {
+  Int,
+  Float,
+  Bool,
+  String,
+  Array(Box<Ty>),              // [element type]
+  Tuple(Vec<Ty>),              // (element types), and (), what a function that returns nothing returns
+  Struct(String),              // A struct, by name
+  Function(Vec<Ty>, Box<Ty>),  // def(parameter types) -> return type
+  Unknown,                     // Nothing in the program decides it, like the parameter of a function that's never called
+  Var(u32),                    // A type still being inferred. Only seen while checking, never in the table check_types returns
+}
+
+impl Ty {
+  pub fn unit() -> Ty {
+    return Ty::Tuple(Vec::new());
+  }
+}
+
+// Types print as they're written in annotations, a function returning nothing without its arrow
+impl fmt::Display for Ty {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let list = |types: &[Ty]| types.iter().map(|ty| ty.to_string()).collect::<Vec<String>>().join(", ");
+    match self {
+      Ty::Int => return write!(f, "int"),
+      Ty::Float => return write!(f, "float"),
+      Ty::Bool => return write!(f, "bool"),
+      Ty::String => return write!(f, "string"),
+      Ty::Array(element) => return write!(f, "[{}]", element),
+      Ty::Tuple(elements) if elements.len() == 1 => return write!(f, "({},)", elements[0]),
+      Ty::Tuple(elements) => return write!(f, "({})", list(elements)),
+      Ty::Struct(name) => return write!(f, "{}", name),
+      Ty::Function(params, returns) if **returns == Ty::unit() => return write!(f, "def({})", list(params)),
+      Ty::Function(params, returns) => return write!(f, "def({}) -> {}", list(params), returns),
+      Ty::Unknown | Ty::Var(_) => return write!(f, "?"),
+    }
+  }
+}
+
+// Infer the type of every expression in `program`, using the declarations the resolver found, and check
+// that operators, conditions, calls, assignments and returns are given the types they need. The table
+// returned has the type of every expression and every declaration (Let and Const statements, parameters and
+// destructured names), by node id.
+//
+// Inference is by unification, so the type of an unannotated parameter comes from how it's used and how the
+// function is called. A function has one type for the whole program, so calling it with an int in one
+// place and a bool in another is an error. Numbers aren't converted: an int and a float can't be added
+// until one of them is passed through int() or float(). Annotations are checked against the types
+// inferred, and a declaration whose value doesn't match its annotation is an error
+pub fn check_types(program: &Program, declarations: &NodeMap<NodeId>) -> Result<NodeMap<Ty>, Vec<Diagnostic>> {
+  let mut checker = Checker {
+    declarations,
+    bindings: Vec::new(),
+    structs: HashMap::new(),
+    types: NodeMap::new(),
+    returns: Vec::new(),
+    errors: Vec::new(),
+  };
+  for stmt in &program.structs {
+    if let StmtAST::Struct(name, fields) = &stmt.node {
+      let fields = fields.iter().map(|field| (field.clone(), checker.fresh())).collect();
+      checker.structs.insert(name.clone(), fields);
+    }
+  }
+  for function in &program.functions {
+    checker.check_expr(function);
+  }
+  for stmt in &program.top_level {
+    checker.check_stmt(stmt);
+  }
+
+  checker.errors.sort_by_key(|error| error.span.start);
+  if !checker.errors.is_empty() {
+    return Err(checker.errors);
+  }
+  let types = checker.types.iter().map(|(id, ty)| (*id, checker.substitute(ty))).collect();
+  return Ok(types);
+}
+
+// The function whose body is being checked
+struct Function {
+  returns: Ty,
+  returns_value: bool,   // Whether any return in it so far has a value
+}
+
+struct Checker<'a> {
+  declarations: &'a NodeMap<NodeId>,
+  bindings: Vec<Option<Ty>>,                    // What each inference variable has been found to be, by number
+  structs: HashMap<String, Vec<(String, Ty)>>,  // The type of every field of every struct
+  types: NodeMap<Ty>,
+  returns: Vec<Function>,                       // The functions being checked, innermost last
+  errors: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+  fn fresh(&mut self) -> Ty {
+    self.bindings.push(None);
+    return Ty::Var(self.bindings.len() as u32 - 1);
+  }
+
+  fn error(&mut self, message: String, expr: &Expr) {
+    self.errors.push(Diagnostic::new(message, expr.span));
+  }
+
+  // Follow `ty` through the variables it's bound to, until it's a type or a variable nothing's bound to yet
+  fn shallow(&self, ty: &Ty) -> Ty {
+    let mut ty = ty.clone();
+    while let Ty::Var(var) = ty {
+      match &self.bindings[var as usize] {
+        Some(bound) => ty = bound.clone(),
+        None => break,
+      }
+    }
+    return ty;
+  }
+
+  // `ty` with everything bound so far filled in, and any variable still unbound as Unknown
+  fn substitute(&self, ty: &Ty) -> Ty {
+    match self.shallow(ty) {
+      Ty::Array(element) => return Ty::Array(Box::new(self.substitute(&element))),
+      Ty::Tuple(elements) => return Ty::Tuple(elements.iter().map(|element| self.substitute(element)).collect()),
+      Ty::Function(params, returns) => {
+        let params = params.iter().map(|param| self.substitute(param)).collect();
+        return Ty::Function(params, Box::new(self.substitute(&returns)));
+      },
+      Ty::Var(_) => return Ty::Unknown,
+      ty => return ty,
+    }
+  }
+
+  // `ty` with everything bound so far filled in, for error messages
+  fn show(&self, ty: &Ty) -> String {
+    return self.substitute(ty).to_string();
+  }
+
+  fn occurs(&self, var: u32, ty: &Ty) -> bool {
+    match self.shallow(ty) {
+      Ty::Var(other) => return other == var,
+      Ty::Array(element) => return self.occurs(var, &element),
+      Ty::Tuple(elements) => return elements.iter().any(|element| self.occurs(var, element)),
+      Ty::Function(params, returns) => return params.iter().any(|param| self.occurs(var, param)) || self.occurs(var, &returns),
+      _ => return false,
+    }
+  }
+
+  // Make `a` and `b` the same type, binding variables in either as needed. False if they can't be
+  fn unify(&mut self, a: &Ty, b: &Ty) -> bool {
+    match (self.shallow(a), self.shallow(b)) {
+      (Ty::Var(a), Ty::Var(b)) if a == b => return true,
+      (Ty::Var(var), ty) | (ty, Ty::Var(var)) => {
+        if self.occurs(var, &ty) {
+          return false;
+        }
+        self.bindings[var as usize] = Some(ty);
+        return true;
+      },
+      (Ty::Array(a), Ty::Array(b)) => return self.unify(&a, &b),
+      (Ty::Tuple(a), Ty::Tuple(b)) => return a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| self.unify(a, b)),
+      (Ty::Function(a_params, a_returns), Ty::Function(b_params, b_returns)) => {
+        return a_params.len() == b_params.len()
+          && a_params.iter().zip(&b_params).all(|(a, b)| self.unify(a, b))
+          && self.unify(&a_returns, &b_returns);
+      },
+      (a, b) => return a == b,
+    }
+  }
+
+  // The type of what `id` declares, a fresh variable the first time it's asked for
+  fn declared(&mut self, id: NodeId) -> Ty {
+    if let Some(ty) = self.types.get(&id) {
+      return ty.clone();
+    }
+    let ty = self.fresh();
+    self.types.insert(id, ty.clone());
+    return ty;
+  }
+
+  fn check_condition(&mut self, condition: &Expr) {
+    let ty = self.check_expr(condition);
+    if !self.unify(&ty, &Ty::Bool) {
+      let message = format!("Condition must be bool, found {}", self.show(&ty));
+      self.error(message, condition);
+    }
+  }
+
+  // The type `annotation` names. Names other than the built in types have to be structs
+  fn annotated(&mut self, annotation: &Type) -> Ty {
+    match &annotation.node {
+      TypeExpr::Named(name) => match name.as_str() {
+        "int" => return Ty::Int,
+        "float" => return Ty::Float,
+        "bool" => return Ty::Bool,
+        "string" => return Ty::String,
+        _ if self.structs.contains_key(name) => return Ty::Struct(name.clone()),
+        _ => {
+          self.errors.push(Diagnostic::new(format!("Unknown type '{}'", name), annotation.span));
+          return self.fresh();
+        },
+      },
+      TypeExpr::Array(element) => return Ty::Array(Box::new(self.annotated(element))),
+      TypeExpr::Function(params, returns) => {
+        let params = params.iter().map(|param| self.annotated(param)).collect();
+        let returns = match returns {
+          Some(returns) => self.annotated(returns),
+          None => Ty::unit(),
+        };
+        return Ty::Function(params, Box::new(returns));
+      },
+      TypeExpr::Tuple(elements) => return Ty::Tuple(elements.iter().map(|element| self.annotated(element)).collect()),
+    }
+  }
+
+  // Give the declaration `id` of `name` the type `ty`, which its value has, checking it against the annotation
+  // if there is one. An annotation that doesn't match is reported at `at`, and wins, so later uses are
+  // checked against what was meant
+  fn declare(&mut self, name: &str, id: NodeId, annotation: &Option<Type>, ty: &Ty, at: &Expr) {
+    let declared = self.declared(id);
+    let Some(annotation) = annotation else {
+      self.unify(&declared, ty);
+      return;
+    };
+    let annotated = self.annotated(annotation);
+    if !self.unify(&annotated, ty) {
+      let message = format!("'{}' is annotated as {}, but its value is {}", name, self.show(&annotated), self.show(ty));
+      self.error(message, at);
+    }
+    self.unify(&declared, &annotated);
+  }
+
+  fn check_function(&mut self, params: &[Spanned<Param>], annotation: &Option<Type>, body: &[Stmt], at: &Expr) -> Ty {
+    let mut param_types: Vec<Ty> = Vec::new();
+    for param in params {
+      let ty = self.declared(param.id);
+      if let Some(annotation) = &param.node.annotation {
+        let annotated = self.annotated(annotation);
+        if !self.unify(&ty, &annotated) {
+          let message = format!("'{}' is annotated as {}, but is used as {}", param.node.name, self.show(&annotated), self.show(&ty));
+          self.errors.push(Diagnostic::new(message, param.span));
+        }
+      }
+      param_types.push(ty);
+    }
+    let returns = match annotation {
+      Some(annotation) => self.annotated(annotation),
+      None => self.fresh(),
+    };
+    self.returns.push(Function { returns: returns.clone(), returns_value: false });
+    for stmt in body {
+      self.check_stmt(stmt);
+    }
+    if !self.returns.pop().unwrap().returns_value && !self.unify(&returns, &Ty::unit()) {
+      let message = format!("Function is annotated to return {}, but never returns a value", self.show(&returns));
+      self.error(message, at);
+    }
+    return Ty::Function(param_types, Box::new(returns));
+  }
+
+  fn check_expr(&mut self, expr: &Expr) -> Ty {
+    let ty = self.infer_expr(expr);
+    self.types.insert(expr.id, ty.clone());
+    return ty;
+  }
+
+  fn infer_expr(&mut self, expr: &Expr) -> Ty {
+    match &expr.node {
+      ExprAST::Integer(_) => return Ty::Int,
+      ExprAST::Float(_) => return Ty::Float,
+      ExprAST::Boolean(_) => return Ty::Bool,
+      ExprAST::StringLiteral(_) => return Ty::String,
+      ExprAST::Variable(_) => match self.declarations.get(&expr.id) {
+        Some(declaration) => return self.declared(*declaration),
+        None => return self.fresh(),
+      },
+      ExprAST::Unary(op, operand) => {
+        let ty = self.check_expr(operand);
+        let allowed = match (op, self.shallow(&ty)) {
+          (UnaryOp::Negate, Ty::Int | Ty::Float | Ty::Var(_)) => true,
+          (UnaryOp::Not, _) => self.unify(&ty, &Ty::Bool),
+          (UnaryOp::BitNot, _) => self.unify(&ty, &Ty::Int),
+          _ => false,
+        };
+        if !allowed {
+          let message = format!("Cannot apply '{}' to {}", op, self.show(&ty));
+          self.error(message, expr);
+          return self.fresh();
+        }
+        return ty;
+      },
+      ExprAST::Binary(op, lhs, rhs) | ExprAST::Logical(op, lhs, rhs) => {
+        let lhs = self.check_expr(lhs);
+        let rhs = self.check_expr(rhs);
+        match self.binary(*op, &lhs, &rhs) {
+          Some(ty) => return ty,
+          None => {
+            let message = format!("Cannot apply '{}' to {} and {}", op, self.show(&lhs), self.show(&rhs));
+            self.error(message, expr);
+            return self.fresh();
+          },
+        }
+      },
+      ExprAST::Conditional(condition, then_value, else_value) => {
+        self.check_condition(condition);
+        let then_ty = self.check_expr(then_value);
+        let else_ty = self.check_expr(else_value);
+        if !self.unify(&then_ty, &else_ty) {
+          let message = format!("The two values of a conditional must have the same type, found {} and {}", self.show(&then_ty), self.show(&else_ty));
+          self.error(message, expr);
+        }
+        return then_ty;
+      },
+      ExprAST::Call(name, args) => {
+        let args: Vec<Ty> = args.iter().map(|arg| self.check_expr(arg)).collect();
+        return self.call(name, &args, expr);
+      },
+      ExprAST::ArrayLiteral(elements) => {
+        let element_ty = self.fresh();
+        for element in elements {
+          let ty = self.check_expr(element);
+          if !self.unify(&element_ty, &ty) {
+            let message = format!("Array element is {}, but the elements before it are {}", self.show(&ty), self.show(&element_ty));
+            self.error(message, element);
+          }
+        }
+        return Ty::Array(Box::new(element_ty));
+      },
+      ExprAST::Index(target, index) => {
+        let target_ty = self.check_expr(target);
+        let index_ty = self.check_expr(index);
+        return self.index(&target_ty, index, &index_ty, expr);
+      },
+      ExprAST::Field(target, field) => {
+        let target_ty = self.check_expr(target);
+        return self.field(&target_ty, field, expr);
+      },
+      // Nothing has methods yet, so every method call is an error, but its receiver and arguments are still
+      // checked
+      ExprAST::MethodCall(receiver, method, args) => {
+        let receiver_ty = self.check_expr(receiver);
+        for arg in args {
+          self.check_expr(arg);
+        }
+        let message = format!("{} has no method '{}'", self.show(&receiver_ty), method);
+        self.error(message, expr);
+        return self.fresh();
+      },
+      ExprAST::Interpolation(parts) => {
+        for part in parts {
+          self.check_expr(part);
+        }
+        return Ty::String;
+      },
+      ExprAST::Function(_, params, annotation, body, _) => {
+        let ty = self.check_function(params, annotation, body, expr);
+        let declared = self.declared(expr.id);
+        self.unify(&declared, &ty);
+        return ty;
+      },
+      ExprAST::Lambda(params, annotation, body, _) => return self.check_function(params, annotation, body, expr),
+      ExprAST::StructLiteral(name, fields) => {
+        for (field, value) in fields {
+          let ty = self.check_expr(value);
+          let Some(declared) = self.structs.get(name) else { continue };
+          match declared.iter().find(|(declared, _)| declared == field).map(|(_, ty)| ty.clone()) {
+            Some(field_ty) => {
+              if !self.unify(&field_ty, &ty) {
+                let message = format!("Field '{}' of '{}' is {}, found {}", field, name, self.show(&field_ty), self.show(&ty));
+                self.error(message, value);
+              }
+            },
+            None => self.error(format!("Struct '{}' has no field '{}'", name, field), value),
+          }
+        }
+        if !self.structs.contains_key(name) {
+          self.error(format!("Use of undefined struct '{}'", name), expr);
+        }
+        return Ty::Struct(name.clone());
+      },
+      ExprAST::Tuple(elements) => return Ty::Tuple(elements.iter().map(|element| self.check_expr(element)).collect()),
+    }
+  }
+
+  // The type of `field` of a value of type `target`. A value whose type isn't known yet, like a parameter, is
+  // taken to be the only struct with a field of that name, if there's just one
+  fn field(&mut self, target: &Ty, field: &str, expr: &Expr) -> Ty {
+    if let Ty::Var(_) = self.shallow(target) {
+      let candidates: Vec<String> = self.structs.iter()
+        .filter(|(_, fields)| fields.iter().any(|(name, _)| name == field))
+        .map(|(name, _)| name.clone())
+        .collect();
+      match candidates.as_slice() {
+        [name] => {
+          self.unify(target, &Ty::Struct(name.clone()));
+        },
+        _ => {
+          let message = format!("Cannot tell which struct has the field '{}', as the type of the value isn't known here", field);
+          self.error(message, expr);
+          return self.fresh();
+        },
+      }
+    }
+    match self.shallow(target) {
+      Ty::Struct(name) => {
+        let declared = self.structs.get(&name).and_then(|fields| fields.iter().find(|(declared, _)| declared == field));
+        match declared {
+          Some((_, ty)) => return ty.clone(),
+          None => {
+            self.error(format!("Struct '{}' has no field '{}'", name, field), expr);
+            return self.fresh();
+          },
+        }
+      },
+      ty => {
+        let message = format!("Cannot access field '{}' of {}", field, self.show(&ty));
+        self.error(message, expr);
+        return self.fresh();
+      },
+    }
+  }
+
+  // The type of `lhs op rhs`, or None if op can't be applied to them
+  fn binary(&mut self, op: BinaryOp, lhs: &Ty, rhs: &Ty) -> Option<Ty> {
+    let numeric = |ty: &Ty| matches!(ty, Ty::Int | Ty::Float | Ty::Var(_)) || (op == BinaryOp::Add && *ty == Ty::String);
+    match op {
+      BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo | BinaryOp::Power => {
+        if !numeric(&self.shallow(lhs)) || !numeric(&self.shallow(rhs)) || !self.unify(lhs, rhs) {
+          return None;
+        }
+        return Some(lhs.clone());
+      },
+      BinaryOp::LessThan | BinaryOp::GreaterThan | BinaryOp::LessThanEqual | BinaryOp::GreaterThanEqual => {
+        if !numeric(&self.shallow(lhs)) || !numeric(&self.shallow(rhs)) || !self.unify(lhs, rhs) {
+          return None;
+        }
+        return Some(Ty::Bool);
+      },
+      BinaryOp::Equal | BinaryOp::NotEqual => {
+        if !self.unify(lhs, rhs) {
+          return None;
+        }
+        return Some(Ty::Bool);
+      },
+      BinaryOp::And | BinaryOp::Or => {
+        if !self.unify(lhs, &Ty::Bool) || !self.unify(rhs, &Ty::Bool) {
+          return None;
+        }
+        return Some(Ty::Bool);
+      },
+      BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor | BinaryOp::ShiftLeft | BinaryOp::ShiftRight => {
+        if !self.unify(lhs, &Ty::Int) || !self.unify(rhs, &Ty::Int) {
+          return None;
+        }
+        return Some(Ty::Int);
+      },
+    }
+  }
+
+  // The type a call to `name` returns. The resolver and call checks have already reported calls to undefined
+  // names and with the wrong number of arguments, so those are let through here
+  fn call(&mut self, name: &str, args: &[Ty], call: &Expr) -> Ty {
+    if let Some(declaration) = self.declarations.get(&call.id) {
+      let callee = self.declared(*declaration);
+      match self.shallow(&callee) {
+        Ty::Function(params, returns) => {
+          if params.len() == args.len() {
+            for (i, (param, arg)) in params.iter().zip(args).enumerate() {
+              if !self.unify(param, arg) {
+                let message = format!("Argument {} to '{}' should be {}, found {}", i + 1, name, self.show(param), self.show(arg));
+                self.error(message, call);
+              }
+            }
+          }
+          return *returns;
+        },
+        Ty::Var(_) => {
+          let returns = self.fresh();
+          self.unify(&callee, &Ty::Function(args.to_vec(), Box::new(returns.clone())));
+          return returns;
+        },
+        ty => {
+          self.error(format!("Cannot call '{}', which is {}, not a function", name, ty), call);
+          return self.fresh();
+        },
+      }
+    }
+
+    match (name, args) {
+      ("print", _) => return Ty::unit(),
+      ("len", [value]) => {
+        if !matches!(self.shallow(value), Ty::Array(_) | Ty::String | Ty::Var(_)) {
+          self.error(format!("Cannot take the len of {}", self.show(value)), call);
+        }
+        return Ty::Int;
+      },
+      ("int" | "float", [value]) => {
+        if !matches!(self.shallow(value), Ty::Int | Ty::Float | Ty::Var(_)) {
+          self.error(format!("Cannot convert {} to {}", self.show(value), name), call);
+        }
+        match name {
+          "int" => return Ty::Int,
+          _ => return Ty::Float,
+        }
+      },
+      ("push", [array, value]) => {
+        if !self.unify(array, &Ty::Array(Box::new(value.clone()))) {
+          let message = format!("Cannot push {} onto {}", self.show(value), self.show(array));
+          self.error(message, call);
+        }
+        return array.clone();
+      },
+      _ => return self.fresh(),
+    }
+  }
+
+  // The type of `target[index]`. Arrays and strings can be indexed by any int, tuples only by an integer
+  // literal, so which element it is is known
+  fn index(&mut self, target_ty: &Ty, index: &Expr, index_ty: &Ty, expr: &Expr) -> Ty {
+    if let Ty::Tuple(elements) = self.shallow(target_ty) {
+      match index.node {
+        ExprAST::Integer(i) if (i as usize) < elements.len() => return elements[i as usize].clone(),
+        ExprAST::Integer(i) => self.error(format!("Index {} is past the end of {}", i, self.show(target_ty)), index),
+        _ => self.error("Tuples can only be indexed by an integer literal".to_string(), index),
+      }
+      return self.fresh();
+    }
+
+    if !self.unify(index_ty, &Ty::Int) {
+      let message = format!("Index must be int, found {}", self.show(index_ty));
+      self.error(message, index);
+    }
+    if self.shallow(target_ty) == Ty::String {
+      return Ty::String;
+    }
+    let element = self.fresh();
+    if !self.unify(target_ty, &Ty::Array(Box::new(element.clone()))) {
+      self.error(format!("Cannot index into {}", self.show(target_ty)), expr);
+      return self.fresh();
+    }
+    return element;
+  }
+
+  fn check_stmt(&mut self, stmt: &Stmt) {
+    match &stmt.node {
+      StmtAST::Let(name, annotation, value) | StmtAST::Const(name, annotation, value) => {
+        let ty = self.check_expr(value);
+        self.declare(name, stmt.id, annotation, &ty, value);
+      },
+      StmtAST::LetTuple(names, value) => {
+        let ty = self.check_expr(value);
+        let elements: Vec<Ty> = names.iter().map(|_| self.fresh()).collect();
+        if !self.unify(&ty, &Ty::Tuple(elements.clone())) {
+          let message = format!("Cannot destructure {} into {} names", self.show(&ty), names.len());
+          self.error(message, value);
+        }
+        for (name, element) in names.iter().zip(&elements) {
+          self.declare(&name.node.name, name.id, &name.node.annotation, element, value);
+        }
+      },
+      StmtAST::Assign(target, value) => {
+        let target_ty = self.check_expr(target);
+        let ty = self.check_expr(value);
+        if !self.unify(&target_ty, &ty) {
+          let message = format!("Cannot assign {} to {}", self.show(&ty), self.show(&target_ty));
+          self.error(message, value);
+        }
+      },
+      StmtAST::Return(value) => {
+        let ty = match value {
+          Some(value) => self.check_expr(value),
+          None => Ty::unit(),
+        };
+        let Some(function) = self.returns.last_mut() else { return };
+        function.returns_value |= value.is_some();
+        let returns = function.returns.clone();
+        if !self.unify(&returns, &ty) {
+          let message = format!("Function returns {}, but this returns {}", self.show(&returns), self.show(&ty));
+          self.errors.push(Diagnostic::new(message, stmt.span));
+        }
+      },
+      StmtAST::If(condition, then_block, else_block) => {
+        self.check_condition(condition);
+        self.check_stmt(then_block);
+        if let Some(else_block) = else_block {
+          self.check_stmt(else_block);
+        }
+      },
+      StmtAST::While(condition, body) => {
+        self.check_condition(condition);
+        self.check_stmt(body);
+      },
+      StmtAST::ExprStmt(expr) => {
+        self.check_expr(expr);
+      },
+      StmtAST::Block(statements) => {
+        for stmt in statements {
+          self.check_stmt(stmt);
+        }
+      },
+      StmtAST::Break | StmtAST::Continue | StmtAST::Struct(..) | StmtAST::Import(_) => {},
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::Parser;
+  use crate::resolver::resolve;
+  use crate::visit::{walk_expr, Visitor};
+
+  fn check(source: &str) -> Result<(Program, NodeMap<Ty>), Vec<String>> {
+    let tokens = lex(source).unwrap();
+    let program = Parser::new(&tokens).parse().unwrap();
+    let declarations = resolve(&program).unwrap();
+    match check_types(&program, &declarations) {
+      Ok(types) => return Ok((program, types)),
+      Err(errors) => return Err(errors.iter().map(|error| error.to_string()).collect()),
+    }
+  }
+
+  // The type of every variable read in `program`, in the order they're read
+  fn variable_types(program: &Program, types: &NodeMap<Ty>) -> Vec<String> {
+    struct Variables<'a> {
+      types: &'a NodeMap<Ty>,
+      found: Vec<String>,
+    }
+    impl Visitor for Variables<'_> {
+      fn visit_expr(&mut self, expr: &Expr) {
+        if let ExprAST::Variable(name) = &expr.node {
+          self.found.push(format!("{}: {}", name, self.types[&expr.id]));
+        }
+        walk_expr(self, expr);
+      }
+    }
+
+    let mut variables = Variables { types, found: Vec::new() };
+    for function in &program.functions {
+      variables.visit_expr(function);
+    }
+    for stmt in &program.top_level {
+      variables.visit_stmt(stmt);
+    }
+    return variables.found;
+  }
+
+  #[test]
+  fn infer_types() {
+    let source: String = r#"
+def fib(n) {
+  if n < 2 { return n }
+  return fib(n - 1) + fib(n - 2)
+}
+def scale(xs, by) {
+  let (first, rest) = (xs[0], xs)
+  return push(rest, first * by), by > 1.5
+}
+let twice = def(f, x) { return f(f(x)) }
+let labels = ["a", "${fib(10)}"]
+let flag = !(fib(3) == 2) && len(labels) > 0
+let result = twice(def(s) { return s + "!" }, labels[0])
+def unused(anything) { }
+"#.to_string();
+    let (program, types) = check(&source).unwrap();
+    assert_eq!(variable_types(&program, &types), vec![
+      "n: int", "n: int", "n: int", "n: int",
+      "xs: [float]", "xs: [float]", "rest: [float]", "first: float", "by: float", "by: float",
+      "x: string", "labels: [string]", "s: string", "labels: [string]",
+    ]);
+    let declared: Vec<String> = program.top_level.iter().map(|stmt| types[&stmt.id].to_string()).collect();
+    assert_eq!(declared, vec!["def(def(string) -> string, string) -> string", "[string]", "bool", "string"]);
+    let function_types: Vec<String> = program.functions.iter().map(|function| types[&function.id].to_string()).collect();
+    assert_eq!(function_types, vec!["def(int) -> int", "def([float], float) -> ([float], bool)", "def(?)"]);
+  }
+
+  #[test]
+  fn report_type_errors() {
+    let source: String = r#"
+let x = 5 + true
+if 1 { }
+while "yes" { }
+let ratio = 1 / 2.0
+def half(n) { return n / 2 }
+half(true)
+def maybe(b) {
+  if b { return 1 }
+  return
+}
+let xs = [1, 2.5]
+let pair = (1, false)
+pair[2] = 1
+print(-"a", ~1.5, 1 << 2)
+"#.to_string();
+    assert_eq!(check(&source).err().unwrap(), vec![
+      "Cannot apply '+' to int and bool at 2:9",
+      "Condition must be bool, found int at 3:4",
+      "Condition must be bool, found string at 4:7",
+      "Cannot apply '/' to int and float at 5:13",
+      "Argument 1 to 'half' should be int, found bool at 7:1",
+      "Function returns int, but this returns () at 10:3",
+      "Array element is float, but the elements before it are int at 12:14",
+      "Index 2 is past the end of (int, bool) at 14:6",
+      "Cannot apply '-' to string at 15:7",
+      "Cannot apply '~' to float at 15:13",
+    ]);
+  }
+
+  #[test]
+  fn check_fields() {
+    let mut source: String = r#"
+struct Point { x, y }
+struct Size { width, height }
+def norm(p) { return p.x * p.x + p.y * p.y }
+let p = Point { x: 3, y: 4 }
+let n = norm(p)
+p.x = 1.5
+let w = p.z
+let count = n.x
+print([1].len())
+def area(s) { return s.width * s.height }
+"#.to_string();
+    assert_eq!(check(&source).err().unwrap(), vec![
+      "Cannot assign float to int at 7:7",
+      "Struct 'Point' has no field 'z' at 8:9",
+      "Cannot access field 'x' of int at 9:13",
+      "[int] has no method 'len' at 10:7",
+    ]);
+
+    // A field more than one struct has can't say which struct a value of unknown type is
+    source = "struct A { v }\nstruct B { v }\ndef get(a) { return a.v }".to_string();
+    assert_eq!(check(&source).err().unwrap(), vec!["Cannot tell which struct has the field 'v', as the type of the value isn't known here at 3:21"]);
+    source = "struct A { v }\nstruct B { v }\ndef get(a: A) { return a.v }\nlet x: bool = get(A { v: true })".to_string();
+    let (program, types) = check(&source).unwrap();
+    assert_eq!(variable_types(&program, &types), vec!["a: A"]);
+  }
+
+  #[test]
+  fn check_annotations() {
+    let mut source: String = r#"
+struct Point { x, y }
+let x: int = 3.5
+const names: [string] = ["a", 1]
+let (a: int, b: bool) = (1, 2)
+def scale(n: float, by: float) -> float { return n * by }
+let p: Point = Point { x: 1, y: scale(2, 1.5) }
+def nothing() -> int { print(1) }
+let f: def(int) -> bool = def(n) { return n }
+let c: Colour = 1
+let ok: float = float(1) + int(2.5)
+"#.to_string();
+    assert_eq!(check(&source).err().unwrap(), vec![
+      "'x' is annotated as int, but its value is float at 3:14",
+      "Array element is int, but the elements before it are string at 4:31",
+      "'b' is annotated as bool, but its value is int at 5:25",
+      "Argument 1 to 'scale' should be float, found int at 7:33",
+      "Function is annotated to return int, but never returns a value at 8:1",
+      "'f' is annotated as def(int) -> bool, but its value is def(int) -> int at 9:27",
+      "Unknown type 'Colour' at 10:8",
+      "Cannot apply '+' to float and int at 11:17",
+    ]);
+
+    // A cast settles which kind of number it is, and annotations that match are fine
+    source = "let n: int = 7\nlet half: float = float(n) / 2.0\nlet back = int(half) + n\nprint(float(true))".to_string();
+    assert_eq!(check(&source).err().unwrap(), vec!["Cannot convert bool to float at 4:7"]);
+    source = "let n: int = 7\nlet half: float = float(n) / 2.0\nlet back: int = int(half) + n".to_string();
+    let (program, types) = check(&source).unwrap();
+    assert_eq!(variable_types(&program, &types), vec!["n: int", "half: float", "n: int"]);
+  }
+}
@@ -0,0 +1,256 @@
+use crate::parser::{Expr, ExprAST, Stmt, StmtAST};
+
+// Walks the AST in source order. Override the methods for the nodes a pass cares about, calling walk_expr or
+// walk_stmt from them to carry on into the children, or leaving that out to skip them. A function's
+// parameters are names being declared rather than expressions, so they aren't visited; a pass that needs
+// them reads them off the Function or Lambda node itself. An assignment's target is visited like any other expression
+pub trait Visitor {
+  fn visit_expr(&mut self, expr: &Expr) {
+    walk_expr(self, expr);
+  }
+
+  fn visit_stmt(&mut self, stmt: &Stmt) {
+    walk_stmt(self, stmt);
+  }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+  match &expr.node {
+    ExprAST::Integer(_) | ExprAST::Float(_) | ExprAST::Boolean(_) | ExprAST::Variable(_) | ExprAST::StringLiteral(_) => {},
+    ExprAST::Unary(_, operand) => visitor.visit_expr(operand),
+    ExprAST::Binary(_, lhs, rhs) | ExprAST::Logical(_, lhs, rhs) => {
+      visitor.visit_expr(lhs);
+      visitor.visit_expr(rhs);
+    },
+    ExprAST::Conditional(condition, then_value, else_value) => {
+      visitor.visit_expr(condition);
+      visitor.visit_expr(then_value);
+      visitor.visit_expr(else_value);
+    },
+    ExprAST::Call(_, args) => {
+      for arg in args {
+        visitor.visit_expr(arg);
+      }
+    },
+    ExprAST::ArrayLiteral(elements) | ExprAST::Tuple(elements) => {
+      for element in elements {
+        visitor.visit_expr(element);
+      }
+    },
+    ExprAST::Index(target, index) => {
+      visitor.visit_expr(target);
+      visitor.visit_expr(index);
+    },
+    ExprAST::Field(target, _) => visitor.visit_expr(target),
+    ExprAST::MethodCall(receiver, _, args) => {
+      visitor.visit_expr(receiver);
+      for arg in args {
+        visitor.visit_expr(arg);
+      }
+    },
+    ExprAST::Interpolation(parts) => {
+      for part in parts {
+        visitor.visit_expr(part);
+      }
+    },
+    ExprAST::Function(_, _, _, body, _) | ExprAST::Lambda(_, _, body, _) => {
+      for stmt in body {
+        visitor.visit_stmt(stmt);
+      }
+    },
+    ExprAST::StructLiteral(_, fields) => {
+      for (_, value) in fields {
+        visitor.visit_expr(value);
+      }
+    },
+  }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+  match &stmt.node {
+    StmtAST::Let(_, _, value) | StmtAST::Const(_, _, value) | StmtAST::LetTuple(_, value) => visitor.visit_expr(value),
+    StmtAST::Assign(target, value) => {
+      visitor.visit_expr(target);
+      visitor.visit_expr(value);
+    },
+    StmtAST::Return(value) => {
+      if let Some(value) = value {
+        visitor.visit_expr(value);
+      }
+    },
+    StmtAST::Break | StmtAST::Continue | StmtAST::Struct(..) | StmtAST::Import(_) => {},
+    StmtAST::If(condition, then_block, else_block) => {
+      visitor.visit_expr(condition);
+      visitor.visit_stmt(then_block);
+      if let Some(else_block) = else_block {
+        visitor.visit_stmt(else_block);
+      }
+    },
+    StmtAST::While(condition, body) => {
+      visitor.visit_expr(condition);
+      visitor.visit_stmt(body);
+    },
+    StmtAST::ExprStmt(expr) => visitor.visit_expr(expr),
+    StmtAST::Block(statements) => {
+      for stmt in statements {
+        visitor.visit_stmt(stmt);
+      }
+    },
+  }
+}
+
+// Visitor for passes that rewrite the tree in place. Children are walked in the same order as Visitor
+pub trait VisitorMut {
+  fn visit_expr_mut(&mut self, expr: &mut Expr) {
+    walk_expr_mut(self, expr);
+  }
+
+  fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+    walk_stmt_mut(self, stmt);
+  }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+  match &mut expr.node {
+    ExprAST::Integer(_) | ExprAST::Float(_) | ExprAST::Boolean(_) | ExprAST::Variable(_) | ExprAST::StringLiteral(_) => {},
+    ExprAST::Unary(_, operand) => visitor.visit_expr_mut(operand),
+    ExprAST::Binary(_, lhs, rhs) | ExprAST::Logical(_, lhs, rhs) => {
+      visitor.visit_expr_mut(lhs);
+      visitor.visit_expr_mut(rhs);
+    },
+    ExprAST::Conditional(condition, then_value, else_value) => {
+      visitor.visit_expr_mut(condition);
+      visitor.visit_expr_mut(then_value);
+      visitor.visit_expr_mut(else_value);
+    },
+    ExprAST::Call(_, args) => {
+      for arg in args {
+        visitor.visit_expr_mut(arg);
+      }
+    },
+    ExprAST::ArrayLiteral(elements) | ExprAST::Tuple(elements) => {
+      for element in elements {
+        visitor.visit_expr_mut(element);
+      }
+    },
+    ExprAST::Index(target, index) => {
+      visitor.visit_expr_mut(target);
+      visitor.visit_expr_mut(index);
+    },
+    ExprAST::Field(target, _) => visitor.visit_expr_mut(target),
+    ExprAST::MethodCall(receiver, _, args) => {
+      visitor.visit_expr_mut(receiver);
+      for arg in args {
+        visitor.visit_expr_mut(arg);
+      }
+    },
+    ExprAST::Interpolation(parts) => {
+      for part in parts {
+        visitor.visit_expr_mut(part);
+      }
+    },
+    ExprAST::Function(_, _, _, body, _) | ExprAST::Lambda(_, _, body, _) => {
+      for stmt in body {
+        visitor.visit_stmt_mut(stmt);
+      }
+    },
+    ExprAST::StructLiteral(_, fields) => {
+      for (_, value) in fields {
+        visitor.visit_expr_mut(value);
+      }
+    },
+  }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+  match &mut stmt.node {
+    StmtAST::Let(_, _, value) | StmtAST::Const(_, _, value) | StmtAST::LetTuple(_, value) => visitor.visit_expr_mut(value),
+    StmtAST::Assign(target, value) => {
+      visitor.visit_expr_mut(target);
+      visitor.visit_expr_mut(value);
+    },
+    StmtAST::Return(value) => {
+      if let Some(value) = value {
+        visitor.visit_expr_mut(value);
+      }
+    },
+    StmtAST::Break | StmtAST::Continue | StmtAST::Struct(..) | StmtAST::Import(_) => {},
+    StmtAST::If(condition, then_block, else_block) => {
+      visitor.visit_expr_mut(condition);
+      visitor.visit_stmt_mut(then_block);
+      if let Some(else_block) = else_block {
+        visitor.visit_stmt_mut(else_block);
+      }
+    },
+    StmtAST::While(condition, body) => {
+      visitor.visit_expr_mut(condition);
+      visitor.visit_stmt_mut(body);
+    },
+    StmtAST::ExprStmt(expr) => visitor.visit_expr_mut(expr),
+    StmtAST::Block(statements) => {
+      for stmt in statements {
+        visitor.visit_stmt_mut(stmt);
+      }
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::lex;
+  use crate::parser::{dump, Parser, UnaryOp};
+
+  fn parse(source: &str) -> Vec<Stmt> {
+    let tokens = lex(source).unwrap();
+    return Parser::new(&tokens).parse_statements().unwrap();
+  }
+
+  // Records every variable read, in the order they're visited
+  struct Reads {
+    names: Vec<String>,
+  }
+
+  impl Visitor for Reads {
+    fn visit_expr(&mut self, expr: &Expr) {
+      if let ExprAST::Variable(name) = &expr.node {
+        self.names.push(name.clone());
+      }
+      walk_expr(self, expr);
+    }
+  }
+
+  #[test]
+  fn visit_in_source_order() {
+    let source: String = "def f(x) {\n  let y = x + g(a, -b)\n  while y { if c { return \"${d}\" } else { e = y } }\n}\nf(z)".to_string();
+    let program = parse(&source);
+    let mut reads = Reads { names: Vec::new() };
+    for stmt in &program {
+      reads.visit_stmt(stmt);
+    }
+    assert_eq!(reads.names, vec!["x", "a", "b", "y", "c", "d", "e", "y", "z"]);
+  }
+
+  // Removes double negations, -(-x) becoming x
+  struct DoubleNegation;
+
+  impl VisitorMut for DoubleNegation {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+      walk_expr_mut(self, expr);
+      if let ExprAST::Unary(UnaryOp::Negate, operand) = &mut expr.node
+        && let ExprAST::Unary(UnaryOp::Negate, inner) = &mut operand.node {
+        expr.node = std::mem::replace(&mut inner.node, ExprAST::Boolean(false));
+      }
+    }
+  }
+
+  #[test]
+  fn rewrite_in_place() {
+    let source: String = "let x = - -1\nwhile - - - y { f(- -(a + - -b)) }".to_string();
+    let mut program = parse(&source);
+    for stmt in &mut program {
+      DoubleNegation.visit_stmt_mut(stmt);
+    }
+    assert_eq!(dump(&program), "(let x 1)\n(while (- y)\n  (block\n    (call f (+ a b))))\n");
+  }
+}
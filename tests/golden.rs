@@ -0,0 +1,55 @@
+// Parses every .sil file under tests/programs and compares its AST dump, or the errors it fails with, against
+// the .ast snapshot beside it. Run with UPDATE_SNAPSHOTS=1 to write the snapshots from the current parser
+#![allow(clippy::needless_return, clippy::bool_assert_comparison)]
+
+use std::{env, fs, path::Path};
+
+use rachit_cc::{captures::resolve_captures, lexer::lex_with_recovery, parser::Parser, source::read_source};
+
+// The canonical text for a program: its AST, or one `error:` line per error that stopped it parsing
+fn render(path: &str) -> String {
+  let source = match read_source(path) {
+    Ok(source) => source,
+    Err(msg) => return format!("error: {}\n", msg),
+  };
+  let (tokens, errors) = lex_with_recovery(&source);
+  if !errors.is_empty() {
+    return errors.iter().map(|msg| format!("error: {}\n", msg)).collect();
+  }
+  match Parser::new(&tokens).parse() {
+    Ok(mut program) => {
+      resolve_captures(&mut program);
+      return program.to_string();
+    },
+    Err(msg) => return format!("error: {}\n", msg),
+  }
+}
+
+#[test]
+fn golden_programs() {
+  let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("programs");
+  let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+
+  let mut programs: Vec<_> = fs::read_dir(&directory).unwrap()
+    .map(|entry| entry.unwrap().path())
+    .filter(|path| path.extension().is_some_and(|extension| extension == "sil"))
+    .collect();
+  programs.sort();
+  assert_eq!(programs.is_empty(), false);
+
+  let mut failures: Vec<String> = Vec::new();
+  for program in &programs {
+    let actual = render(program.to_str().unwrap());
+    let snapshot = program.with_extension("ast");
+    if update {
+      fs::write(&snapshot, &actual).unwrap();
+      continue;
+    }
+    match fs::read_to_string(&snapshot) {
+      Ok(expected) if expected == actual => {},
+      Ok(expected) => failures.push(format!("{}\n--- expected\n{}--- actual\n{}", snapshot.display(), expected, actual)),
+      Err(_) => failures.push(format!("{} is missing, run with UPDATE_SNAPSHOTS=1 to create it", snapshot.display())),
+    }
+  }
+  assert!(failures.is_empty(), "{} snapshot(s) differ:\n\n{}", failures.len(), failures.join("\n"));
+}